@@ -1,11 +1,14 @@
 
+use std::convert::TryFrom;
+use std::io::Read;
+
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 
-use io::{BinaryComponent, DecodeError, WrResult};
+use io::{BinaryComponent, Decoder, DecodeError, EncodeErrorKind, WrResult, encode_frame};
 
 impl BinaryComponent for u8 {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
         Ok(read.read_u8()?)
     }
 
@@ -17,7 +20,7 @@ impl BinaryComponent for u8 {
 
 impl BinaryComponent for u16 {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
         Ok(read.read_u16::<BigEndian>()?)
     }
 
@@ -29,7 +32,7 @@ impl BinaryComponent for u16 {
 
 impl BinaryComponent for u32 {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
         Ok(read.read_u32::<BigEndian>()?)
     }
 
@@ -39,26 +42,107 @@ impl BinaryComponent for u32 {
 
 }
 
-#[derive(Clone)]
+impl BinaryComponent for u64 {
+
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        Ok(read.read_u64::<BigEndian>()?)
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+        write.write_u64::<BigEndian>(*self).map_err(Into::into)
+    }
+
+}
+
+impl BinaryComponent for i64 {
+
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        Ok(read.read_i64::<BigEndian>()?)
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+        write.write_i64::<BigEndian>(*self).map_err(Into::into)
+    }
+
+}
+
+/// Raw bytes with a length prefix of type `L`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct Blob<L>(Vec<u8>, ::std::marker::PhantomData<L>);
 pub type SmallBlob = Blob<u8>;
 pub type MediumBlob = Blob<u16>;
 pub type BigBlob = Blob<u32>;
+pub type HugeBlob = Blob<u64>;
+
+impl<L> Blob<L> {
 
-impl<L: BinaryComponent + Into<usize> + From<usize>> BinaryComponent for Blob<L> {
+    pub fn new(data: Vec<u8>) -> Blob<L> {
+        Blob(data, ::std::marker::PhantomData)
+    }
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        let len = L::from_reader(read)?.into();
-        let mut body = vec![0; len];
-        read.read(&mut body)?;
-        Ok(Blob(body, ::std::marker::PhantomData))
+    pub fn data(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+}
+
+impl<L: BinaryComponent + Into<u64> + TryFrom<usize>> BinaryComponent for Blob<L> {
+
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+            let len = L::decode(read)?.into();
+            let body = read.read_bytes(len)?;
+            Ok(Blob(body, ::std::marker::PhantomData))
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        let len: L = self.0.len().into();
-        BinaryComponent::to_writer(&len, write)?; // this isn't the normal way to do it
-        write.write(self.0.as_slice())?;
-        Ok(())
+        encode_frame::<Self, _>(|| {
+            let len = match L::try_from(self.0.len()) {
+                Ok(l) => l,
+                Err(_) => return Err(EncodeErrorKind::LengthOverflow.into())
+            };
+            BinaryComponent::to_writer(&len, write)?; // this isn't the normal way to do it
+            write.write_all(self.0.as_slice())?;
+            Ok(())
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use testutil::*;
+
+    use super::*;
+
+    quickcheck! {
+
+        fn prop_ints_canonical(a: u8, b: u16, c: u32, d: u64, e: i64, idx: usize, byte: u8) -> bool {
+            canonical(&a) && canonical(&b) && canonical(&c) && canonical(&d) && canonical(&e)
+                && mutant_canonical(&c, idx, byte) && mutant_canonical(&e, idx, byte)
+                && strict_bounds(&a) && strict_bounds(&b) && strict_bounds(&c)
+                && strict_bounds(&d) && strict_bounds(&e)
+        }
+
+        fn prop_blobs_canonical(d: Vec<u8>, idx: usize, byte: u8) -> bool {
+            let small = SmallBlob::new(d.iter().cloned().take(0xff).collect());
+            let medium = MediumBlob::new(d.clone());
+            let big = BigBlob::new(d);
+            canonical(&small) && canonical(&medium) && canonical(&big)
+                && mutant_canonical(&small, idx, byte)
+                && mutant_canonical(&medium, idx, byte)
+                && mutant_canonical(&big, idx, byte)
+                && strict_bounds(&small) && strict_bounds(&medium) && strict_bounds(&big)
+        }
+
+    }
+
+    #[test]
+    fn ck_small_blob_overflow() {
+        let mut out = Vec::new();
+        assert!(SmallBlob::new(vec![0; 0x100]).to_writer(&mut out).is_err());
     }
 
 }
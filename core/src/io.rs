@@ -1,11 +1,17 @@
 extern crate byteorder;
 
+use std::cmp;
+use std::convert::TryFrom;
 use std::error;
 use std::fmt;
 use std::io;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
+use std::mem;
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::ReadBytesExt;
+
+// Re-exported so that derived impls can name it without depending on byteorder.
+pub use byteorder::WriteBytesExt;
 
 use sig;
 use sig::Signed;
@@ -14,12 +20,80 @@ use sig::Signed;
 pub type WrResult = Result<(), EncodeError>;
 
 /// Type to represent encoding errors.
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
-pub struct EncodeError;
+#[derive(Debug)]
+pub struct EncodeError {
+    kind: EncodeErrorKind,
+    path: Vec<String>
+}
+
+/// What actually went wrong while encoding.
+#[derive(Debug)]
+pub enum EncodeErrorKind {
+
+    /// The underlying writer failed.
+    Io(io::Error),
+
+    /// Some length was too large to fit in its length prefix.
+    LengthOverflow
+
+}
+
+impl EncodeError {
+
+    pub fn new(kind: EncodeErrorKind) -> EncodeError {
+        EncodeError {
+            kind,
+            path: Vec::new()
+        }
+    }
+
+    pub fn kind(&self) -> &EncodeErrorKind {
+        &self.kind
+    }
+
+    /// The chain of types being encoded when the error happened, outermost first.
+    pub fn path(&self) -> &[String] {
+        self.path.as_slice()
+    }
+
+    /// Marks the error as having happened inside of the named component.
+    pub fn within<S: Into<String>>(mut self, frame: S) -> EncodeError {
+        self.path.insert(0, frame.into());
+        self
+    }
+
+}
+
+impl From<EncodeErrorKind> for EncodeError {
+    fn from(k: EncodeErrorKind) -> Self {
+        EncodeError::new(k)
+    }
+}
 
 impl From<io::Error> for EncodeError {
-    fn from(_: io::Error) -> Self {
-        EncodeError
+    fn from(e: io::Error) -> Self {
+        EncodeError::new(EncodeErrorKind::Io(e))
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            EncodeErrorKind::Io(ref e) => write!(f, "io error ({})", e)?,
+            EncodeErrorKind::LengthOverflow => write!(f, "length too large for prefix")?
+        }
+        write_path(f, &self.path)
+    }
+}
+
+impl error::Error for EncodeError {
+    fn description(&self) -> &str { "an encoding error" }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            EncodeErrorKind::Io(ref e) => Some(e),
+            _ => None
+        }
     }
 }
 
@@ -31,14 +105,51 @@ impl From<io::Error> for EncodeError {
 /// easily described to a general-purpose library.
 pub trait BinaryComponent where Self: Clone {
 
+    /// Reads input through the decoder, potentially failing.  Implementations should decode
+    /// their fields with `decode` (not `from_reader`) so the decoder's limits carry through.
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError>;
+
+    /// The limits used when this is decoded on its own, as a top-level component.
+    fn decode_limits() -> DecodeLimits {
+        DecodeLimits::default()
+    }
+
+    /// Tag that signatures over this type commit to, like `"jiyunet/segment/v1"`, so that a
+    /// signature over one type can't be passed off as one over another.  Anything that gets
+    /// signed on its own should declare one.
+    fn signing_domain() -> &'static str {
+        ""
+    }
+
     /// Reads input from some kind of byte reader, potentially failing.
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError>;
+    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+        Self::from_reader_limited(read, Self::decode_limits())
+    }
+
+    /// Reads input from some kind of byte reader with explicit limits, potentially failing.
+    /// Errors report the number of bytes consumed before the failure.
+    fn from_reader_limited<R: ReadBytesExt>(read: &mut R, limits: DecodeLimits) -> Result<Self, DecodeError> {
+        let mut dec = Decoder::new(read, limits);
+        Self::decode(&mut dec).map_err(|e| e.at(dec.position()))
+    }
 
-    /// Decodes from a slice, potentially failing.
+    /// Decodes from a slice, potentially failing.  Ignores anything after the component.
     fn from_slice(s: &[u8]) -> Result<Self, DecodeError> {
         Self::from_reader(&mut Cursor::new(s))
     }
 
+    /// Decodes from a slice, failing if there's anything left over after the component.  Every
+    /// encoding is canonical, so if this succeeds then re-encoding gives back exactly `s`.  Use
+    /// this for anything that's addressed by its hash.
+    fn from_slice_exact(s: &[u8]) -> Result<Self, DecodeError> {
+        let mut c = Cursor::new(s);
+        let t = Self::from_reader(&mut c)?;
+        if c.position() != s.len() as u64 {
+            return Err(DecodeError::new(DecodeErrorKind::TrailingBytes).at(c.position()));
+        }
+        Ok(t)
+    }
+
     /// Writes the binary representation of itself to the byte writer, potentially returning the total number of bytes written.
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult;
 
@@ -63,18 +174,136 @@ pub trait BinaryComponent where Self: Clone {
 }
 
 /// An error in decoding a DagComponent.  Should propagate up the call stack.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DecodeError {
+    kind: DecodeErrorKind,
+    offset: Option<u64>,
+    path: Vec<String>
+}
+
+/// What actually went wrong while decoding.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
-pub struct DecodeError;
+pub enum DecodeErrorKind {
+
+    /// The input ended before the component was fully read.
+    Truncated,
+
+    /// A tag or scheme byte that doesn't match any known variant.
+    UnknownDiscriminator(u8),
+
+    /// A varint code (like a multihash algorithm) that doesn't match anything we know.
+    UnknownCode(u64),
+
+    /// A length that isn't the one the thing being decoded requires.
+    BadLength(u64),
+
+    /// A varint that used more bytes than it needed to.
+    NonCanonical,
+
+    /// Something that decoded fine but breaks the rules of the type it's in, like a list that's
+    /// supposed to be sorted that isn't.
+    InvalidValue,
+
+    /// A string that isn't valid UTF-8.
+    InvalidUtf8,
+
+    /// A length prefix that's too large to be represented.
+    LengthOverflow,
+
+    /// One of the decoder's limits would have been exceeded.
+    LimitExceeded(Limit),
+
+    /// There was still data left over after the component was read.
+    TrailingBytes,
+
+    /// The underlying reader failed for some other reason.
+    Io(io::ErrorKind)
+
+}
+
+impl DecodeError {
+
+    pub fn new(kind: DecodeErrorKind) -> DecodeError {
+        DecodeError {
+            kind,
+            offset: None,
+            path: Vec::new()
+        }
+    }
+
+    pub fn kind(&self) -> DecodeErrorKind {
+        self.kind
+    }
+
+    /// The number of bytes that had been consumed when decoding failed, if known.  This is
+    /// filled in when decoding through `from_reader` and friends.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// The chain of types being decoded when the error happened, outermost first.
+    pub fn path(&self) -> &[String] {
+        self.path.as_slice()
+    }
+
+    /// Marks the error as having happened inside of the named component.
+    pub fn within<S: Into<String>>(mut self, frame: S) -> DecodeError {
+        self.path.insert(0, frame.into());
+        self
+    }
+
+    /// Sets the offset of the error, if it hasn't been set already.
+    pub fn at(mut self, offset: u64) -> DecodeError {
+        if self.offset.is_none() {
+            self.offset = Some(offset);
+        }
+        self
+    }
+
+}
+
+impl From<DecodeErrorKind> for DecodeError {
+    fn from(k: DecodeErrorKind) -> Self {
+        DecodeError::new(k)
+    }
+}
 
 impl From<io::Error> for DecodeError {
-    fn from(_: io::Error) -> Self {
-        DecodeError
+    fn from(e: io::Error) -> Self {
+
+        // The decoder smuggles limit errors out through the `Read` impl.
+        if let Some(&LimitHit(l)) = e.get_ref().and_then(|i| i.downcast_ref::<LimitHit>()) {
+            return DecodeError::new(DecodeErrorKind::LimitExceeded(l));
+        }
+
+        DecodeError::new(match e.kind() {
+            io::ErrorKind::UnexpectedEof => DecodeErrorKind::Truncated,
+            k => DecodeErrorKind::Io(k)
+        })
+
     }
 }
 
 impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "error while reading DAG")
+        use self::DecodeErrorKind::*;
+        match self.kind {
+            Truncated => write!(f, "truncated input")?,
+            UnknownDiscriminator(d) => write!(f, "unknown discriminator 0x{:02x}", d)?,
+            UnknownCode(c) => write!(f, "unknown code 0x{:x}", c)?,
+            BadLength(l) => write!(f, "bad length {}", l)?,
+            NonCanonical => write!(f, "non-canonical varint")?,
+            InvalidValue => write!(f, "invalid value")?,
+            InvalidUtf8 => write!(f, "invalid UTF-8")?,
+            LengthOverflow => write!(f, "length prefix too large")?,
+            LimitExceeded(l) => write!(f, "{} limit exceeded", l)?,
+            TrailingBytes => write!(f, "trailing bytes")?,
+            Io(k) => write!(f, "io error ({:?})", k)?
+        }
+        if let Some(o) = self.offset {
+            write!(f, " at byte {}", o)?;
+        }
+        write_path(f, &self.path)
     }
 }
 
@@ -82,70 +311,375 @@ impl error::Error for DecodeError {
     fn description(&self) -> &str { "a decoding error" }
 }
 
-impl BinaryComponent for String {
+fn write_path(f: &mut fmt::Formatter, path: &[String]) -> fmt::Result {
+    if !path.is_empty() {
+        write!(f, " in {}", path.join(" > "))?;
+    }
+    Ok(())
+}
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        let len = read.read_u64::<BigEndian>()?;
-        let mut utf8 = vec![0; len as usize];
-        read.read(utf8.as_mut_slice())?;
-        match String::from_utf8(utf8) {
-            Ok(s) => Ok(s),
-            Err(_) => Err(DecodeError)
+/// Limits on how much work decoding a component is allowed to do, so that a peer can't make us
+/// allocate huge amounts of memory just by sending us a big length prefix.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DecodeLimits {
+
+    /// Total number of bytes that can be read.
+    pub max_bytes: u64,
+
+    /// Number of elements allowed in any single list.
+    pub max_len: u64,
+
+    /// How deeply components can be nested inside of each other.
+    pub max_depth: u32
+
+}
+
+impl DecodeLimits {
+
+    pub const fn new(max_bytes: u64, max_len: u64, max_depth: u32) -> DecodeLimits {
+        DecodeLimits {
+            max_bytes,
+            max_len,
+            max_depth
+        }
+    }
+
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits::new(1 << 24, 1 << 16, 32)
+    }
+}
+
+/// Which of the `DecodeLimits` was hit.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Limit {
+    Bytes,
+    Length,
+    Depth
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Limit::Bytes => "byte",
+            Limit::Length => "length",
+            Limit::Depth => "depth"
+        })
+    }
+}
+
+/// Carries a `Limit` through an `io::Error`, see `Decoder::read`.
+#[derive(Debug)]
+struct LimitHit(Limit);
+
+impl fmt::Display for LimitHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} limit exceeded", self.0)
+    }
+}
+
+impl error::Error for LimitHit {
+    fn description(&self) -> &str { "a decoding limit was exceeded" }
+}
+
+/// Wraps a reader while decoding, keeping track of how much has been read and how deep we are,
+/// and enforcing the `DecodeLimits`.
+pub struct Decoder<R> {
+    inner: R,
+    limits: DecodeLimits,
+    consumed: u64,
+    depth: u32
+}
+
+impl<R: Read> Decoder<R> {
+
+    pub fn new(inner: R, limits: DecodeLimits) -> Decoder<R> {
+        Decoder {
+            inner,
+            limits,
+            consumed: 0,
+            depth: 0
         }
     }
 
+    pub fn limits(&self) -> DecodeLimits {
+        self.limits
+    }
+
+    /// The number of bytes read so far.
+    pub fn position(&self) -> u64 {
+        self.consumed
+    }
+
+    /// The number of bytes that can still be read before hitting the byte limit.
+    pub fn remaining(&self) -> u64 {
+        self.limits.max_bytes - self.consumed
+    }
+
+    /// Decodes something nested inside of the current component, checking the depth limit and
+    /// tagging any error with the name of the type being decoded.
+    pub fn frame<T, F>(&mut self, f: F) -> Result<T, DecodeError> where F: FnOnce(&mut Self) -> Result<T, DecodeError> {
+
+        if self.depth >= self.limits.max_depth {
+            let e: DecodeError = DecodeErrorKind::LimitExceeded(Limit::Depth).into();
+            return Err(e.within(short_type_name::<T>()));
+        }
+
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res.map_err(|e| e.within(short_type_name::<T>()))
+
+    }
+
+    /// Checks a decoded list length prefix against the limits.
+    pub fn collection_len(&self, len: u64) -> Result<usize, DecodeError> {
+        if len > self.limits.max_len {
+            Err(DecodeErrorKind::LimitExceeded(Limit::Length).into())
+        } else if len > usize::MAX as u64 {
+            Err(DecodeErrorKind::LengthOverflow.into())
+        } else {
+            Ok(len as usize)
+        }
+    }
+
+    /// Reads exactly `len` raw bytes, checking that there's budget for them *before* allocating.
+    pub fn read_bytes(&mut self, len: u64) -> Result<Vec<u8>, DecodeError> {
+
+        if len > self.remaining() {
+            return Err(DecodeErrorKind::LimitExceeded(Limit::Bytes).into());
+        } else if len > usize::MAX as u64 {
+            return Err(DecodeErrorKind::LengthOverflow.into());
+        }
+
+        let mut buf = vec![0; len as usize];
+        self.read_exact(buf.as_mut_slice())?;
+        Ok(buf)
+
+    }
+
+    /// Reads an unsigned LEB128 varint, like multiformats use, rejecting overlong encodings.
+    pub fn read_uvarint(&mut self) -> Result<u64, DecodeError> {
+
+        let mut v = 0u64;
+        for i in 0..10 {
+
+            let b = self.read_u8()?;
+            if i == 9 && b > 1 {
+                return Err(DecodeErrorKind::LengthOverflow.into());
+            }
+
+            v |= ((b & 0x7f) as u64) << (7 * i);
+            if b & 0x80 == 0 {
+                // A zero final byte means the previous one didn't need its continuation bit.
+                if b == 0 && i > 0 {
+                    return Err(DecodeErrorKind::NonCanonical.into());
+                }
+                return Ok(v);
+            }
+
+        }
+
+        Err(DecodeErrorKind::LengthOverflow.into())
+
+    }
+
+}
+
+/// Writes an unsigned LEB128 varint, the inverse of `Decoder::read_uvarint`.
+pub fn write_uvarint<W: WriteBytesExt>(mut v: u64, write: &mut W) -> WrResult {
+    while v >= 0x80 {
+        write.write_u8((v as u8) | 0x80)?;
+        v >>= 7;
+    }
+    write.write_u8(v as u8)?;
+    Ok(())
+}
+
+impl<R: Read> Read for Decoder<R> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        // Refuse outright instead of doing a short read, otherwise this looks like truncation.
+        if buf.len() as u64 > self.remaining() {
+            return Err(io::Error::other(LimitHit(Limit::Bytes)));
+        }
+
+        let n = self.inner.read(buf)?;
+        self.consumed += n as u64;
+        Ok(n)
+
+    }
+
+}
+
+/// Something encoded as a length followed by that many things, like a `String` or a `Vec`.
+/// Normally the length is a `u64`, but a containing component can pick a narrower one by using
+/// `decode_prefixed` and `encode_prefixed` with a different `L`.
+pub trait LengthPrefixed: BinaryComponent {
+
+    /// The length that gets written in the prefix.
+    fn prefix_len(&self) -> u64;
+
+    /// Reads the contents, after the length prefix has already been read.
+    fn decode_body<R: Read>(read: &mut Decoder<R>, len: u64) -> Result<Self, DecodeError>;
+
+    /// Writes the contents, without the length prefix.
+    fn write_body<W: WriteBytesExt>(&self, write: &mut W) -> WrResult;
+
+}
+
+/// Decodes a `T` that has a length prefix of type `L`.
+pub fn decode_prefixed<L, T, R>(read: &mut Decoder<R>) -> Result<T, DecodeError>
+        where L: BinaryComponent + Into<u64>, T: LengthPrefixed, R: Read {
+    let len = L::decode(read).map_err(|e| e.within(short_type_name::<T>()))?;
+    T::decode_body(read, len.into())
+}
+
+/// Encodes a `T` with a length prefix of type `L`, failing if the length doesn't fit.
+pub fn encode_prefixed<L, T, W>(t: &T, write: &mut W) -> WrResult
+        where L: BinaryComponent + TryFrom<u64>, T: LengthPrefixed, W: WriteBytesExt {
+
+    let len = match L::try_from(t.prefix_len()) {
+        Ok(l) => l,
+        Err(_) => return Err(EncodeError::new(EncodeErrorKind::LengthOverflow).within(short_type_name::<T>()))
+    };
+
+    len.to_writer(write).map_err(|e| e.within(short_type_name::<T>()))?;
+    t.write_body(write)
+
+}
+
+/// Runs the encoding function, tagging any error with the name of the type `T` being encoded.
+pub fn encode_frame<T, F>(f: F) -> WrResult where F: FnOnce() -> WrResult {
+    f().map_err(|e| e.within(short_type_name::<T>()))
+}
+
+/// Returns the name of the type without any module paths, like `Vec<Signed<Segment>>`.
+pub fn short_type_name<T>() -> String {
+
+    let full = ::std::any::type_name::<T>();
+    let mut out = String::with_capacity(full.len());
+    let mut ident = String::new();
+
+    for c in full.chars() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            ident.push(c);
+        } else {
+            out.push_str(ident.rsplit("::").next().unwrap_or(""));
+            ident.clear();
+            out.push(c);
+        }
+    }
+
+    out.push_str(ident.rsplit("::").next().unwrap_or(""));
+    out
+
+}
+
+impl BinaryComponent for String {
+
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        decode_prefixed::<u64, Self, _>(read)
+    }
+
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        write.write_u64::<BigEndian>(self.len() as u64)?;
-        write.write(self.as_bytes())?;
-        Ok(())
+        encode_prefixed::<u64, Self, _>(self, write)
+    }
+
+}
+
+impl LengthPrefixed for String {
+
+    fn prefix_len(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn decode_body<R: Read>(read: &mut Decoder<R>, len: u64) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+            let utf8 = read.read_bytes(len)?;
+            match String::from_utf8(utf8) {
+                Ok(s) => Ok(s),
+                Err(_) => Err(DecodeErrorKind::InvalidUtf8.into())
+            }
+        })
+    }
+
+    fn write_body<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+        encode_frame::<Self, _>(|| Ok(write.write_all(self.as_bytes())?))
     }
 
 }
 
 impl<T> BinaryComponent for Option<T> where T: BinaryComponent {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        match read.read_u8()? {
-            0 => Ok(None),
-            1 => Ok(Some(T::from_reader(read)?)),
-            _ => Err(DecodeError)
-        }
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+            match read.read_u8()? {
+                0 => Ok(None),
+                1 => Ok(Some(T::decode(read)?)),
+                t => Err(DecodeErrorKind::UnknownDiscriminator(t).into())
+            }
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+        encode_frame::<Self, _>(|| {
 
-        write.write_u8(if self.is_some() { 1 } else { 0 })?;
-        match self {
-            &Some(ref t) => t.to_writer(write)?,
-            &None => {}
-        }
+            write.write_u8(if self.is_some() { 1 } else { 0 })?;
+            if let Some(ref t) = *self {
+                t.to_writer(write)?;
+            }
 
-        Ok(())
+            Ok(())
 
+        })
     }
 
 }
 
 impl<T> BinaryComponent for Vec<T> where T: BinaryComponent {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        decode_prefixed::<u64, Self, _>(read)
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+        encode_prefixed::<u64, Self, _>(self, write)
+    }
+
+}
 
-        let len = read.read_u64::<BigEndian>()? as usize;
+impl<T> LengthPrefixed for Vec<T> where T: BinaryComponent {
 
-        let mut v = Vec::with_capacity(len);
-        for _ in 0..len {
-            v.push(T::from_reader(read)?);
+    fn prefix_len(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn decode_body<R: Read>(read: &mut Decoder<R>, len: u64) -> Result<Self, DecodeError> {
+
+        let len = read.collection_len(len).map_err(|e| e.within(short_type_name::<Self>()))?;
+
+        let mut v = Vec::with_capacity(initial_capacity::<T>(len, read.remaining()));
+
+        // Tag each element's errors with its index, so we know where in the list it broke.
+        for i in 0..len {
+            match T::decode(read) {
+                Ok(t) => v.push(t),
+                Err(e) => return Err(e.within(format!("{}[{}]", short_type_name::<Self>(), i)))
+            }
         }
 
         Ok(v)
 
     }
 
-    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+    fn write_body<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
 
-        write.write_u64::<BigEndian>(self.len() as u64)?;
-        for e in self {
-            e.to_writer(write)?;
+        for (i, e) in self.iter().enumerate() {
+            e.to_writer(write).map_err(|e| e.within(format!("{}[{}]", short_type_name::<Self>(), i)))?;
         }
 
         Ok(())
@@ -154,17 +688,133 @@ impl<T> BinaryComponent for Vec<T> where T: BinaryComponent {
 
 }
 
+/// How many elements to reserve room for before decoding them.  Every element takes at least a
+/// byte to encode, so this never reserves more memory than there are bytes left to read.
+fn initial_capacity<T>(len: usize, remaining: u64) -> usize {
+    let per = cmp::max(mem::size_of::<T>(), 1) as u64;
+    cmp::min(len as u64, remaining / per) as usize
+}
+
 impl BinaryComponent for [u8; 64] {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
         let mut v = [0; 64];
-        read.read(&mut v)?;
+        read.read_exact(&mut v)?;
         Ok(v)
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        write.write(self)?;
+        write.write_all(self)?;
         Ok(())
     }
 
 }
+
+#[cfg(test)]
+mod test {
+
+    use testutil::*;
+
+    use super::*;
+
+    quickcheck! {
+
+        fn prop_string_canonical(s: String, idx: usize, byte: u8) -> bool {
+            canonical(&s) && mutant_canonical(&s, idx, byte) && strict_bounds(&s)
+        }
+
+        fn prop_option_canonical(o: Option<String>, idx: usize, byte: u8) -> bool {
+            canonical(&o) && mutant_canonical(&o, idx, byte) && strict_bounds(&o)
+        }
+
+        fn prop_vec_canonical(v: Vec<Option<u16>>, idx: usize, byte: u8) -> bool {
+            canonical(&v) && mutant_canonical(&v, idx, byte) && strict_bounds(&v)
+        }
+
+        fn prop_array_canonical(d: Vec<u8>, idx: usize, byte: u8) -> bool {
+            let a = to_array_64(d.as_slice());
+            canonical(&a) && mutant_canonical(&a, idx, byte) && strict_bounds(&a)
+        }
+
+        fn prop_uvarint_roundtrip(v: u64) -> bool {
+            let mut b = Vec::new();
+            write_uvarint(v, &mut b).unwrap();
+            let mut dec = Decoder::new(Cursor::new(b.clone()), DecodeLimits::default());
+            dec.read_uvarint().ok() == Some(v) && dec.position() == b.len() as u64
+        }
+
+    }
+
+    #[test]
+    fn ck_uvarint_strict() {
+
+        let read = |b: &[u8]| Decoder::new(Cursor::new(b.to_vec()), DecodeLimits::default()).read_uvarint();
+
+        assert_eq!(read(&[0xa0, 0xe4, 0x02]).unwrap(), 0xb220);
+        assert_eq!(read(&[0x92, 0x00]).unwrap_err().kind(), DecodeErrorKind::NonCanonical);
+        assert_eq!(read(&[0x80; 10]).unwrap_err().kind(), DecodeErrorKind::LengthOverflow);
+        assert_eq!(read(&[0x80]).unwrap_err().kind(), DecodeErrorKind::Truncated);
+
+    }
+
+    #[test]
+    fn ck_trailing_bytes() {
+        let err = String::from_slice_exact(&[0, 0, 0, 0, 0, 0, 0, 1, 65, 66]).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::TrailingBytes);
+        assert_eq!(err.offset(), Some(9));
+        assert_eq!(String::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 65, 66]).unwrap(), "A");
+    }
+
+    #[test]
+    fn ck_array_truncated() {
+        let err = <[u8; 64]>::from_slice(&[7; 63]).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::Truncated);
+    }
+
+    #[test]
+    fn ck_short_type_name() {
+        assert_eq!(short_type_name::<Vec<Option<String>>>(), "Vec<Option<String>>");
+        assert_eq!(short_type_name::<sig::Signed<sig::Hash>>(), "Signed<Hash>");
+    }
+
+    #[test]
+    fn ck_string_invalid_utf8() {
+        let err = String::from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, 0xc3, 0x28]).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::InvalidUtf8);
+        assert_eq!(err.path(), &["String"]);
+        assert_eq!(format!("{}", err), "invalid UTF-8 at byte 10 in String");
+    }
+
+    #[test]
+    fn ck_string_length_bomb() {
+        let err = String::from_slice(&[0xff; 9]).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::LimitExceeded(Limit::Bytes));
+        assert_eq!(err.offset(), Some(8));
+    }
+
+    #[test]
+    fn ck_vec_reservation() {
+        // Big elements get less room reserved than the bytes left, not one each.
+        assert_eq!(initial_capacity::<[u8; 64]>(1 << 20, 1000), 15);
+        assert_eq!(initial_capacity::<u8>(1 << 20, 1000), 1000);
+        assert_eq!(initial_capacity::<u8>(10, 1000), 10);
+        assert_eq!(initial_capacity::<()>(10, 1000), 10);
+    }
+
+    #[test]
+    fn ck_depth_limit() {
+        let blob = Some(Some(Some(5u8))).to_blob();
+        let lim = DecodeLimits::new(64, 64, 2);
+        let err = Option::<Option<Option<u8>>>::from_reader_limited(&mut Cursor::new(blob), lim).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::LimitExceeded(Limit::Depth));
+        assert_eq!(err.path(), &["Option<Option<Option<u8>>>", "Option<Option<u8>>", "Option<u8>"]);
+    }
+
+    #[test]
+    fn ck_option_truncated() {
+        let err = Option::<String>::from_slice(&[]).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::Truncated);
+        assert_eq!(err.offset(), Some(0));
+    }
+
+}
@@ -0,0 +1,409 @@
+//! Passphrase-encrypted keypair files.
+//!
+//! The layout is a fixed header followed by the sealed keypair:
+//!
+//! ```text
+//! magic     "JIYUKEY\0"
+//! version   u8        (currently 1)
+//! kdf       u8        (0x00 = scrypt)
+//! log_n     u8
+//! r         u32 BE
+//! p         u32 BE
+//! salt      [u8; 16]
+//! cipher    u8        (0x00 = ChaCha20-Poly1305)
+//! nonce     [u8; 8]
+//! sealed    ...       (the keypair's normal encoding, encrypted)
+//! tag       [u8; 16]
+//! ```
+//!
+//! The whole header is fed in as associated data, so changing the KDF parameters or anything else
+//! in it makes the tag check fail the same way a wrong passphrase does.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::ptr;
+
+use byteorder::{BigEndian, ByteOrder};
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::scrypt;
+use rand::Rng;
+use rand::os::OsRng;
+
+use io::{BinaryComponent, DecodeError};
+use sig::Keypair;
+
+/// Every keystore file starts with this.
+pub const MAGIC: &[u8; 8] = b"JIYUKEY\0";
+
+/// The version of the format we write.
+pub const VERSION: u8 = 1;
+
+const KDF_SCRYPT: u8 = 0x00;
+const CIPHER_CHACHA20_POLY1305: u8 = 0x00;
+
+const SALT_WIDTH: usize = 16;
+const NONCE_WIDTH: usize = 8;
+const KEY_WIDTH: usize = 32;
+const TAG_WIDTH: usize = 16;
+const HEADER_WIDTH: usize = 8 + 1 + 1 + 1 + 4 + 4 + SALT_WIDTH + 1 + NONCE_WIDTH;
+
+/// Keystore files are tiny, so anything bigger than this is garbage.
+const MAX_FILE_WIDTH: u64 = 1024;
+
+/// Most memory scrypt parameters from a file can make us use, so that a doctored one can't make us
+/// allocate gigabytes before we even get to check the passphrase.
+const MAX_MEMORY: u64 = 256 << 20;
+
+/// Most passes we'll do, which only costs time but there's no reason for more.
+const MAX_P: u32 = 4;
+
+/// scrypt cost parameters.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32
+}
+
+impl KdfParams {
+
+    /// What new keystores use, about 32 MiB and a fraction of a second.
+    pub const DEFAULT: KdfParams = KdfParams { log_n: 15, r: 8, p: 1 };
+
+    /// Checks that the parameters are ones scrypt accepts and are within our limits.
+    pub fn is_sane(&self) -> bool {
+        self.log_n > 0 && self.log_n < 64
+            && self.r > 0
+            && self.p > 0 && self.p <= MAX_P
+            && (self.log_n as u64) < self.r as u64 * 16
+            && self.memory().is_some_and(|m| m <= MAX_MEMORY)
+    }
+
+    /// About how many bytes scrypt needs with these parameters, `128 * r * (2^log_n + p)`.
+    pub fn memory(&self) -> Option<u64> {
+        1u64.checked_shl(self.log_n as u32)
+            .and_then(|n| n.checked_add(self.p as u64))
+            .and_then(|n| n.checked_mul(128 * self.r as u64))
+    }
+
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams::DEFAULT
+    }
+}
+
+/// Why a keystore couldn't be written or opened.
+#[derive(Debug)]
+pub enum KeystoreError {
+
+    /// The underlying reader or writer failed.
+    Io(io::Error),
+
+    /// Doesn't start with the magic, so it isn't a keystore at all.
+    BadMagic,
+
+    /// Written by a version of the format we don't know about.
+    UnsupportedVersion(u8),
+
+    /// Uses a KDF or cipher we don't know about.
+    UnsupportedAlgorithm(u8),
+
+    /// The KDF parameters are out of range.
+    BadParams(KdfParams),
+
+    /// Too short to hold a header and a tag, or too long to be a keystore.
+    BadLength(usize),
+
+    /// The tag didn't check out, so either the passphrase is wrong or the file has been tampered
+    /// with.  There's no way to tell which.
+    BadPassphrase,
+
+    /// It decrypted fine but what was inside wasn't a keypair.
+    Decode(DecodeError)
+
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeystoreError::Io(ref e) => write!(f, "keystore io error: {}", e),
+            KeystoreError::BadMagic => write!(f, "not a keystore file"),
+            KeystoreError::UnsupportedVersion(v) => write!(f, "unsupported keystore version {}", v),
+            KeystoreError::UnsupportedAlgorithm(a) => write!(f, "unsupported keystore algorithm 0x{:02x}", a),
+            KeystoreError::BadParams(p) => write!(f, "bad kdf parameters (log_n {}, r {}, p {})", p.log_n, p.r, p.p),
+            KeystoreError::BadLength(n) => write!(f, "keystore is the wrong length ({} bytes)", n),
+            KeystoreError::BadPassphrase => write!(f, "wrong passphrase or corrupted keystore"),
+            KeystoreError::Decode(ref e) => write!(f, "keystore contents invalid: {}", e)
+        }
+    }
+}
+
+impl error::Error for KeystoreError {}
+
+impl From<io::Error> for KeystoreError {
+    fn from(e: io::Error) -> Self {
+        KeystoreError::Io(e)
+    }
+}
+
+/// Checks if the data looks like a keystore, as opposed to a bare keypair from before we had them.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Overwrites secrets we're done with.  Volatile so it doesn't get optimized away.
+fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+}
+
+fn derive_key(passphrase: &[u8], salt: &[u8], params: KdfParams) -> [u8; KEY_WIDTH] {
+    let sp = scrypt::ScryptParams::new(params.log_n, params.r, params.p);
+    let mut key = [0; KEY_WIDTH];
+    scrypt::scrypt(passphrase, salt, &sp, &mut key);
+    key
+}
+
+fn write_header(params: KdfParams, salt: &[u8; SALT_WIDTH], nonce: &[u8; NONCE_WIDTH]) -> Vec<u8> {
+    let mut h = Vec::with_capacity(HEADER_WIDTH);
+    h.extend_from_slice(MAGIC);
+    h.push(VERSION);
+    h.push(KDF_SCRYPT);
+    h.push(params.log_n);
+    let mut buf = [0; 4];
+    BigEndian::write_u32(&mut buf, params.r);
+    h.extend_from_slice(&buf);
+    BigEndian::write_u32(&mut buf, params.p);
+    h.extend_from_slice(&buf);
+    h.extend_from_slice(salt);
+    h.push(CIPHER_CHACHA20_POLY1305);
+    h.extend_from_slice(nonce);
+    h
+}
+
+impl Keypair {
+
+    /// Writes the keypair encrypted under the passphrase, with the default KDF parameters.
+    pub fn to_encrypted_writer<W: Write>(&self, write: &mut W, passphrase: &[u8]) -> Result<(), KeystoreError> {
+        self.to_encrypted_writer_with(write, passphrase, KdfParams::DEFAULT)
+    }
+
+    /// Writes the keypair encrypted under the passphrase, with specific KDF parameters.
+    pub fn to_encrypted_writer_with<W: Write>(&self, write: &mut W, passphrase: &[u8], params: KdfParams) -> Result<(), KeystoreError> {
+
+        if !params.is_sane() {
+            return Err(KeystoreError::BadParams(params));
+        }
+
+        let mut rng = OsRng::new()?;
+        let mut salt = [0; SALT_WIDTH];
+        let mut nonce = [0; NONCE_WIDTH];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let header = write_header(params, &salt, &nonce);
+        let mut key = derive_key(passphrase, &salt, params);
+        let mut plain = self.to_blob();
+
+        let mut sealed = vec![0; plain.len()];
+        let mut tag = [0; TAG_WIDTH];
+        ChaCha20Poly1305::new(&key, &nonce, header.as_slice()).encrypt(plain.as_slice(), sealed.as_mut_slice(), &mut tag);
+        wipe(&mut key);
+        wipe(plain.as_mut_slice());
+
+        write.write_all(header.as_slice())?;
+        write.write_all(sealed.as_slice())?;
+        write.write_all(&tag)?;
+        Ok(())
+
+    }
+
+    /// Reads and decrypts a keypair written by `to_encrypted_writer`.
+    pub fn from_encrypted_reader<R: Read>(read: &mut R, passphrase: &[u8]) -> Result<Keypair, KeystoreError> {
+
+        let mut data = Vec::new();
+        read.take(MAX_FILE_WIDTH + 1).read_to_end(&mut data)?;
+        let n = data.len();
+
+        if !is_encrypted(data.as_slice()) {
+            return Err(KeystoreError::BadMagic);
+        }
+
+        if n < HEADER_WIDTH + TAG_WIDTH || n as u64 > MAX_FILE_WIDTH {
+            return Err(KeystoreError::BadLength(n));
+        }
+
+        let (header, rest) = data.split_at(HEADER_WIDTH);
+        if header[8] != VERSION {
+            return Err(KeystoreError::UnsupportedVersion(header[8]));
+        }
+
+        if header[9] != KDF_SCRYPT {
+            return Err(KeystoreError::UnsupportedAlgorithm(header[9]));
+        }
+
+        let params = KdfParams {
+            log_n: header[10],
+            r: BigEndian::read_u32(&header[11..15]),
+            p: BigEndian::read_u32(&header[15..19])
+        };
+
+        if !params.is_sane() {
+            return Err(KeystoreError::BadParams(params));
+        }
+
+        let salt = &header[19..19 + SALT_WIDTH];
+        let cipher = header[19 + SALT_WIDTH];
+        if cipher != CIPHER_CHACHA20_POLY1305 {
+            return Err(KeystoreError::UnsupportedAlgorithm(cipher));
+        }
+
+        let nonce = &header[20 + SALT_WIDTH..];
+        let (sealed, tag) = rest.split_at(rest.len() - TAG_WIDTH);
+
+        let mut key = derive_key(passphrase, salt, params);
+        let mut plain = vec![0; sealed.len()];
+        let ok = ChaCha20Poly1305::new(&key, nonce, header).decrypt(sealed, plain.as_mut_slice(), tag);
+        wipe(&mut key);
+
+        if !ok {
+            return Err(KeystoreError::BadPassphrase);
+        }
+
+        let res = Keypair::from_slice_exact(plain.as_slice()).map_err(KeystoreError::Decode);
+        wipe(plain.as_mut_slice());
+        res
+
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use sig::Scheme;
+
+    // Cheap enough that the tests don't take forever.
+    const TEST_PARAMS: KdfParams = KdfParams { log_n: 4, r: 8, p: 1 };
+
+    fn seal(kp: &Keypair, pass: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        kp.to_encrypted_writer_with(&mut out, pass, TEST_PARAMS).unwrap();
+        out
+    }
+
+    #[test]
+    fn ck_keystore_roundtrip() {
+        for s in [Scheme::Ed25519, Scheme::Secp256k1] {
+            let kp = s.generate(&[7; 32]);
+            let data = seal(&kp, b"hunter2");
+            assert!(is_encrypted(data.as_slice()));
+            assert_eq!(data.len(), HEADER_WIDTH + kp.to_blob().len() + TAG_WIDTH);
+            assert_eq!(Keypair::from_encrypted_reader(&mut data.as_slice(), b"hunter2").unwrap(), kp);
+        }
+    }
+
+    #[test]
+    fn ck_keystore_bad_keypair() {
+
+        // Sealing doesn't check anything, but opening it again does.
+        let kp = match Scheme::Secp256k1.generate(&[7; 32]) {
+            Keypair::Secp256k1(_, p) => Keypair::Secp256k1([0; 32], p),
+            _ => unreachable!()
+        };
+        let data = seal(&kp, b"pass");
+        match Keypair::from_encrypted_reader(&mut data.as_slice(), b"pass") {
+            Err(KeystoreError::Decode(_)) => {},
+            r => panic!("expected Decode, got {:?}", r)
+        }
+
+    }
+
+    #[test]
+    fn ck_keystore_salted() {
+        let kp = Scheme::Ed25519.generate(&[7; 32]);
+        assert!(seal(&kp, b"pass") != seal(&kp, b"pass"));
+    }
+
+    #[test]
+    fn ck_keystore_wrong_passphrase() {
+        let kp = Scheme::Ed25519.generate(&[7; 32]);
+        let data = seal(&kp, b"right");
+        match Keypair::from_encrypted_reader(&mut data.as_slice(), b"wrong") {
+            Err(KeystoreError::BadPassphrase) => {},
+            r => panic!("expected BadPassphrase, got {:?}", r)
+        }
+    }
+
+    #[test]
+    fn ck_keystore_tampered() {
+
+        let kp = Scheme::Ed25519.generate(&[7; 32]);
+        let data = seal(&kp, b"pass");
+
+        // Flipping a bit anywhere in the salt, nonce, ciphertext, or tag gets caught by the tag.
+        for i in (19..data.len()).filter(|i| *i != 19 + SALT_WIDTH) {
+            let mut d = data.clone();
+            d[i] ^= 0x01;
+            match Keypair::from_encrypted_reader(&mut d.as_slice(), b"pass") {
+                Err(KeystoreError::BadPassphrase) => {},
+                r => panic!("byte {}: expected BadPassphrase, got {:?}", i, r)
+            }
+        }
+
+        // Weakening the KDF parameters also does, since the header is authenticated.
+        let mut d = data.clone();
+        d[10] -= 1;
+        match Keypair::from_encrypted_reader(&mut d.as_slice(), b"pass") {
+            Err(KeystoreError::BadPassphrase) => {},
+            r => panic!("expected BadPassphrase, got {:?}", r)
+        }
+
+    }
+
+    #[test]
+    fn ck_keystore_bad_header() {
+
+        let kp = Scheme::Ed25519.generate(&[7; 32]);
+        let data = seal(&kp, b"pass");
+
+        let open = |d: &[u8]| Keypair::from_encrypted_reader(&mut &d[..], b"pass").unwrap_err();
+
+        assert!(matches!(open(&kp.to_blob()), KeystoreError::BadMagic));
+        assert!(matches!(open(&data[..HEADER_WIDTH]), KeystoreError::BadLength(_)));
+
+        let mut d = data.clone();
+        d[8] = 2;
+        assert!(matches!(open(&d), KeystoreError::UnsupportedVersion(2)));
+
+        let mut d = data.clone();
+        d[9] = 0x01;
+        assert!(matches!(open(&d), KeystoreError::UnsupportedAlgorithm(0x01)));
+
+        let mut d = data.clone();
+        d[10] = 60;
+        assert!(matches!(open(&d), KeystoreError::BadParams(_)));
+
+        // It's the total memory that's limited, not each parameter.
+        assert!(KdfParams { log_n: 19, r: 2, p: 4 }.is_sane());
+        assert!(KdfParams { log_n: 10, r: 1024, p: 1 }.is_sane());
+        assert!(!KdfParams { log_n: 20, r: 16, p: 1 }.is_sane());
+        assert!(!KdfParams { log_n: 10, r: 4096, p: 1 }.is_sane());
+        assert!(!KdfParams { log_n: 63, r: 8, p: 1 }.is_sane());
+        assert!(!KdfParams { log_n: 4, r: u32::MAX, p: 1 }.is_sane());
+        assert_eq!(KdfParams::DEFAULT.memory(), Some(128 * 8 * ((1 << 15) + 1)));
+
+        let mut out = Vec::new();
+        let bad = KdfParams { log_n: 0, r: 8, p: 1 };
+        assert!(matches!(kp.to_encrypted_writer_with(&mut out, b"pass", bad), Err(KeystoreError::BadParams(_))));
+
+    }
+
+}
@@ -1,30 +1,64 @@
+extern crate bip39;
+extern crate blake3;
 extern crate byteorder;
 extern crate crypto;
+extern crate curve25519_dalek;
+extern crate ed25519_dalek;
+extern crate k256;
+extern crate rand;
 
 #[allow(unused_imports)]
 #[macro_use] extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
 
-use byteorder::{ReadBytesExt, WriteBytesExt};
+#[cfg(test)]
+#[macro_use] extern crate quickcheck;
+
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use byteorder::WriteBytesExt;
 
 pub mod io;
 pub mod sig;
 pub mod blobs;
-
-use io::{BinaryComponent, DecodeError, WrResult};
-use sig::Hash;
-
-/// Used to directly address something in the DAG.  Just a SHA-256 hash of whatever it is that
-/// it's addressing.  Yay for content-addressed objects.
+pub mod text;
+pub mod keystore;
+pub mod mnemonic;
+
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+
+use io::{BinaryComponent, Decoder, DecodeError, DecodeErrorKind, WrResult, encode_frame, write_uvarint};
+use sig::{Hash, HashAlgo};
+
+/// Used to directly address something in the DAG.  Just a hash of whatever it is that it's
+/// addressing, along with which algorithm made it.  Yay for content-addressed objects.
+///
+/// Encoded as a multihash, so the algorithm's code and the digest length as varints followed by
+/// the digest.  That way we can move to another hash function later without breaking every
+/// reference that's already been stored.
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-pub struct Address(sig::Hash);
+pub struct Address {
+    algo: HashAlgo,
+    digest: Hash
+}
 
 impl Address {
 
-    /// Creates a new address, assuming the specified hash data.
+    /// Creates a new address, assuming the specified SHA-256 hash data.
     pub fn new(hash: Hash) -> Address {
-        Address(hash)
+        Address::with_algo(HashAlgo::Sha256, hash)
+    }
+
+    /// Creates a new address, assuming the hash was made by the given algorithm.
+    pub fn with_algo(algo: HashAlgo, hash: Hash) -> Address {
+        Address {
+            algo,
+            digest: hash
+        }
     }
 
     pub fn from_raw(hex: [u8; sig::SHA256_WIDTH]) -> Address {
@@ -33,28 +67,180 @@ impl Address {
 
     /// Returns the address of the given blob, assuming that it's a node in a dag.
     pub fn of_slice(blob: &[u8]) -> Address {
-        Address(Hash::of_slice(blob))
+        Address::of_slice_with(HashAlgo::Sha256, blob)
+    }
+
+    /// Returns the address of the given blob, hashed with the given algorithm.
+    pub fn of_slice_with(algo: HashAlgo, blob: &[u8]) -> Address {
+        Address::with_algo(algo, algo.digest(blob))
     }
 
     pub fn of_bincomp<T: BinaryComponent>(t: &T) -> Address {
         Address::new(t.get_hash())
     }
 
-    pub fn to_string(&self) -> String {
-        unimplemented!();
+    pub fn of_bincomp_with<T: BinaryComponent>(algo: HashAlgo, t: &T) -> Address {
+        Address::of_slice_with(algo, t.to_blob().as_slice())
+    }
+
+    pub fn algo(&self) -> HashAlgo {
+        self.algo
+    }
+
+    pub fn digest(&self) -> Hash {
+        self.digest
+    }
+
+    /// Checks that this is actually the address of the blob, using our own algorithm.
+    pub fn verify(&self, blob: &[u8]) -> bool {
+        Address::of_slice_with(self.algo, blob) == *self
+    }
+
+    /// Checks that this is actually the address of the component, using our own algorithm.
+    pub fn verify_bincomp<T: BinaryComponent>(&self, t: &T) -> bool {
+        self.verify(t.to_blob().as_slice())
+    }
+
+    /// Returns the checksummed base58 form, like `addr_...`.
+    pub fn to_short_string(&self) -> String {
+        text::to_short(text::ADDRESS_PREFIX, self.to_blob().as_slice())
     }
 
 }
 
+/// Writes the multihash as lowercase hex.
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        text::write_hex(f, self.to_blob().as_slice())
+    }
+}
+
+/// Accepts either the hex or the `addr_` form.  Bare 64 digit hex is taken to be a SHA-256
+/// digest, like what `Display` wrote before addresses were multihashes.
+impl FromStr for Address {
+    type Err = text::ParseError;
+    fn from_str(s: &str) -> Result<Address, text::ParseError> {
+        let buf = text::parse(text::ADDRESS_PREFIX, s)?;
+        if buf.len() == sig::SHA256_WIDTH {
+            return text::parse_hash(text::ADDRESS_PREFIX, s).map(Address::new);
+        }
+        Address::from_slice_exact(buf.as_slice()).map_err(|_| text::ParseError::BadPayload)
+    }
+}
+
 impl BinaryComponent for Address {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        Ok(Address::new(Hash::from_reader(read)?))
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+
+            let code = read.read_uvarint()?;
+            let algo = match HashAlgo::from_code(code) {
+                Some(a) => a,
+                None => return Err(DecodeErrorKind::UnknownCode(code).into())
+            };
+
+            let len = read.read_uvarint()?;
+            if len != sig::SHA256_WIDTH as u64 {
+                return Err(DecodeErrorKind::BadLength(len).into());
+            }
+
+            Ok(Address::with_algo(algo, Hash::decode(read)?))
+
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        let &Address(h) = self;
-        h.to_writer(write)
+        encode_frame::<Self, _>(|| {
+            write_uvarint(self.algo.code(), write)?;
+            write_uvarint(sig::SHA256_WIDTH as u64, write)?;
+            self.digest.to_writer(write)
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use testutil::*;
+
+    use super::*;
+
+    fn gen_algo(n: u8) -> HashAlgo {
+        match n % 3 {
+            0 => HashAlgo::Sha256,
+            1 => HashAlgo::Blake2b256,
+            _ => HashAlgo::Blake3
+        }
+    }
+
+    #[test]
+    fn ck_address_multihash() {
+
+        let a = Address::of_slice_with(HashAlgo::Blake2b256, b"abc");
+        let b = a.to_blob();
+        assert_eq!(&b[..4], &[0xa0, 0xe4, 0x02, 0x20]);
+        assert_eq!(b.len(), 4 + sig::SHA256_WIDTH);
+        assert_eq!(a.digest().to_string(), "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319");
+
+        let c = Address::of_slice_with(HashAlgo::Blake3, b"abc");
+        assert_eq!(c.to_string(), "1e206437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+
+        // Same data, different algorithm, different address.
+        assert!(a != Address::of_slice_with(HashAlgo::Blake3, b"abc"));
+        assert_eq!(Address::of_slice(b"abc").to_blob()[..2], [0x12, 0x20]);
+
+    }
+
+    #[test]
+    fn ck_address_verify() {
+        for n in 0..3 {
+            let a = Address::of_slice_with(gen_algo(n), b"hello");
+            assert!(a.verify(b"hello"));
+            assert!(!a.verify(b"hellp"));
+            assert!(!Address::with_algo(gen_algo(n + 1), a.digest()).verify(b"hello"));
+        }
+    }
+
+    #[test]
+    fn ck_address_bad_multihash() {
+
+        let mut b = Address::of_slice(b"x").to_blob();
+        b[0] = 0x13;
+        assert_eq!(Address::from_slice(b.as_slice()).unwrap_err().kind(), DecodeErrorKind::UnknownCode(0x13));
+
+        let mut b = Address::of_slice(b"x").to_blob();
+        b[1] = 0x14;
+        assert_eq!(Address::from_slice(b.as_slice()).unwrap_err().kind(), DecodeErrorKind::BadLength(0x14));
+
+    }
+
+    #[test]
+    fn ck_address_text() {
+
+        let a = Address::of_slice(&[1, 3, 3, 7]);
+        assert_eq!(a.to_string(), "1220acb86a9cb70a84f695de89e7fe22819466205759d798d52d4a3dd95b0cdaa2a1");
+        assert!(a.to_short_string().starts_with("addr_"));
+        assert_eq!("acb86a9cb70a84f695de89e7fe22819466205759d798d52d4a3dd95b0cdaa2a1".parse(), Ok(a));
+
+        // An address isn't a hash, even though they're made of the same thing.
+        let h: Result<sig::Hash, _> = a.to_short_string().parse();
+        assert_eq!(h, Err(text::ParseError::WrongPrefix { expected: "hash_" }));
+
+    }
+
+    quickcheck! {
+
+        fn prop_address_text(d: Vec<u8>, algo: u8) -> bool {
+            let a = Address::of_slice_with(gen_algo(algo), d.as_slice());
+            a.to_string().parse::<Address>() == Ok(a) && a.to_short_string().parse::<Address>() == Ok(a)
+        }
+
+        fn prop_address_canonical(d: Vec<u8>, algo: u8, idx: usize, byte: u8) -> bool {
+            let a = Address::of_slice_with(gen_algo(algo), d.as_slice());
+            canonical(&a) && mutant_canonical(&a, idx, byte) && strict_bounds(&a)
+        }
+
     }
 
 }
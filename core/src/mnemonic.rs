@@ -0,0 +1,207 @@
+//! BIP-39 mnemonic phrases, so that people can write their identity down and get it back later.
+//!
+//! The phrase and its optional passphrase are turned into the standard 64 byte BIP-39 seed, which
+//! then goes through `Scheme::generate` like any other seed.  So the same phrase always gives the
+//! same keypair for a given scheme, but the two schemes give unrelated keys.
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use bip39;
+use rand::Rng;
+use rand::os::OsRng;
+
+use sig::{Keypair, Scheme};
+
+/// Word count that new phrases get by default, which is 256 bits of entropy.
+pub const DEFAULT_WORDS: usize = 24;
+
+/// Width of the seed that a phrase turns into.
+pub const SEED_WIDTH: usize = 64;
+
+/// A mnemonic phrase from the English BIP-39 word list.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Mnemonic(bip39::Mnemonic);
+
+/// Why a phrase couldn't be made or parsed.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum MnemonicError {
+
+    /// Phrases have to be 12, 15, 18, 21, or 24 words.
+    BadWordCount(usize),
+
+    /// The word at this index isn't in the word list.
+    UnknownWord(usize),
+
+    /// Entropy has to be 16 to 32 bytes, in steps of 4.
+    BadEntropyLength(usize),
+
+    /// All the words are real but the checksum doesn't match, so there's probably a typo.
+    BadChecksum,
+
+    /// The system RNG couldn't be opened.
+    Rng
+
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MnemonicError::BadWordCount(n) => write!(f, "phrase has {} words, must be 12, 15, 18, 21, or 24", n),
+            MnemonicError::UnknownWord(i) => write!(f, "word {} isn't in the word list", i + 1),
+            MnemonicError::BadEntropyLength(n) => write!(f, "entropy is the wrong length ({} bytes)", n),
+            MnemonicError::BadChecksum => write!(f, "checksum mismatch, check the phrase for typos"),
+            MnemonicError::Rng => write!(f, "unable to initialize RNG")
+        }
+    }
+}
+
+impl error::Error for MnemonicError {}
+
+impl From<bip39::Error> for MnemonicError {
+    fn from(e: bip39::Error) -> Self {
+        match e {
+            bip39::Error::BadWordCount(n) => MnemonicError::BadWordCount(n),
+            bip39::Error::UnknownWord(i) => MnemonicError::UnknownWord(i),
+            bip39::Error::BadEntropyBitCount(n) => MnemonicError::BadEntropyLength(n / 8),
+            bip39::Error::InvalidChecksum => MnemonicError::BadChecksum,
+            // We only ever use the English list, so this can't happen.
+            bip39::Error::AmbiguousLanguages(_) => unreachable!()
+        }
+    }
+}
+
+impl Mnemonic {
+
+    /// Makes a new random phrase with the given number of words.
+    pub fn generate(words: usize) -> Result<Mnemonic, MnemonicError> {
+
+        if !(12..=24).contains(&words) || !words.is_multiple_of(3) {
+            return Err(MnemonicError::BadWordCount(words));
+        }
+
+        let mut rng = OsRng::new().map_err(|_| MnemonicError::Rng)?;
+        let mut ent = vec![0; words / 3 * 4];
+        rng.fill_bytes(ent.as_mut_slice());
+        Mnemonic::from_entropy(ent.as_slice())
+
+    }
+
+    /// Makes the phrase that encodes the given entropy.
+    pub fn from_entropy(ent: &[u8]) -> Result<Mnemonic, MnemonicError> {
+        Ok(Mnemonic(bip39::Mnemonic::from_entropy_in(bip39::Language::English, ent)?))
+    }
+
+    /// Gets the entropy back out of the phrase.
+    pub fn to_entropy(&self) -> Vec<u8> {
+        self.0.to_entropy()
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.0.word_count()
+    }
+
+    /// Returns the BIP-39 seed for the phrase and passphrase.  The passphrase can be empty.
+    pub fn to_seed(&self, passphrase: &str) -> [u8; SEED_WIDTH] {
+        self.0.to_seed(passphrase)
+    }
+
+    /// Deterministically derives the keypair for the phrase and passphrase.
+    pub fn to_keypair(&self, scheme: Scheme, passphrase: &str) -> Keypair {
+        scheme.generate(&self.to_seed(passphrase))
+    }
+
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for Mnemonic {
+    type Err = MnemonicError;
+
+    /// Parses a phrase.  Case and extra whitespace between words are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let norm = s.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>().join(" ");
+        Ok(Mnemonic(bip39::Mnemonic::parse_in(bip39::Language::English, norm)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use sig::Fingerprint;
+    use text;
+
+    fn hex(s: &str) -> Vec<u8> {
+        text::parse_hex(s).unwrap()
+    }
+
+    #[test]
+    fn ck_bip39_vectors() {
+
+        // From the reference test vectors, which all use "TREZOR" as the passphrase.
+        let vectors = vec![
+            ("00000000000000000000000000000000",
+             "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+             "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"),
+            ("8080808080808080808080808080808080808080808080808080808080808080",
+             "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless",
+             "c0c519bd0e91a2ed54357d9d1ebef6f5af218a153624cf4f2da911a0ed8f7a09e2ef61af0aca007096df430022f7a2b6fb91661a9589097069720d015e4e982f")
+        ];
+
+        for (ent, phrase, seed) in vectors {
+            let m = Mnemonic::from_entropy(hex(ent).as_slice()).unwrap();
+            assert_eq!(m.to_string(), phrase);
+            assert_eq!(phrase.parse::<Mnemonic>().unwrap(), m);
+            assert_eq!(m.to_entropy(), hex(ent));
+            assert_eq!(m.to_seed("TREZOR").to_vec(), hex(seed));
+        }
+
+    }
+
+    #[test]
+    fn ck_keypair_derivation() {
+
+        let m: Mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".parse().unwrap();
+
+        for s in [Scheme::Ed25519, Scheme::Secp256k1] {
+            let kp = m.to_keypair(s, "");
+            assert_eq!(kp.scheme(), s);
+            assert_eq!(m.to_keypair(s, ""), kp);
+            assert!(m.to_keypair(s, "other") != kp);
+        }
+
+        // Pin down the derivation so that it never changes out from under anyone's backups.
+        let fp: Fingerprint = m.to_keypair(Scheme::Ed25519, "").into();
+        assert_eq!(fp.to_string(), "a5b5511197a9a44b878d1511fd70d34d037e69111a1fa371d8d3d5302606e4e0");
+
+    }
+
+    #[test]
+    fn ck_parse_errors() {
+
+        let good = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        assert_eq!(good.parse::<Mnemonic>(), format!("  {}  ", good.to_uppercase()).parse::<Mnemonic>());
+
+        let bad_sum = good.replace("about", "abandon");
+        assert_eq!(bad_sum.parse::<Mnemonic>(), Err(MnemonicError::BadChecksum));
+        assert_eq!(good.replace("about", "aboot").parse::<Mnemonic>(), Err(MnemonicError::UnknownWord(11)));
+        assert_eq!("abandon about".parse::<Mnemonic>(), Err(MnemonicError::BadWordCount(2)));
+        assert_eq!(Mnemonic::from_entropy(&[0; 15]), Err(MnemonicError::BadEntropyLength(15)));
+
+    }
+
+    #[test]
+    fn ck_generate() {
+        let m = Mnemonic::generate(DEFAULT_WORDS).unwrap();
+        assert_eq!(m.word_count(), 24);
+        assert_eq!(m.to_string().parse::<Mnemonic>().unwrap(), m);
+        assert_eq!(Mnemonic::generate(13), Err(MnemonicError::BadWordCount(13)));
+    }
+
+}
@@ -1,17 +1,35 @@
 
+use std::collections::HashMap;
 use std::convert::{From, Into};
-use std::fmt::{Debug, Error, Formatter};
+use std::fmt::{self, Debug, Display, Error, Formatter};
+use std::io::Read;
+use std::str::FromStr;
 
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
-use crypto::{sha2, ed25519};
+use blake3;
+use crypto::{blake2b, sha2};
 use crypto::digest::Digest;
+use crypto::hmac::Hmac;
 
-use io::{BinaryComponent, WrResult};
-use io::DecodeError;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use ed25519_dalek;
+use k256::ecdsa;
+use k256::ecdsa::signature::{Signer, Verifier};
+
+use io::{BinaryComponent, WrResult, encode_frame};
+use io::{Decoder, DecodeError, DecodeErrorKind, DecodeLimits};
+use text;
 
 pub const SHA256_WIDTH: usize = 32;
 
+/// Mixed into child key derivation so the child seeds can't collide with anything else.
+const CHILD_KEY_DOMAIN: &str = "jiyunet/child-key/v1";
+
+/// Network id for signatures that aren't for any particular network.  These are signed the same
+/// way as before signatures were bound to networks.
+pub const NO_NETWORK: u32 = 0;
+
 /// Generic type for a "signed" version of `T`.
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Signed<T> where T: BinaryComponent {
@@ -23,8 +41,13 @@ impl<T> Signed<T> where T: BinaryComponent {
 
     /// Creates a new signed verison of the given `T`, signed with the specified keypair.
     pub fn new(kp: Keypair, body: T) -> Signed<T> {
+        Signed::new_on(kp, NO_NETWORK, body)
+    }
+
+    /// Like `new`, but the signature is only good on the given network.
+    pub fn new_on(kp: Keypair, network: u32, body: T) -> Signed<T> {
         Signed {
-            signature: kp.sign(body.get_hash()),
+            signature: kp.sign(signing_hash_on(&body, network)),
             body: body
         }
     }
@@ -44,31 +67,374 @@ impl<T> Signed<T> where T: BinaryComponent {
         self.signature.clone()
     }
 
+    /// Checks that the signature was made by the given key, over this body.
+    pub fn verify(&self, vk: ValidationKey) -> Result<(), SigVerificationError> {
+        self.verify_compat(vk, SigCompat::Strict)
+    }
+
+    /// Like `verify`, but for a signature made with `new_on` for the network.
+    pub fn verify_on(&self, vk: ValidationKey, network: u32) -> Result<(), SigVerificationError> {
+
+        if self.signature.into_fingerprint() != vk.into() {
+            return Err(SigVerificationError::FingerprintMismatch);
+        }
+
+        verify(self.signature, vk, &signing_hash_on(&self.body, network).into_array())
+
+    }
+
+    /// Like `verify`, but can also accept signatures from before signing domains.
+    pub fn verify_compat(&self, vk: ValidationKey, compat: SigCompat) -> Result<(), SigVerificationError> {
+
+        if self.signature.into_fingerprint() != vk.into() {
+            return Err(SigVerificationError::FingerprintMismatch);
+        }
+
+        let res = verify(self.signature, vk, &signing_hash(&self.body).into_array());
+        match (res, compat) {
+            (Err(SigVerificationError::KeyMismatch), SigCompat::AllowLegacy) => {
+                // These just signed the hash of the body directly.
+                verify(self.signature, vk, &self.body.get_hash().into_array())
+            },
+            (r, _) => r
+        }
+
+    }
+
+    /// Looks up the key by the fingerprint in the signature and checks against it.
+    pub fn verify_with_resolver<K: KeyResolver>(&self, kr: &K) -> Result<(), SigVerificationError> {
+        self.verify_with_resolver_compat(kr, SigCompat::Strict)
+    }
+
+    /// Like `verify_with_resolver`, but for a signature made with `new_on` for the network.
+    pub fn verify_with_resolver_on<K: KeyResolver>(&self, kr: &K, network: u32) -> Result<(), SigVerificationError> {
+        match kr.resolve(self.signature.into_fingerprint()) {
+            Some(vk) => self.verify_on(vk, network),
+            None => Err(SigVerificationError::UnknownKey)
+        }
+    }
+
+    /// Like `verify_with_resolver`, but can also accept signatures from before signing domains.
+    pub fn verify_with_resolver_compat<K: KeyResolver>(&self, kr: &K, compat: SigCompat) -> Result<(), SigVerificationError> {
+        match kr.resolve(self.signature.into_fingerprint()) {
+            Some(vk) => self.verify_compat(vk, compat),
+            None => Err(SigVerificationError::UnknownKey)
+        }
+    }
+
 }
 
 impl<T> BinaryComponent for Signed<T> where T: BinaryComponent {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode_limits() -> DecodeLimits {
+        T::decode_limits()
+    }
 
-        let sig = Signature::from_reader(read)?;
-        let body = T::from_reader(read)?;
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
 
-        Ok(Signed {
-            signature: sig,
-            body: body
+            let sig = Signature::decode(read)?;
+            let body = T::decode(read)?;
+
+            Ok(Signed {
+                signature: sig,
+                body
+            })
+
+        })
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+        encode_frame::<Self, _>(|| {
+            self.signature.to_writer(write)?;
+            self.body.to_writer(write)?;
+            Ok(())
         })
+    }
+
+}
+
+/// Returns the hash that actually gets signed for the body, which commits to the body's
+/// `signing_domain` as well as its contents.  It's the SHA-256 of the length of the domain as a
+/// big-endian `u16`, the domain, and then the hash of the body.
+pub fn signing_hash<T: BinaryComponent>(body: &T) -> Hash {
+    signing_hash_on(body, NO_NETWORK)
+}
+
+/// Like `signing_hash`, but also commits to the network, so signatures can't be replayed from one
+/// network onto another.  The network id goes between the domain and the hash of the body, as a
+/// big-endian `u32`, unless it's `NO_NETWORK`.
+pub fn signing_hash_on<T: BinaryComponent>(body: &T, network: u32) -> Hash {
+    let dom = T::signing_domain().as_bytes();
+    debug_assert!(dom.len() <= 0xffff, "signing domain too long");
+    let mut buf = Vec::with_capacity(2 + dom.len() + 4 + SHA256_WIDTH);
+    buf.push((dom.len() >> 8) as u8);
+    buf.push(dom.len() as u8);
+    buf.extend_from_slice(dom);
+    if network != NO_NETWORK {
+        buf.write_u32::<BigEndian>(network).unwrap();
+    }
+    buf.extend_from_slice(&body.get_hash().into_array());
+    Hash::of_slice(buf.as_slice())
+}
+
+/// Most keys a `SigPolicy` can have.
+pub const MAX_POLICY_SIGNERS: usize = 32;
+
+/// Which keys have to sign a `MultiSigned<T>`, which is at least `threshold` of them.
+///
+/// The keys are kept sorted and there can't be duplicates, so a policy only has one encoding.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SigPolicy {
+    threshold: u8,
+    signers: Vec<Fingerprint>
+}
+
+impl SigPolicy {
+
+    /// Makes a new policy, if the threshold is between 1 and the number of distinct signers, and
+    /// there aren't more than `MAX_POLICY_SIGNERS` of them.
+    pub fn new(threshold: u8, mut signers: Vec<Fingerprint>) -> Option<SigPolicy> {
+
+        signers.sort();
+        signers.dedup();
+
+        if threshold == 0 || threshold as usize > signers.len() || signers.len() > MAX_POLICY_SIGNERS {
+            return None;
+        }
+
+        Some(SigPolicy {
+            threshold,
+            signers
+        })
+
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// The keys that can sign, sorted.
+    pub fn signers(&self) -> &[Fingerprint] {
+        self.signers.as_slice()
+    }
+
+    pub fn contains(&self, fp: Fingerprint) -> bool {
+        self.signers.binary_search(&fp).is_ok()
+    }
 
+    /// Identifies the policy, so things can refer to a group of keys like they would to one key.
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::new(self.get_hash())
+    }
+
+}
+
+impl BinaryComponent for SigPolicy {
+
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+
+            let threshold = read.read_u8()?;
+            let n = read.read_u8()? as usize;
+            let mut signers = Vec::with_capacity(n);
+            for _ in 0..n {
+                signers.push(Fingerprint::decode(read)?);
+            }
+
+            let sorted = signers.windows(2).all(|w| w[0] < w[1]);
+            match SigPolicy::new(threshold, signers) {
+                Some(p) if sorted => Ok(p),
+                _ => Err(DecodeErrorKind::InvalidValue.into())
+            }
+
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        self.signature.to_writer(write)?;
-        self.body.to_writer(write)?;
+        encode_frame::<Self, _>(|| {
+            write.write_u8(self.threshold)?;
+            write.write_u8(self.signers.len() as u8)?;
+            for fp in &self.signers {
+                fp.to_writer(write)?;
+            }
+            Ok(())
+        })
+    }
+
+}
+
+/// Like `Signed<T>`, but signed by some number of keys from a `SigPolicy`.
+///
+/// Each key signs `multisig_hash` of the policy and the body, so signatures can't be moved onto
+/// some other policy with a lower threshold.  The signatures are kept in the same order as the
+/// policy's keys, with at most one from each.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct MultiSigned<T> where T: BinaryComponent {
+    policy: SigPolicy,
+    signatures: Vec<Signature>,
+    body: T
+}
+
+impl<T> MultiSigned<T> where T: BinaryComponent {
+
+    /// Makes a new one with no signatures yet.
+    pub fn new(policy: SigPolicy, body: T) -> MultiSigned<T> {
+        MultiSigned {
+            policy,
+            signatures: Vec::new(),
+            body
+        }
+    }
+
+    /// Signs with the keypair, which has to be one of the policy's keys.  Signing again with the
+    /// same key replaces its old signature.
+    pub fn sign(&mut self, kp: Keypair) -> Result<(), SigVerificationError> {
+        self.sign_on(kp, NO_NETWORK)
+    }
+
+    /// Like `sign`, but the signature is only good on the given network.
+    pub fn sign_on(&mut self, kp: Keypair, network: u32) -> Result<(), SigVerificationError> {
+        let sig = kp.sign(multisig_hash_on(&self.policy, &self.body, network));
+        self.add_signature(sig)
+    }
+
+    /// Adds a signature made elsewhere over `multisig_hash`.  It isn't checked until `verify`.
+    pub fn add_signature(&mut self, sig: Signature) -> Result<(), SigVerificationError> {
+
+        let fp = sig.into_fingerprint();
+        if !self.policy.contains(fp) {
+            return Err(SigVerificationError::NotInPolicy);
+        }
+
+        match self.signatures.binary_search_by_key(&fp, |s| s.into_fingerprint()) {
+            Ok(i) => self.signatures[i] = sig,
+            Err(i) => self.signatures.insert(i, sig)
+        }
+
         Ok(())
+
+    }
+
+    pub fn policy(&self) -> &SigPolicy {
+        &self.policy
+    }
+
+    pub fn sigs(&self) -> &[Signature] {
+        self.signatures.as_slice()
     }
 
+    /// Unwraps the contained type into its unsigned form.
+    pub fn extract(self) -> T {
+        self.body
+    }
+
+    /// Extracts a copy of the body.
+    pub fn extract_owned(&self) -> T {
+        self.body.clone()
+    }
+
+    /// Checks that every signature is good and that there's enough of them, looking up the keys
+    /// with the resolver.
+    pub fn verify_with_resolver<K: KeyResolver>(&self, kr: &K) -> Result<(), SigVerificationError> {
+        self.verify_with_resolver_on(kr, NO_NETWORK)
+    }
+
+    /// Like `verify_with_resolver`, but for signatures made with `sign_on` for the network.
+    pub fn verify_with_resolver_on<K: KeyResolver>(&self, kr: &K, network: u32) -> Result<(), SigVerificationError> {
+
+        let msg = multisig_hash_on(&self.policy, &self.body, network).into_array();
+        for sig in &self.signatures {
+            let fp = sig.into_fingerprint();
+            match kr.resolve(fp) {
+                Some(vk) if fp == vk.into() => verify(*sig, vk, &msg)?,
+                Some(_) => return Err(SigVerificationError::FingerprintMismatch),
+                None => return Err(SigVerificationError::UnknownKey)
+            }
+        }
+
+        match self.signatures.len() >= self.policy.threshold as usize {
+            true => Ok(()),
+            false => Err(SigVerificationError::ThresholdNotMet)
+        }
+
+    }
+
+}
+
+impl<T> BinaryComponent for MultiSigned<T> where T: BinaryComponent {
+
+    fn decode_limits() -> DecodeLimits {
+        T::decode_limits()
+    }
+
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+
+            let policy = SigPolicy::decode(read)?;
+            let n = read.read_u8()? as usize;
+            if n > policy.signers.len() {
+                return Err(DecodeErrorKind::InvalidValue.into());
+            }
+
+            let mut sigs: Vec<Signature> = Vec::with_capacity(n);
+            for _ in 0..n {
+                let sig = Signature::decode(read)?;
+                let fp = sig.into_fingerprint();
+                let ordered = sigs.last().is_none_or(|l| l.into_fingerprint() < fp);
+                if !ordered || !policy.contains(fp) {
+                    return Err(DecodeErrorKind::InvalidValue.into());
+                }
+                sigs.push(sig);
+            }
+
+            Ok(MultiSigned {
+                policy,
+                signatures: sigs,
+                body: T::decode(read)?
+            })
+
+        })
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+        encode_frame::<Self, _>(|| {
+            self.policy.to_writer(write)?;
+            write.write_u8(self.signatures.len() as u8)?;
+            for sig in &self.signatures {
+                sig.to_writer(write)?;
+            }
+            self.body.to_writer(write)?;
+            Ok(())
+        })
+    }
+
+}
+
+/// Returns the hash the keys sign in a `MultiSigned<T>`.  It's the SHA-256 of the policy's hash
+/// and the body's `signing_hash`.
+pub fn multisig_hash<T: BinaryComponent>(policy: &SigPolicy, body: &T) -> Hash {
+    multisig_hash_on(policy, body, NO_NETWORK)
+}
+
+/// Like `multisig_hash`, but with the body's `signing_hash_on` for the network.
+pub fn multisig_hash_on<T: BinaryComponent>(policy: &SigPolicy, body: &T, network: u32) -> Hash {
+    let mut buf = Vec::with_capacity(SHA256_WIDTH * 2);
+    buf.extend_from_slice(&policy.get_hash().into_array());
+    buf.extend_from_slice(&signing_hash_on(body, network).into_array());
+    Hash::of_slice(buf.as_slice())
+}
+
+/// Whether to accept signatures made before signing domains existed, which are over the bare
+/// hash of the body.  Only meant for checking data that was signed back then.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SigCompat {
+    Strict,
+    AllowLegacy
 }
 
-/// A SHA-256 hash.
+/// A 32 byte hash.  This is SHA-256 everywhere except in `Address`es, which say which
+/// `HashAlgo` made them.
 #[derive(Copy, Ord, PartialOrd, Hash, Debug)]
 pub struct Hash([u8; SHA256_WIDTH]);
 
@@ -93,6 +459,24 @@ impl Hash {
         self.0
     }
 
+    /// Returns the checksummed base58 form, like `hash_...`.
+    pub fn to_short_string(&self) -> String {
+        text::to_short(text::HASH_PREFIX, &self.0)
+    }
+
+}
+
+impl Display for Hash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        text::write_hex(f, &self.0)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = text::ParseError;
+    fn from_str(s: &str) -> Result<Hash, text::ParseError> {
+        text::parse_hash(text::HASH_PREFIX, s)
+    }
 }
 
 impl Clone for Hash {
@@ -119,16 +503,74 @@ impl Into<[u8; SHA256_WIDTH]> for Hash {
 
 impl BinaryComponent for Hash {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        let mut buf = [0; SHA256_WIDTH];
-        read.read(&mut buf)?;
-        Ok(Hash::new(buf))
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+            let mut buf = [0; SHA256_WIDTH];
+            read.read_exact(&mut buf)?;
+            Ok(Hash::new(buf))
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
         let &Hash(d) = self;
-        write.write(&d)?;
-        Ok(())
+        encode_frame::<Self, _>(|| {
+            write.write_all(&d)?;
+            Ok(())
+        })
+    }
+
+}
+
+/// Hash functions that an `Address` can be made with.  All of them produce `SHA256_WIDTH` bytes.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum HashAlgo {
+    Sha256,
+    Blake2b256,
+    Blake3
+}
+
+impl HashAlgo {
+
+    /// The multicodec code for the algorithm, which is what gets written in a multihash.
+    pub fn code(self) -> u64 {
+        match self {
+            HashAlgo::Sha256 => 0x12,
+            HashAlgo::Blake2b256 => 0xb220,
+            HashAlgo::Blake3 => 0x1e
+        }
+    }
+
+    pub fn from_code(code: u64) -> Option<HashAlgo> {
+        match code {
+            0x12 => Some(HashAlgo::Sha256),
+            0xb220 => Some(HashAlgo::Blake2b256),
+            0x1e => Some(HashAlgo::Blake3),
+            _ => None
+        }
+    }
+
+    /// The multicodec name for the algorithm.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha2-256",
+            HashAlgo::Blake2b256 => "blake2b-256",
+            HashAlgo::Blake3 => "blake3"
+        }
+    }
+
+    /// Hashes the blob of data with the algorithm.
+    pub fn digest(self, data: &[u8]) -> Hash {
+        match self {
+            HashAlgo::Sha256 => Hash::of_slice(data),
+            HashAlgo::Blake2b256 => {
+                let mut hasher = blake2b::Blake2b::new(SHA256_WIDTH);
+                hasher.input(data);
+                let mut out = [0u8; SHA256_WIDTH];
+                hasher.result(&mut out);
+                Hash::new(out)
+            },
+            HashAlgo::Blake3 => Hash::new(*blake3::hash(data).as_bytes())
+        }
     }
 
 }
@@ -143,6 +585,24 @@ impl Fingerprint {
         Fingerprint(hash)
     }
 
+    /// Returns the checksummed base58 form, like `fpr_...`.
+    pub fn to_short_string(&self) -> String {
+        text::to_short(text::FINGERPRINT_PREFIX, &self.0 .0)
+    }
+
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = text::ParseError;
+    fn from_str(s: &str) -> Result<Fingerprint, text::ParseError> {
+        text::parse_hash(text::FINGERPRINT_PREFIX, s).map(Fingerprint::new)
+    }
 }
 
 impl From<[u8; SHA256_WIDTH]> for Fingerprint {
@@ -165,38 +625,62 @@ impl Into<Hash> for Fingerprint {
 
 impl BinaryComponent for Fingerprint {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        Ok(Fingerprint::new(Hash::from_reader(read)?))
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| Ok(Fingerprint::new(Hash::decode(read)?)))
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        self.0.to_writer(write)
+        encode_frame::<Self, _>(|| self.0.to_writer(write))
     }
 
 }
 
-/// A signature algorithm scheme.  Only supports Ed25519 for now.
+/// A signature algorithm scheme.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum Scheme {
     Ed25519,
+
+    /// ECDSA over secp256k1 with SHA-256, like Bitcoin.  Signatures are always low-S.
+    Secp256k1,
 }
 
 impl Scheme {
 
     /// Generates a new keypair using the scheme (ourselves) and the given seed,
+    ///
+    /// Seeds are 32 bytes.  Seeds of any other length get hashed down to that first, since that's
+    /// all an Ed25519 secret key is.  For secp256k1 the seed is the secret key, and it gets
+    /// rehashed in the unlikely case it's out of range.
     pub fn generate(self, seed: &[u8]) -> Keypair {
+
+        let mut sd = [0; SHA256_WIDTH];
+        match seed.len() {
+            SHA256_WIDTH => sd.copy_from_slice(seed),
+            _ => sd = Hash::of_slice(seed).into_array()
+        }
+
         match self {
             Scheme::Ed25519 => {
-                let (kpriv, kpub) = ed25519::keypair(seed);
-                Keypair::Ed25519(kpriv, kpub)
+                let k = ed25519_dalek::SigningKey::from_bytes(&sd);
+                Keypair::Ed25519(k.to_keypair_bytes(), k.verifying_key().to_bytes())
+            },
+            Scheme::Secp256k1 => loop {
+                if let Ok(k) = ecdsa::SigningKey::from_slice(&sd) {
+                    let mut p = [0; 33];
+                    p.copy_from_slice(k.verifying_key().to_encoded_point(true).as_bytes());
+                    return Keypair::Secp256k1(sd, p);
+                }
+                sd = Hash::of_slice(&sd).into_array();
             }
         }
+
     }
 
     pub fn from_specifier(s: u8) -> Option<Scheme> {
         use self::Scheme::*;
         match s {
             0x00 => Some(Ed25519),
+            0x01 => Some(Secp256k1),
             _ => None
         }
     }
@@ -204,18 +688,38 @@ impl Scheme {
     pub fn to_specifier(&self) -> u8 {
         use self::Scheme::*;
         match *self {
-            Ed25519 => 0x00
+            Ed25519 => 0x00,
+            Secp256k1 => 0x01
+        }
+    }
+
+    /// Lowercase name of the scheme, for command lines and config files.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Scheme::Ed25519 => "ed25519",
+            Scheme::Secp256k1 => "secp256k1"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Scheme> {
+        match name {
+            "ed25519" => Some(Scheme::Ed25519),
+            "secp256k1" => Some(Scheme::Secp256k1),
+            _ => None
         }
     }
 
 }
 
-/// A keypair using some signature algorithm.  Only support Ed25519 for now.
+/// A keypair using some signature algorithm.
 #[derive(Copy)]
 pub enum Keypair {
 
     /// Edwards curve signature algorithm.  `(private key, public key)`
-    Ed25519([u8; 64], [u8; 32])
+    Ed25519([u8; 64], [u8; 32]),
+
+    /// secp256k1 ECDSA.  `(secret scalar, compressed public key)`
+    Secp256k1([u8; 32], [u8; 33])
 
 }
 
@@ -223,7 +727,8 @@ impl Debug for Keypair {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         use self::Keypair::*;
         f.write_str(match self {
-            &Ed25519(_, _) => "[keypair Ed25519]"
+            &Ed25519(_, _) => "[keypair Ed25519]",
+            &Secp256k1(_, _) => "[keypair Secp256k1]"
         })
     }
 }
@@ -235,28 +740,27 @@ impl ::std::fmt::Display for Keypair {
         use std::fmt::Write;
         use self::Keypair::*;
 
-        match self {
-            &Ed25519(ref k, ref p) => {
-
-                f.write_str("Ed25519(")?;
-                let mut ks = String::with_capacity(128);
-                for i in 0..k.len() {
-                    write!(&mut ks, "{:X}", k[i]).expect("error printing keypair");
-                }
-
-                let mut ps = String::with_capacity(64);
-                for i in 0..p.len() {
-                    write!(&mut ps, "{:X}", p[i]).expect("error printing keypair");
-                }
+        let (name, k, p): (&str, &[u8], &[u8]) = match *self {
+            Ed25519(ref k, ref p) => ("Ed25519(", k, p),
+            Secp256k1(ref k, ref p) => ("Secp256k1(", k, p)
+        };
 
-                f.write_str(ks.as_str())?;
-                f.write_str(", ")?;
-                f.write_str(ps.as_str())?;
-                f.write_str(")")?;
+        f.write_str(name)?;
+        let mut ks = String::with_capacity(k.len() * 2);
+        for b in k {
+            write!(&mut ks, "{:X}", b).expect("error printing keypair");
+        }
 
-            }
+        let mut ps = String::with_capacity(p.len() * 2);
+        for b in p {
+            write!(&mut ps, "{:X}", b).expect("error printing keypair");
         }
 
+        f.write_str(ks.as_str())?;
+        f.write_str(", ")?;
+        f.write_str(ps.as_str())?;
+        f.write_str(")")?;
+
         Ok(())
 
     }
@@ -266,10 +770,75 @@ impl Keypair {
 
     /// Generates a signature for the given hash using this keypair.
     pub fn sign(&self, hash: Hash) -> Signature {
+        let fp: Fingerprint = (*self).into();
         match self {
-            &Keypair::Ed25519(kpriv, kpub) => {
-                let q = ed25519::signature(&hash.into_array(), &kpriv);
-                Signature::Ed25519(q, Fingerprint::new(Hash::of_slice(&kpub)))
+            &Keypair::Ed25519(kpriv, _) => {
+                let mut sd = [0; 32];
+                sd.copy_from_slice(&kpriv[..32]);
+                let q = ed25519_dalek::SigningKey::from_bytes(&sd).sign(&hash.into_array());
+                Signature::Ed25519(q.to_bytes(), fp)
+            },
+            &Keypair::Secp256k1(kpriv, _) => {
+                // Decoding makes sure of this, so it's only possible if someone built a bad one by hand.
+                let k = ecdsa::SigningKey::from_slice(&kpriv).expect("secp256k1 secret out of range");
+                let s: ecdsa::Signature = k.sign(&hash.into_array());
+                let mut q = [0; 64];
+                q.copy_from_slice(s.to_bytes().as_slice());
+                Signature::Secp256k1(q, fp)
+            }
+        }
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        match *self {
+            Keypair::Ed25519(_, _) => Scheme::Ed25519,
+            Keypair::Secp256k1(_, _) => Scheme::Secp256k1
+        }
+    }
+
+    /// Deterministically derives the child keypair at the index, using the same scheme.
+    ///
+    /// The child seed is HMAC-SHA256 keyed with our secret over the domain, scheme, and index, so
+    /// there's no way to get from a public key to its children.  Anyone who needs to know that a
+    /// child belongs to us has to be told with a signed delegation.
+    pub fn derive_child(&self, index: u32) -> Keypair {
+
+        // Not imported up top since its methods clash with `Digest`'s.
+        use crypto::mac::Mac;
+
+        let secret: &[u8] = match *self {
+            Keypair::Ed25519(ref k, _) => &k[..32], // The rest is the public key.
+            Keypair::Secp256k1(ref k, _) => k
+        };
+
+        let mut mac = Hmac::new(sha2::Sha256::new(), secret);
+        mac.input(CHILD_KEY_DOMAIN.as_bytes());
+        mac.input(&[self.scheme().to_specifier()]);
+        mac.input(&[(index >> 24) as u8, (index >> 16) as u8, (index >> 8) as u8, index as u8]);
+
+        let mut seed = [0; SHA256_WIDTH];
+        mac.raw_result(&mut seed);
+        self.scheme().generate(&seed)
+
+    }
+
+    /// Derives down a whole path of indexes, like `[7, 0]` for the first child of the 8th child.
+    pub fn derive_path(&self, path: &[u32]) -> Keypair {
+        path.iter().fold(*self, |kp, i| kp.derive_child(*i))
+    }
+
+    /// If the secret is one we can sign with and the public key is actually the one for it.
+    fn is_consistent(&self) -> bool {
+        match *self {
+            Keypair::Ed25519(k, p) => {
+                let mut sd = [0; 32];
+                sd.copy_from_slice(&k[..32]);
+                let kp = ed25519_dalek::SigningKey::from_bytes(&sd).to_keypair_bytes();
+                arr_eq(&kp[..], &k[..]) && arr_eq(&k[32..], &p[..])
+            },
+            Keypair::Secp256k1(k, p) => match ecdsa::SigningKey::from_slice(&k) {
+                Ok(sk) => arr_eq(sk.verifying_key().to_encoded_point(true).as_bytes(), &p[..]),
+                Err(_) => false
             }
         }
     }
@@ -280,17 +849,30 @@ impl BinaryComponent for Keypair {
 
     // TODO Make the 0x00 discriminators not be stupid.
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
         use self::Keypair::*;
-        match read.read_u8()? {
-            0x00 => {
-                let mut kbuf = [0; 64];
-                read.read(&mut kbuf)?;
-                let mut pbuf = [0; 32];
-                read.read(&mut pbuf)?;
-                Ok(Ed25519(kbuf, pbuf))
-            },
-            _ => Err(DecodeError)
+        let kp = read.frame(|read| {
+            match read.read_u8()? {
+                0x00 => {
+                    let mut kbuf = [0; 64];
+                    read.read_exact(&mut kbuf)?;
+                    let mut pbuf = [0; 32];
+                    read.read_exact(&mut pbuf)?;
+                    Ok(Ed25519(kbuf, pbuf))
+                },
+                0x01 => {
+                    let mut kbuf = [0; 32];
+                    read.read_exact(&mut kbuf)?;
+                    let mut pbuf = [0; 33];
+                    read.read_exact(&mut pbuf)?;
+                    Ok(Secp256k1(kbuf, pbuf))
+                },
+                t => Err(DecodeErrorKind::UnknownDiscriminator(t).into())
+            }
+        })?;
+        match kp.is_consistent() {
+            true => Ok(kp),
+            false => Err(DecodeErrorKind::InvalidValue.into())
         }
     }
 
@@ -298,18 +880,24 @@ impl BinaryComponent for Keypair {
 
         use self::Keypair::*;
 
-        write.write_u8(match self {
-            &Ed25519(_, _) => 0x00
-        })?;
+        encode_frame::<Self, _>(|| {
 
-        match self {
-            &Ed25519(k, p) => {
-                write.write(&k)?;
-                write.write(&p)?;
+            write.write_u8(self.scheme().to_specifier())?;
+
+            match *self {
+                Ed25519(k, p) => {
+                    write.write_all(&k)?;
+                    write.write_all(&p)?;
+                },
+                Secp256k1(k, p) => {
+                    write.write_all(&k)?;
+                    write.write_all(&p)?;
+                }
             }
-        }
 
-        Ok(())
+            Ok(())
+
+        })
 
     }
 
@@ -318,11 +906,19 @@ impl BinaryComponent for Keypair {
 impl Into<ValidationKey> for Keypair {
     fn into(self) -> ValidationKey {
         match self {
-            Keypair::Ed25519(_, k) => ValidationKey::Ed25519(k)
+            Keypair::Ed25519(_, k) => ValidationKey::Ed25519(k),
+            Keypair::Secp256k1(_, k) => ValidationKey::Secp256k1(k)
         }
     }
 }
 
+impl From<Keypair> for Fingerprint {
+    fn from(kp: Keypair) -> Fingerprint {
+        let vk: ValidationKey = kp.into();
+        vk.into()
+    }
+}
+
 impl Clone for Keypair {
     fn clone(&self) -> Self {
         *self
@@ -335,6 +931,7 @@ impl PartialEq for Keypair {
         use self::Keypair::*;
         match (*self, *other) {
             (Ed25519(ap, ak), Ed25519(bp, bk)) => arr_eq(&ap, &bp) && arr_eq(&ak, &bk),
+            (Secp256k1(ap, ak), Secp256k1(bp, bk)) => arr_eq(&ap, &bp) && arr_eq(&ak, &bk),
             _ => false,
         }
     }
@@ -346,13 +943,28 @@ impl Eq for Keypair {}
 #[derive(Copy, Hash, Debug)]
 pub enum ValidationKey {
     Ed25519([u8; 32]),
+
+    /// A SEC1 compressed point.
+    Secp256k1([u8; 33]),
+}
+
+impl ValidationKey {
+
+    pub fn scheme(&self) -> Scheme {
+        match *self {
+            ValidationKey::Ed25519(_) => Scheme::Ed25519,
+            ValidationKey::Secp256k1(_) => Scheme::Secp256k1
+        }
+    }
+
 }
 
 impl Into<Fingerprint> for ValidationKey {
     fn into(self) -> Fingerprint {
         use self::ValidationKey::*;
         Fingerprint::new(match self {
-            Ed25519(k) => Hash::of_slice(&k)
+            Ed25519(k) => Hash::of_slice(&k),
+            Secp256k1(k) => Hash::of_slice(&k)
         })
     }
 }
@@ -369,6 +981,7 @@ impl PartialEq for ValidationKey {
         use self::ValidationKey::*;
         match (*self, *other) {
             (Ed25519(a), Ed25519(b)) => arr_eq(&a, &b),
+            (Secp256k1(a), Secp256k1(b)) => arr_eq(&a, &b),
             _ => false,
         }
     }
@@ -378,31 +991,41 @@ impl Eq for ValidationKey {}
 
 impl BinaryComponent for ValidationKey {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
         use self::ValidationKey::*;
-        match read.read_u8()? {
-            0x00 => {
-                let mut buf = [0; 32];
-                read.read(&mut buf)?;
-                Ok(Ed25519(buf))
-            },
-            _ => Err(DecodeError)
-        }
+        read.frame(|read| {
+            match read.read_u8()? {
+                0x00 => {
+                    let mut buf = [0; 32];
+                    read.read_exact(&mut buf)?;
+                    Ok(Ed25519(buf))
+                },
+                0x01 => {
+                    let mut buf = [0; 33];
+                    read.read_exact(&mut buf)?;
+                    Ok(Secp256k1(buf))
+                },
+                t => Err(DecodeErrorKind::UnknownDiscriminator(t).into())
+            }
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
 
         use self::ValidationKey::*;
 
-        write.write_u8(match self {
-            &Ed25519(_) => 0x00
-        })?;
+        encode_frame::<Self, _>(|| {
 
-        match self {
-            &Ed25519(k) => write.write(&k).map(|_| ())?
-        }
+            write.write_u8(self.scheme().to_specifier())?;
 
-        Ok(())
+            match *self {
+                Ed25519(k) => write.write_all(&k)?,
+                Secp256k1(k) => write.write_all(&k)?
+            }
+
+            Ok(())
+
+        })
 
     }
 
@@ -412,6 +1035,9 @@ impl BinaryComponent for ValidationKey {
 #[derive(Copy)]
 pub enum Signature {
     Ed25519([u8; 64], Fingerprint),
+
+    /// Compact `r || s`, with `s` in the lower half of the order.
+    Secp256k1([u8; 64], Fingerprint),
 }
 
 impl Clone for Signature {
@@ -426,6 +1052,7 @@ impl PartialEq for Signature {
         use self::Signature::*;
         match (*self, *other) {
             (Ed25519(a, af), Ed25519(b, bf)) => af == bf && arr_eq(&a, &b),
+            (Secp256k1(a, af), Secp256k1(b, bf)) => af == bf && arr_eq(&a, &b),
             _ => false,
         }
     }
@@ -443,11 +1070,11 @@ impl Debug for Signature {
 impl Signature {
 
     /// Returns the signature scheme used for this signature.
-    #[allow(dead_code)]
-    fn scheme(&self) -> Scheme {
+    pub fn scheme(&self) -> Scheme {
         use self::Signature::*;
         match self {
             &Ed25519(_, _) => Scheme::Ed25519,
+            &Secp256k1(_, _) => Scheme::Secp256k1,
         }
     }
 
@@ -455,6 +1082,7 @@ impl Signature {
     pub fn into_fingerprint(self) -> Fingerprint {
         match self {
             Signature::Ed25519(_, f) => f,
+            Signature::Secp256k1(_, f) => f,
         }
     }
 
@@ -462,28 +1090,36 @@ impl Signature {
 
 impl BinaryComponent for Signature {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        match Scheme::from_specifier(read.read_u8()?) {
-            Some(s) => match s {
-                Scheme::Ed25519 => {
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+            let spec = read.read_u8()?;
+            match Scheme::from_specifier(spec) {
+                Some(s) => {
+                    // Both of them happen to be 64 bytes.
                     let mut sd = [0; 64];
-                    read.read(&mut sd)?;
-                    let f = Fingerprint::from_reader(read)?;
-                    Ok(Signature::Ed25519(sd, f))
-                }
-            },
-            None => Err(DecodeError)
-        }
+                    read.read_exact(&mut sd)?;
+                    let f = Fingerprint::decode(read)?;
+                    Ok(match s {
+                        Scheme::Ed25519 => Signature::Ed25519(sd, f),
+                        Scheme::Secp256k1 => Signature::Secp256k1(sd, f)
+                    })
+                },
+                None => Err(DecodeErrorKind::UnknownDiscriminator(spec).into())
+            }
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        match self {
-            &Signature::Ed25519(t, f) => {
-                write.write(&t)?;
-                f.to_writer(write)?;
+        encode_frame::<Self, _>(|| {
+            write.write_u8(self.scheme().to_specifier())?;
+            match self {
+                &Signature::Ed25519(t, f) | &Signature::Secp256k1(t, f) => {
+                    write.write_all(&t)?;
+                    f.to_writer(write)?;
+                }
             }
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
 }
@@ -493,35 +1129,171 @@ pub enum SigVerificationError {
     FingerprintMismatch,
     SignatureSchemeMismatch,
     KeyMismatch,
+    UnknownKey,
+
+    /// Signed by a key that isn't in the `SigPolicy`.
+    NotInPolicy,
+
+    /// Not enough keys from the `SigPolicy` signed.
+    ThresholdNotMet,
+}
+
+/// Something that knows the `ValidationKey` behind some `Fingerprint`s, like the set of
+/// identities that have been declared so far.
+pub trait KeyResolver {
+    fn resolve(&self, fp: Fingerprint) -> Option<ValidationKey>;
+}
+
+impl KeyResolver for HashMap<Fingerprint, ValidationKey> {
+    fn resolve(&self, fp: Fingerprint) -> Option<ValidationKey> {
+        self.get(&fp).cloned()
+    }
 }
 
-#[allow(unreachable_patterns)] // Remove this when necessary.
 /// Verifies that a `Signature`, `ValidationKey`, and binary data match properly.
+///
+/// Ed25519 signatures get ed25519-dalek's strict check, which rejects small-order keys and nonces
+/// and non-canonical scalars, so there's exactly one answer for whether a signature is good.
 pub fn verify(sig: Signature, vk: ValidationKey, data: &[u8]) -> Result<(), SigVerificationError> {
     use self::SigVerificationError::*;
-    match (sig, vk) {
+    let ok = match (sig, vk) {
         (Signature::Ed25519(sd, _), ValidationKey::Ed25519(kd)) => {
-            if ed25519::verify(data, &kd, &sd) {
-                Ok(())
-            } else {
-                Err(KeyMismatch)
+            match ed25519_dalek::VerifyingKey::from_bytes(&kd) {
+                Ok(k) => k.verify_strict(data, &ed25519_dalek::Signature::from_bytes(&sd)).is_ok(),
+                Err(_) => false
             }
-        }
-        _ => Err(SignatureSchemeMismatch),
+        },
+        (Signature::Secp256k1(sd, _), ValidationKey::Secp256k1(kd)) => {
+            // This rejects high-S signatures, so they can't be malleated.
+            match (ecdsa::VerifyingKey::from_sec1_bytes(&kd), ecdsa::Signature::from_slice(&sd)) {
+                (Ok(k), Ok(s)) => k.verify(data, &s).is_ok(),
+                _ => false
+            }
+        },
+        _ => return Err(SignatureSchemeMismatch),
+    };
+    match ok {
+        true => Ok(()),
+        false => Err(KeyMismatch)
     }
 }
 
 /// Verifies that a `Signed<T>` is correct, assuming the specified `ValidationKey`.
 pub fn verify_signed<T: BinaryComponent>(st: &Signed<T>, vk: ValidationKey) -> Result<(), SigVerificationError> {
+    st.verify(vk)
+}
 
-    use self::SigVerificationError::*;
+/// Below this it's not worth setting up a batch.
+const MIN_BATCH: usize = 4;
+
+/// Verifies a bunch of `(Signature, ValidationKey, data)` triples at once, which is a lot faster
+/// than doing them one at a time.  If any are bad then we fall back to checking each of them with
+/// `verify` to find out which, and return their indexes along with what was wrong.
+///
+/// Only Ed25519 signatures can actually be batched, anything else gets checked on its own.
+///
+/// The batch equation can disagree with `verify` when the key or the nonce has a small-order
+/// component.  So those never go in the batch, they get checked with `verify` like everything
+/// else, and both ways always agree.
+pub fn verify_batch(items: &[(Signature, ValidationKey, &[u8])]) -> Result<(), Vec<(usize, SigVerificationError)>> {
+
+    let batchable: Vec<bool> = items.iter()
+        .map(|&(sig, vk, _)| batch_safe(sig, vk))
+        .collect();
+
+    let batch: Vec<_> = items.iter().zip(batchable.iter()).filter(|&(_, b)| *b).map(|(it, _)| *it).collect();
+    let batched = batch.len() >= MIN_BATCH && batch_ok(batch.as_slice());
+
+    let bad: Vec<_> = items.iter()
+        .enumerate()
+        .filter(|&(i, _)| !(batched && batchable[i]))
+        .filter_map(|(i, &(sig, vk, data))| verify(sig, vk, data).err().map(|e| (i, e)))
+        .collect();
+
+    match bad.is_empty() {
+        true => Ok(()),
+        false => Err(bad)
+    }
 
-    let sfp = st.sig().into_fingerprint(); // Signature FingerPrint
+}
+
+/// If it's an Ed25519 signature where both the key and the nonce are in the prime-order subgroup,
+/// which is when the batch equation and `verify` give the same answer.
+fn batch_safe(sig: Signature, vk: ValidationKey) -> bool {
+    match (sig, vk) {
+        (Signature::Ed25519(sd, _), ValidationKey::Ed25519(kd)) => {
+            let mut r = [0; 32];
+            r.copy_from_slice(&sd[..32]);
+            [r, kd].iter().all(|p| match CompressedEdwardsY(*p).decompress() {
+                Some(pt) => pt.is_torsion_free() && !pt.is_small_order(),
+                None => false
+            })
+        },
+        _ => false
+    }
+}
+
+#[allow(unreachable_patterns)]
+fn batch_ok(items: &[(Signature, ValidationKey, &[u8])]) -> bool {
+
+    let mut msgs = Vec::with_capacity(items.len());
+    let mut sigs = Vec::with_capacity(items.len());
+    let mut keys = Vec::with_capacity(items.len());
+
+    for &(sig, vk, data) in items {
+        match (sig, vk) {
+            (Signature::Ed25519(sd, _), ValidationKey::Ed25519(kd)) => {
+                match ed25519_dalek::VerifyingKey::from_bytes(&kd) {
+                    Ok(k) => keys.push(k),
+                    Err(_) => return false
+                }
+                sigs.push(ed25519_dalek::Signature::from_bytes(&sd));
+                msgs.push(data);
+            },
+            _ => return false
+        }
+    }
+
+    ed25519_dalek::verify_batch(msgs.as_slice(), sigs.as_slice(), keys.as_slice()).is_ok()
+
+}
+
+/// Verifies a list of `Signed<T>`s in one batch, looking up each of their keys with the resolver.
+/// Returns the indexes of the ones that are bad, like `verify_batch`.
+pub fn verify_signed_batch<T, K>(sts: &[Signed<T>], kr: &K) -> Result<(), Vec<(usize, SigVerificationError)>>
+        where T: BinaryComponent, K: KeyResolver {
+    verify_signed_batch_on(sts, kr, NO_NETWORK)
+}
+
+/// Like `verify_signed_batch`, but for signatures made with `Signed::new_on` for the network.
+pub fn verify_signed_batch_on<T, K>(sts: &[Signed<T>], kr: &K, network: u32) -> Result<(), Vec<(usize, SigVerificationError)>>
+        where T: BinaryComponent, K: KeyResolver {
+
+    let mut bad = Vec::new();
+    let mut idxs = Vec::with_capacity(sts.len());
+    let mut checks = Vec::with_capacity(sts.len());
+
+    for (i, st) in sts.iter().enumerate() {
+        let fp = st.signature.into_fingerprint();
+        match kr.resolve(fp) {
+            Some(vk) if fp == vk.into() => {
+                idxs.push(i);
+                checks.push((st.signature, vk, signing_hash_on(&st.body, network).into_array()));
+            },
+            Some(_) => bad.push((i, SigVerificationError::FingerprintMismatch)),
+            None => bad.push((i, SigVerificationError::UnknownKey))
+        }
+    }
+
+    let items: Vec<_> = checks.iter().map(|&(sig, vk, ref msg)| (sig, vk, &msg[..])).collect();
+    if let Err(es) = verify_batch(items.as_slice()) {
+        bad.extend(es.into_iter().map(|(j, e)| (idxs[j], e)));
+        bad.sort_by_key(|&(i, _)| i);
+    }
 
-    if sfp != vk.into() {
-        Err(FingerprintMismatch)
-    } else {
-        verify_signed(st, vk)
+    match bad.is_empty() {
+        true => Ok(()),
+        false => Err(bad)
     }
 
 }
@@ -538,6 +1310,577 @@ fn arr_eq<T: PartialEq>(a: &[T], b: &[T]) -> bool {
         }
     }
 
-    return true;
+    true
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use io::DecodeErrorKind;
+    use testutil::*;
+
+    use super::*;
+
+    fn keypair(seed: &[u8]) -> Keypair {
+        Scheme::Ed25519.generate(seed)
+    }
+
+    #[test]
+    fn ck_derive_child() {
+
+        for secp in [false, true] {
+
+            let master = scheme(secp).generate(&[42; 32]);
+            let a = master.derive_child(0);
+            let b = master.derive_child(1);
+
+            assert_eq!(a.scheme(), master.scheme());
+            assert_eq!(master.derive_child(0), a);
+            assert!(a != b && a != master);
+            assert_eq!(master.derive_path(&[1, 0]), b.derive_child(0));
+            assert_eq!(master.derive_path(&[]), master);
+
+            // Children are real keys.
+            assert!(Signed::new(a, "hi".to_string()).verify(a.into()).is_ok());
+
+        }
+
+        // Pin the derivation down, since changing it would orphan everyone's sub-identities.
+        let fp: Fingerprint = scheme(false).generate(&[42; 32]).derive_path(&[3, 1]).into();
+        assert_eq!(fp.to_string(), "768e884321b0e3dfbbc01e7cc8c3eb1e1a366faaa5267125f99a5a1a0245ab3d");
+
+    }
+
+    quickcheck! {
+
+        fn prop_multisigned_canonical(n: u8, k: u8, signed: u8, body: String, idx: usize, byte: u8) -> bool {
+            let n = n % 5 + 1;
+            let kps: Vec<Keypair> = (0..n).map(|i| scheme(i % 2 == 0).generate(&[i, n])).collect();
+            let policy = SigPolicy::new(k % n + 1, kps.iter().map(|kp| (*kp).into()).collect()).unwrap();
+            let mut ms = MultiSigned::new(policy, body);
+            for kp in kps.iter().take((signed % (n + 1)) as usize) {
+                ms.sign(*kp).unwrap();
+            }
+            canonical(&ms) && mutant_canonical(&ms, idx, byte) && strict_bounds(&ms)
+        }
+
+        fn prop_any_seed_verifies(seed: Vec<u8>, secp: bool, body: String) -> bool {
+            let kp = scheme(secp).generate(seed.as_slice());
+            Signed::new(kp, body).verify(kp.into()).is_ok()
+        }
+
+        fn prop_hash_canonical(d: Vec<u8>, idx: usize, byte: u8) -> bool {
+            let h = Hash::of_slice(d.as_slice());
+            let f = Fingerprint::new(h);
+            canonical(&h) && mutant_canonical(&h, idx, byte) && strict_bounds(&h)
+                && canonical(&f) && mutant_canonical(&f, idx, byte) && strict_bounds(&f)
+        }
+
+        fn prop_keys_canonical(seed: Vec<u8>, secp: bool, idx: usize, byte: u8) -> bool {
+            let kp = scheme(secp).generate(seed.as_slice());
+            let vk: ValidationKey = kp.into();
+            canonical(&kp) && mutant_canonical(&kp, idx, byte) && strict_bounds(&kp)
+                && canonical(&vk) && mutant_canonical(&vk, idx, byte) && strict_bounds(&vk)
+        }
+
+        fn prop_signed_canonical(seed: Vec<u8>, secp: bool, body: String, idx: usize, byte: u8) -> bool {
+            let st = Signed::new(scheme(secp).generate(seed.as_slice()), body);
+            let sig = st.sig();
+            canonical(&sig) && mutant_canonical(&sig, idx, byte) && strict_bounds(&sig)
+                && canonical(&st) && mutant_canonical(&st, idx, byte) && strict_bounds(&st)
+        }
+
+        fn prop_raw_keys_canonical(d: Vec<u8>) -> bool {
+            let vk = ValidationKey::Ed25519(to_array_32(d.as_slice()));
+            let sig = Signature::Ed25519(to_array_64(d.as_slice()), Fingerprint::new(Hash::of_slice(d.as_slice())));
+            let vk2 = ValidationKey::Secp256k1(to_array_33(d.as_slice()));
+            let sig2 = Signature::Secp256k1(to_array_64(d.as_slice()), vk2.into());
+            canonical(&vk) && canonical(&sig) && canonical(&vk2) && canonical(&sig2)
+        }
+
+    }
+
+    fn scheme(secp: bool) -> Scheme {
+        match secp {
+            true => Scheme::Secp256k1,
+            false => Scheme::Ed25519
+        }
+    }
+
+    fn to_array_33(v: &[u8]) -> [u8; 33] {
+        let mut a = [0; 33];
+        a[..32].copy_from_slice(&to_array_32(v));
+        a
+    }
+
+    #[test]
+    fn ck_secp256k1_keys() {
+
+        // A secret of 1 makes the public key the generator point.
+        let mut seed = [0; 32];
+        seed[31] = 1;
+        let kp = Scheme::Secp256k1.generate(&seed);
+        let vk: ValidationKey = kp.into();
+        assert_eq!(vk.to_blob()[1..].to_vec(), vec![
+            0x02, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+            0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98
+        ]);
+
+        // Zero isn't a valid secret, so that gets rehashed into one that is.
+        let kz = Scheme::Secp256k1.generate(&[0; 32]);
+        assert!(Signed::new(kz, String::from("hi")).verify(kz.into()).is_ok());
+
+        assert_eq!(kp.to_blob()[0], 0x01);
+        assert_eq!(kp.to_blob().len(), 1 + 32 + 33);
+        assert_eq!(vk.to_blob().len(), 1 + 33);
+
+        // Secrets that are out of range and public keys that don't go with the secret don't decode.
+        let bad = |kp: Keypair| Keypair::from_slice_exact(kp.to_blob().as_slice()).map_err(|e| e.kind());
+        let other = Scheme::Secp256k1.generate(&[9; 32]);
+        let ed = Scheme::Ed25519.generate(&[9; 32]);
+        let (kk, kpub) = match kp { Keypair::Secp256k1(k, p) => (k, p), _ => unreachable!() };
+        let (ok, opub) = match other { Keypair::Secp256k1(k, p) => (k, p), _ => unreachable!() };
+        let (ek, epub) = match ed { Keypair::Ed25519(k, p) => (k, p), _ => unreachable!() };
+        assert_eq!(bad(kp), Ok(kp));
+        assert_eq!(bad(Keypair::Secp256k1([0; 32], kpub)), Err(DecodeErrorKind::InvalidValue));
+        assert_eq!(bad(Keypair::Secp256k1([0xff; 32], kpub)), Err(DecodeErrorKind::InvalidValue));
+        assert_eq!(bad(Keypair::Secp256k1(kk, opub)), Err(DecodeErrorKind::InvalidValue));
+        assert_eq!(bad(Keypair::Secp256k1(ok, kpub)), Err(DecodeErrorKind::InvalidValue));
+        assert_eq!(bad(ed), Ok(ed));
+        let mut ek2 = ek;
+        ek2[40] ^= 1;
+        assert_eq!(bad(Keypair::Ed25519(ek2, epub)), Err(DecodeErrorKind::InvalidValue));
+        assert_eq!(bad(Keypair::Ed25519(ek, [1; 32])), Err(DecodeErrorKind::InvalidValue));
+
+    }
+
+    #[test]
+    fn ck_mixed_schemes() {
+
+        let ed = Scheme::Ed25519.generate(&[5; 32]);
+        let ec = Scheme::Secp256k1.generate(&[5; 32]);
+        let edk: ValidationKey = ed.into();
+        let eck: ValidationKey = ec.into();
+
+        let st = Signed::new(ec, String::from("hello"));
+        assert_eq!(st.sig().scheme(), Scheme::Secp256k1);
+        assert_eq!(st.sig().to_blob()[0], 0x01);
+        assert_eq!(st.verify(eck), Ok(()));
+        assert_eq!(st.verify(edk), Err(SigVerificationError::FingerprintMismatch));
+
+        // Same signature bytes claimed under the other scheme.
+        let sd = match st.sig() { Signature::Secp256k1(sd, _) => sd, _ => unreachable!() };
+        assert_eq!(verify(Signature::Ed25519(sd, eck.into()), eck, b"x"), Err(SigVerificationError::SignatureSchemeMismatch));
+
+        // High-S version of a good signature is rejected.
+        let s = ecdsa::Signature::from_slice(&sd).unwrap();
+        let (r, s) = s.split_scalars();
+        let high = ecdsa::Signature::from_scalars(r, -*s).unwrap();
+        let mut hd = [0; 64];
+        hd.copy_from_slice(high.to_bytes().as_slice());
+        let msg = signing_hash(&String::from("hello")).into_array();
+        assert_eq!(verify(Signature::Secp256k1(sd, eck.into()), eck, &msg), Ok(()));
+        assert_eq!(verify(Signature::Secp256k1(hd, eck.into()), eck, &msg), Err(SigVerificationError::KeyMismatch));
+
+        for s in &["ed25519", "secp256k1"] {
+            assert_eq!(Scheme::from_name(s).unwrap().name(), *s);
+        }
+
+    }
+
+    #[test]
+    fn ck_verify_batch_mixed() {
+
+        let kps: Vec<_> = (0..12u8).map(|i| scheme(i % 3 == 0).generate(&[i; 32])).collect();
+        let msg = Hash::of_slice(b"msg").into_array();
+        let sigs: Vec<_> = kps.iter().map(|kp| kp.sign(Hash::new(msg))).collect();
+
+        let mut items: Vec<_> = (0..12).map(|i| (sigs[i], kps[i].into(), &msg[..])).collect();
+        assert_eq!(verify_batch(items.as_slice()), Ok(()));
+
+        items[3].1 = kps[6].into();
+        items[4].1 = kps[5].into();
+        assert_eq!(verify_batch(items.as_slice()), Err(vec![
+            (3, SigVerificationError::KeyMismatch),
+            (4, SigVerificationError::KeyMismatch)
+        ]));
+
+    }
+
+    #[test]
+    fn ck_hash_short_read() {
+        let err = Hash::from_slice(&[1; SHA256_WIDTH - 1]).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::Truncated);
+        assert_eq!(err.path(), &["Hash"]);
+    }
+
+    #[test]
+    fn ck_verify_matrix() {
+
+        use self::SigVerificationError::*;
+
+        let kp = keypair(&[1, 2, 3]);
+        let other = keypair(&[4, 5, 6]);
+        let vk: ValidationKey = kp.into();
+        let ovk: ValidationKey = other.into();
+
+        let st = Signed::new(kp, String::from("hello"));
+        assert_eq!(st.verify(vk), Ok(()));
+        assert_eq!(verify_signed(&st, vk), Ok(()));
+        assert_eq!(st.verify(ovk), Err(FingerprintMismatch));
+
+        // Same signature, different body.
+        let tampered = Signed { signature: st.sig(), body: String::from("hellp") };
+        assert_eq!(tampered.verify(vk), Err(KeyMismatch));
+
+        // Real signature, but claiming to be from someone else.
+        let sd = match st.sig() { Signature::Ed25519(sd, _) => sd, _ => unreachable!() };
+        let liar = Signed { signature: Signature::Ed25519(sd, ovk.into()), body: String::from("hello") };
+        assert_eq!(liar.verify(vk), Err(FingerprintMismatch));
+        assert_eq!(liar.verify(ovk), Err(KeyMismatch));
+
+        // Someone else's signature over the same body.
+        let forged = Signed { signature: Signed::new(other, String::from("hello")).sig(), body: String::from("hello") };
+        assert_eq!(forged.verify(vk), Err(FingerprintMismatch));
+        assert_eq!(forged.verify(ovk), Ok(()));
+
+    }
+
+    /// A string, except signatures over it are in their own domain.
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    struct Tagged(String);
+
+    impl BinaryComponent for Tagged {
+
+        fn signing_domain() -> &'static str {
+            "test/tagged/v1"
+        }
+
+        fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+            Ok(Tagged(String::decode(read)?))
+        }
+
+        fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+            self.0.to_writer(write)
+        }
+
+    }
+
+    #[test]
+    fn ck_signing_domains() {
+
+        let kp = keypair(&[1, 2, 3]);
+        let vk: ValidationKey = kp.into();
+
+        // Same bytes, but the signature doesn't carry over to the other type.
+        let st = Signed::new(kp, Tagged(String::from("hello")));
+        assert_eq!(st.body.to_blob(), String::from("hello").to_blob());
+        let replay = Signed { signature: st.sig(), body: String::from("hello") };
+        assert_eq!(st.verify(vk), Ok(()));
+        assert_eq!(replay.verify(vk), Err(SigVerificationError::KeyMismatch));
+        assert_eq!(replay.verify_compat(vk, SigCompat::AllowLegacy), Err(SigVerificationError::KeyMismatch));
+
+    }
+
+    #[test]
+    fn ck_legacy_signatures() {
+
+        let kp = keypair(&[1, 2, 3]);
+        let vk: ValidationKey = kp.into();
+        let body = Tagged(String::from("hello"));
+
+        // What `Signed::new` used to do.
+        let old = Signed { signature: kp.sign(body.get_hash()), body };
+        assert_eq!(old.verify(vk), Err(SigVerificationError::KeyMismatch));
+        assert_eq!(old.verify_compat(vk, SigCompat::AllowLegacy), Ok(()));
+
+        let mut keys = HashMap::new();
+        keys.insert(vk.into(), vk);
+        assert_eq!(old.verify_with_resolver(&keys), Err(SigVerificationError::KeyMismatch));
+        assert_eq!(old.verify_with_resolver_compat(&keys, SigCompat::AllowLegacy), Ok(()));
+
+    }
+
+    #[test]
+    fn ck_signing_networks() {
+
+        let kp = keypair(&[1, 2, 3]);
+        let vk: ValidationKey = kp.into();
+        let mut keys = HashMap::new();
+        keys.insert(vk.into(), vk);
+
+        // Signatures for one network aren't any good on another, or off of any network.
+        let st = Signed::new_on(kp, 1, String::from("hello"));
+        assert_eq!(st.verify_on(vk, 1), Ok(()));
+        assert_eq!(st.verify_with_resolver_on(&keys, 1), Ok(()));
+        assert_eq!(st.verify_on(vk, 2), Err(SigVerificationError::KeyMismatch));
+        assert_eq!(st.verify(vk), Err(SigVerificationError::KeyMismatch));
+        assert_eq!(st.verify_compat(vk, SigCompat::AllowLegacy), Err(SigVerificationError::KeyMismatch));
+
+        // Not being on a network is the same as before networks.
+        let plain = Signed::new(kp, String::from("hello"));
+        assert_eq!(plain.verify_on(vk, NO_NETWORK), Ok(()));
+        assert_eq!(plain.verify_on(vk, 1), Err(SigVerificationError::KeyMismatch));
+
+        let sts: Vec<_> = (0..5).map(|_| st.clone()).collect();
+        assert_eq!(verify_signed_batch_on(sts.as_slice(), &keys, 1), Ok(()));
+        assert!(verify_signed_batch_on(sts.as_slice(), &keys, 2).is_err());
+
+        let (kps, policy, keys) = multisig_setup();
+        let mut ms = MultiSigned::new(policy, String::from("ban spammer"));
+        ms.sign_on(kps[0], 1).unwrap();
+        ms.sign_on(kps[1], 1).unwrap();
+        assert_eq!(ms.verify_with_resolver_on(&keys, 1), Ok(()));
+        assert_eq!(ms.verify_with_resolver_on(&keys, 2), Err(SigVerificationError::KeyMismatch));
+        assert_eq!(ms.verify_with_resolver(&keys), Err(SigVerificationError::KeyMismatch));
+
+    }
+
+    #[test]
+    fn ck_verify_batch() {
+
+        let kps: Vec<_> = (0..10u8).map(|i| keypair(&[i; 32])).collect();
+        let msgs: Vec<_> = (0..10u8).map(|i| Hash::of_slice(&[i]).into_array()).collect();
+        let sigs: Vec<_> = kps.iter().zip(msgs.iter()).map(|(kp, m)| kp.sign(Hash::new(*m))).collect();
+
+        let mut items: Vec<_> = (0..10).map(|i| (sigs[i], kps[i].into(), &msgs[i][..])).collect();
+        assert_eq!(verify_batch(items.as_slice()), Ok(()));
+        assert_eq!(verify_batch(&items[..2]), Ok(()));
+        assert_eq!(verify_batch(&[]), Ok(()));
+
+        // Wrong message and wrong key.
+        items[3].2 = &msgs[4][..];
+        items[7].1 = kps[8].into();
+        let expected = vec![(3, SigVerificationError::KeyMismatch), (7, SigVerificationError::KeyMismatch)];
+        assert_eq!(verify_batch(items.as_slice()), Err(expected));
+        assert_eq!(verify_batch(&items[2..5]), Err(vec![(1, SigVerificationError::KeyMismatch)]));
+
+        // A key that isn't even a valid point shouldn't break anything.
+        items[5].1 = ValidationKey::Ed25519([0xff; 32]);
+        assert_eq!(verify_batch(items.as_slice()).unwrap_err().len(), 3);
+
+    }
+
+    /// Signs with a nonce that has a small-order point added to it, so the signature only passes
+    /// checks that multiply that part away.
+    fn torsion_sign(seed: &[u8; 32], msg: &[u8]) -> (Signature, ValidationKey) {
+
+        use crypto::digest::Digest;
+        use curve25519_dalek::constants::{ED25519_BASEPOINT_POINT, EIGHT_TORSION};
+        use curve25519_dalek::scalar::Scalar;
+
+        let sk = ed25519_dalek::SigningKey::from_bytes(seed);
+        let pk = sk.verifying_key().to_bytes();
+        let r = Scalar::from_bytes_mod_order([7; 32]);
+        let nonce = (ED25519_BASEPOINT_POINT * r + EIGHT_TORSION[1]).compress().to_bytes();
+
+        let mut h = sha2::Sha512::new();
+        h.input(&nonce);
+        h.input(&pk);
+        h.input(msg);
+        let mut k = [0; 64];
+        h.result(&mut k);
+
+        let mut sd = [0; 64];
+        sd[..32].copy_from_slice(&nonce);
+        sd[32..].copy_from_slice((r + Scalar::from_bytes_mod_order_wide(&k) * sk.to_scalar()).as_bytes());
+        let vk = ValidationKey::Ed25519(pk);
+        (Signature::Ed25519(sd, vk.into()), vk)
+
+    }
+
+    #[test]
+    fn ck_small_order_signatures() {
+
+        let msg = Hash::of_slice(b"msg").into_array();
+
+        // The identity point as the key and the nonce with a zero scalar works for any message.
+        let mut id = [0; 32];
+        id[0] = 1;
+        let mut sd = [0; 64];
+        sd[..32].copy_from_slice(&id);
+        let weak = ValidationKey::Ed25519(id);
+        let forged = (Signature::Ed25519(sd, weak.into()), weak, &msg[..]);
+
+        let (sig, vk) = torsion_sign(&[3; 32], &msg);
+        let mixed = (sig, vk, &msg[..]);
+
+        // The batch equation takes the first one, and the second one whenever the batch's random
+        // coefficient happens to wipe out the small-order part.
+        assert!(batch_ok(&[forged]));
+        assert_eq!(verify(forged.0, forged.1, &msg), Err(SigVerificationError::KeyMismatch));
+        assert_eq!(verify(mixed.0, mixed.1, &msg), Err(SigVerificationError::KeyMismatch));
+
+        // But they're still caught when they're big enough to go in one.
+        let kps: Vec<_> = (0..6u8).map(|i| keypair(&[i; 32])).collect();
+        let mut items: Vec<_> = kps.iter().map(|kp| (kp.sign(Hash::new(msg)), (*kp).into(), &msg[..])).collect();
+        assert_eq!(verify_batch(items.as_slice()), Ok(()));
+        items[2] = forged;
+        items[5] = mixed;
+        assert_eq!(verify_batch(items.as_slice()), Err(vec![
+            (2, SigVerificationError::KeyMismatch),
+            (5, SigVerificationError::KeyMismatch)
+        ]));
+
+    }
+
+    #[test]
+    fn ck_verify_signed_batch() {
+
+        let kps: Vec<_> = (0..8u8).map(|i| keypair(&[i; 32])).collect();
+        let mut sts: Vec<_> = kps.iter().map(|kp| Signed::new(*kp, Tagged(String::from("seg")))).collect();
+
+        let mut keys = HashMap::new();
+        for kp in &kps[1..] {
+            let vk: ValidationKey = (*kp).into();
+            keys.insert(vk.into(), vk);
+        }
+
+        sts[5].body = Tagged(String::from("tampered"));
+        assert_eq!(verify_signed_batch(sts.as_slice(), &keys), Err(vec![
+            (0, SigVerificationError::UnknownKey),
+            (5, SigVerificationError::KeyMismatch)
+        ]));
+
+        assert_eq!(verify_signed_batch(&sts[1..5], &keys), Ok(()));
+
+    }
+
+    #[test]
+    fn ck_verify_with_resolver() {
+
+        let kp = keypair(&[1, 2, 3]);
+        let vk: ValidationKey = kp.into();
+        let st = Signed::new(kp, String::from("hello"));
+
+        let mut keys = HashMap::new();
+        assert_eq!(st.verify_with_resolver(&keys), Err(SigVerificationError::UnknownKey));
+
+        keys.insert(vk.into(), vk);
+        assert_eq!(st.verify_with_resolver(&keys), Ok(()));
+
+        // A resolver that hands back the wrong key for the fingerprint doesn't fool us.
+        let ovk: ValidationKey = keypair(&[4, 5, 6]).into();
+        keys.insert(vk.into(), ovk);
+        assert_eq!(st.verify_with_resolver(&keys), Err(SigVerificationError::FingerprintMismatch));
+
+    }
+
+    fn multisig_setup() -> (Vec<Keypair>, SigPolicy, HashMap<Fingerprint, ValidationKey>) {
+        let kps: Vec<Keypair> = (0..3u8).map(|i| scheme(i == 1).generate(&[i; 32])).collect();
+        let mut keys = HashMap::new();
+        for kp in &kps {
+            let vk: ValidationKey = (*kp).into();
+            keys.insert(vk.into(), vk);
+        }
+        let policy = SigPolicy::new(2, kps.iter().map(|kp| (*kp).into()).collect()).unwrap();
+        (kps, policy, keys)
+    }
+
+    #[test]
+    fn ck_sig_policy() {
+
+        let fps: Vec<Fingerprint> = (0..3u8).map(|i| keypair(&[i]).into()).collect();
+        let p = SigPolicy::new(2, vec![fps[2], fps[0], fps[1], fps[0]]).unwrap();
+        assert_eq!(p.signers().len(), 3);
+        assert!(p.signers().windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(SigPolicy::from_slice_exact(p.to_blob().as_slice()).unwrap(), p);
+
+        assert_eq!(SigPolicy::new(0, fps.clone()), None);
+        assert_eq!(SigPolicy::new(4, fps.clone()), None);
+        let many: Vec<Fingerprint> = (0..MAX_POLICY_SIGNERS as u32 + 1).map(|i| Fingerprint::new(Hash::of_slice(&i.to_be_bytes()))).collect();
+        assert_eq!(SigPolicy::new(1, many), None);
+
+        // Unsorted or zero thresholds don't decode.
+        let mut b = p.to_blob();
+        b[2..2 + SHA256_WIDTH].copy_from_slice(&p.to_blob()[2 + SHA256_WIDTH..2 + 2 * SHA256_WIDTH]);
+        assert_eq!(SigPolicy::from_slice_exact(b.as_slice()).unwrap_err().kind(), DecodeErrorKind::InvalidValue);
+        let mut b = p.to_blob();
+        b[0] = 0;
+        assert_eq!(SigPolicy::from_slice_exact(b.as_slice()).unwrap_err().kind(), DecodeErrorKind::InvalidValue);
+
+    }
+
+    #[test]
+    fn ck_multisigned() {
+
+        let (kps, policy, keys) = multisig_setup();
+        let mut ms = MultiSigned::new(policy.clone(), String::from("ban spammer"));
+
+        assert_eq!(ms.verify_with_resolver(&keys), Err(SigVerificationError::ThresholdNotMet));
+        ms.sign(kps[2]).unwrap();
+        assert_eq!(ms.verify_with_resolver(&keys), Err(SigVerificationError::ThresholdNotMet));
+        ms.sign(kps[1]).unwrap();
+        ms.sign(kps[1]).unwrap(); // Doesn't count twice.
+        assert_eq!(ms.sigs().len(), 2);
+        assert_eq!(ms.verify_with_resolver(&keys), Ok(()));
+
+        let dec = MultiSigned::<String>::from_slice_exact(ms.to_blob().as_slice()).unwrap();
+        assert_eq!(dec, ms);
+        assert_eq!(dec.verify_with_resolver(&keys), Ok(()));
+
+        // Outsiders can't sign.
+        assert_eq!(ms.sign(keypair(&[9])), Err(SigVerificationError::NotInPolicy));
+
+        // Signatures don't carry over to a different body or a weaker policy.
+        let mut other = MultiSigned::new(policy.clone(), String::from("ban someone else"));
+        for sig in ms.sigs() {
+            other.add_signature(*sig).unwrap();
+        }
+        assert_eq!(other.verify_with_resolver(&keys), Err(SigVerificationError::KeyMismatch));
+
+        let weak = SigPolicy::new(1, policy.signers().to_vec()).unwrap();
+        let mut other = MultiSigned::new(weak, ms.extract_owned());
+        other.add_signature(ms.sigs()[0]).unwrap();
+        assert_eq!(other.verify_with_resolver(&keys), Err(SigVerificationError::KeyMismatch));
+
+        // A single signer's key being unknown fails the whole thing.
+        let mut fewer = keys.clone();
+        fewer.remove(&kps[1].into());
+        assert_eq!(ms.verify_with_resolver(&fewer), Err(SigVerificationError::UnknownKey));
+
+    }
+
+    #[test]
+    fn ck_multisigned_decode() {
+
+        let (kps, policy, _) = multisig_setup();
+        let mut ms = MultiSigned::new(policy.clone(), 7u8);
+        ms.sign(kps[0]).unwrap();
+        ms.sign(kps[2]).unwrap();
+
+        let plen = policy.to_blob().len();
+        let slen = ms.sigs()[0].to_blob().len();
+        let b = ms.to_blob();
+
+        // Swap the signatures so they're out of order.
+        let mut swapped = b[..plen + 1].to_vec();
+        swapped.extend_from_slice(&b[plen + 1 + slen..plen + 1 + 2 * slen]);
+        swapped.extend_from_slice(&b[plen + 1..plen + 1 + slen]);
+        swapped.extend_from_slice(&b[plen + 1 + 2 * slen..]);
+        assert_eq!(MultiSigned::<u8>::from_slice_exact(swapped.as_slice()).unwrap_err().kind(), DecodeErrorKind::InvalidValue);
+
+        // More signatures than there are keys.
+        let mut many = b.clone();
+        many[plen] = 4;
+        assert_eq!(MultiSigned::<u8>::from_slice_exact(many.as_slice()).unwrap_err().kind(), DecodeErrorKind::InvalidValue);
+
+        // From someone who isn't in the policy.
+        let mut outsider = b[..plen].to_vec();
+        outsider.push(1);
+        outsider.extend(Signed::new(keypair(&[9]), 7u8).sig().to_blob());
+        outsider.push(7);
+        assert_eq!(MultiSigned::<u8>::from_slice_exact(outsider.as_slice()).unwrap_err().kind(), DecodeErrorKind::InvalidValue);
+
+    }
+
+    #[test]
+    fn ck_signature_has_scheme() {
+        let sig = Signed::new(keypair(&[1, 2, 3]), String::from("hello")).sig();
+        let blob = sig.to_blob();
+        assert_eq!(blob[0], Scheme::Ed25519.to_specifier());
+        assert_eq!(blob.len(), 1 + 64 + SHA256_WIDTH);
+    }
 
 }
@@ -0,0 +1,62 @@
+//! Helpers for the canonical encoding property tests.
+
+use io::BinaryComponent;
+
+/// Checks that the component survives an encode/decode round trip, byte-for-byte.
+pub fn canonical<T: BinaryComponent + Eq>(t: &T) -> bool {
+    let b = t.to_blob();
+    match T::from_slice_exact(b.as_slice()) {
+        Ok(d) => d == *t && d.to_blob() == b,
+        Err(_) => false
+    }
+}
+
+/// Checks that if an arbitrary corruption of the encoding still decodes, then it re-encodes to
+/// exactly the corrupted bytes.  Otherwise two different blobs could have the same meaning.
+pub fn mutant_canonical<T: BinaryComponent>(t: &T, idx: usize, byte: u8) -> bool {
+    let mut b = t.to_blob();
+    if b.is_empty() {
+        return true;
+    }
+    let i = idx % b.len();
+    b[i] = byte;
+    match T::from_slice_exact(b.as_slice()) {
+        Ok(d) => d.to_blob() == b,
+        Err(_) => true
+    }
+}
+
+/// Checks that no truncated form of the encoding is accepted, and that trailing bytes aren't.
+pub fn strict_bounds<T: BinaryComponent>(t: &T) -> bool {
+    let mut b = t.to_blob();
+    for n in 0..b.len() {
+        if T::from_slice_exact(&b[..n]).is_ok() {
+            return false;
+        }
+    }
+    b.push(0);
+    T::from_slice_exact(b.as_slice()).is_err()
+}
+
+/// Lowercase hex of the encoding, for comparing against known wire formats.
+pub fn hex_blob<T: BinaryComponent>(t: &T) -> String {
+    t.to_blob().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Copies as much of the slice as fits into a fixed-size array, padding with zeros.
+pub fn to_array_32(v: &[u8]) -> [u8; 32] {
+    let mut a = [0; 32];
+    for (d, s) in a.iter_mut().zip(v) {
+        *d = *s;
+    }
+    a
+}
+
+/// Copies as much of the slice as fits into a fixed-size array, padding with zeros.
+pub fn to_array_64(v: &[u8]) -> [u8; 64] {
+    let mut a = [0; 64];
+    for (d, s) in a.iter_mut().zip(v) {
+        *d = *s;
+    }
+    a
+}
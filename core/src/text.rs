@@ -0,0 +1,271 @@
+//! Textual forms of hashes and the things built out of them.
+//!
+//! There's two of them.  The plain form is just the lowercase hex of the binary encoding, which is
+//! what `Display` writes.  The short form is a type prefix like `addr_` followed by the base58 of
+//! the encoding and a 4 byte checksum, so that a mistyped or truncated string or one that's meant
+//! to be a different kind of thing gets caught instead of silently pointing at the wrong object.
+//! `FromStr` accepts either.
+
+use std::error;
+use std::fmt;
+
+use sig::{Hash, SHA256_WIDTH};
+
+/// Width of the checksum appended to the hash in the short form.
+const CHECKSUM_WIDTH: usize = 4;
+
+/// Short form prefix for `Address`.
+pub const ADDRESS_PREFIX: &str = "addr_";
+
+/// Short form prefix for `Hash`.
+pub const HASH_PREFIX: &str = "hash_";
+
+/// Short form prefix for `Fingerprint`.
+pub const FINGERPRINT_PREFIX: &str = "fpr_";
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Why some text couldn't be parsed.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ParseError {
+
+    /// Hex form was the wrong number of digits.
+    BadLength(usize),
+
+    /// Hit a character that isn't in the alphabet, at the given character index.
+    BadChar(char, usize),
+
+    /// Short form had some other type's prefix, or none at all.
+    WrongPrefix { expected: &'static str },
+
+    /// Short form decoded to the wrong number of bytes.
+    BadPayload,
+
+    /// Short form's checksum didn't match, so it's probably got a typo in it.
+    BadChecksum
+
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::BadLength(n) => write!(f, "hex is the wrong length ({} digits)", n),
+            ParseError::BadChar(c, i) => write!(f, "invalid character {:?} at position {}", c, i),
+            ParseError::WrongPrefix { expected } => write!(f, "expected hex or a string starting with \"{}\"", expected),
+            ParseError::BadPayload => write!(f, "encoded data is the wrong length"),
+            ParseError::BadChecksum => write!(f, "checksum mismatch")
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// Writes the lowercase hex of the bytes.
+pub fn write_hex(f: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
+    for b in data {
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+/// Parses a nonempty, even number of hex digits, in either case.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, ParseError> {
+
+    let n = s.chars().count();
+    if n == 0 || !n.is_multiple_of(2) {
+        return Err(ParseError::BadLength(n));
+    }
+
+    let mut out = vec![0; n / 2];
+    for (i, c) in s.chars().enumerate() {
+        let v = match c.to_digit(16) {
+            Some(v) => v as u8,
+            None => return Err(ParseError::BadChar(c, i))
+        };
+        out[i / 2] = (out[i / 2] << 4) | v;
+    }
+
+    Ok(out)
+
+}
+
+/// Returns the base58 (Bitcoin alphabet) encoding of the bytes.
+pub fn to_base58(data: &[u8]) -> String {
+
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+
+    // Little-endian base-58 digits of the number, built up one input byte at a time.
+    let mut digits: Vec<u8> = Vec::new();
+    for b in &data[zeros..] {
+        let mut carry = *b as u32;
+        for d in digits.iter_mut() {
+            carry += (*d as u32) << 8;
+            *d = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut s = String::with_capacity(zeros + digits.len());
+    for _ in 0..zeros {
+        s.push('1');
+    }
+    for d in digits.iter().rev() {
+        s.push(BASE58_ALPHABET[*d as usize] as char);
+    }
+
+    s
+
+}
+
+/// Decodes base58 (Bitcoin alphabet).
+pub fn from_base58(s: &str) -> Result<Vec<u8>, ParseError> {
+
+    let zeros = s.chars().take_while(|c| *c == '1').count();
+
+    // Little-endian bytes of the number.
+    let mut bytes: Vec<u8> = Vec::new();
+    for (i, c) in s.chars().enumerate().skip(zeros) {
+        let mut carry = match BASE58_ALPHABET.iter().position(|a| *a as char == c) {
+            Some(v) => v as u32,
+            None => return Err(ParseError::BadChar(c, i))
+        };
+        for b in bytes.iter_mut() {
+            carry += (*b as u32) * 58;
+            *b = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut out = vec![0; zeros];
+    out.extend(bytes.iter().rev());
+    Ok(out)
+
+}
+
+fn checksum(prefix: &str, data: &[u8]) -> [u8; CHECKSUM_WIDTH] {
+    let mut buf = prefix.as_bytes().to_vec();
+    buf.extend_from_slice(data);
+    let sum = Hash::of_slice(buf.as_slice()).into_array();
+    [sum[0], sum[1], sum[2], sum[3]]
+}
+
+/// Returns the short form of the data with the given type prefix.
+pub fn to_short(prefix: &str, data: &[u8]) -> String {
+    let mut buf = data.to_vec();
+    buf.extend_from_slice(&checksum(prefix, data));
+    format!("{}{}", prefix, to_base58(buf.as_slice()))
+}
+
+/// Parses either the hex form or the short form with the given type prefix.
+pub fn parse(prefix: &'static str, s: &str) -> Result<Vec<u8>, ParseError> {
+
+    if !s.starts_with(prefix) {
+        // Tell them about the prefix if it looks like they were going for the short form.
+        return match s.find('_') {
+            Some(_) => Err(ParseError::WrongPrefix { expected: prefix }),
+            None => parse_hex(s)
+        };
+    }
+
+    let body = &s[prefix.len()..];
+    let mut buf = from_base58(body).map_err(|e| match e {
+        ParseError::BadChar(c, i) => ParseError::BadChar(c, i + prefix.len()),
+        e => e
+    })?;
+
+    if buf.len() < CHECKSUM_WIDTH {
+        return Err(ParseError::BadPayload);
+    }
+
+    let sum = buf.split_off(buf.len() - CHECKSUM_WIDTH);
+    if sum != checksum(prefix, buf.as_slice()) {
+        return Err(ParseError::BadChecksum);
+    }
+
+    Ok(buf)
+
+}
+
+/// Parses a bare hash, in either form.
+pub fn parse_hash(prefix: &'static str, s: &str) -> Result<Hash, ParseError> {
+
+    let short = s.starts_with(prefix);
+    let buf = parse(prefix, s)?;
+
+    if buf.len() != SHA256_WIDTH {
+        return Err(match short {
+            true => ParseError::BadPayload,
+            false => ParseError::BadLength(s.chars().count())
+        });
+    }
+
+    let mut d = [0; SHA256_WIDTH];
+    d.copy_from_slice(buf.as_slice());
+    Ok(Hash::new(d))
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn ck_base58_vectors() {
+        assert_eq!(to_base58(b""), "");
+        assert_eq!(to_base58(&[0, 0, 1]), "112");
+        assert_eq!(to_base58(b"Hello World!"), "2NEpo7TZRRrLZSi2U");
+        assert_eq!(from_base58("2NEpo7TZRRrLZSi2U").unwrap(), b"Hello World!".to_vec());
+        assert_eq!(from_base58("112").unwrap(), vec![0, 0, 1]);
+        assert_eq!(from_base58("10"), Err(ParseError::BadChar('0', 1)));
+    }
+
+    #[test]
+    fn ck_short_errors() {
+
+        let h = Hash::of_slice(b"foo");
+        let s = to_short("hash_", &h.into_array());
+        assert_eq!(parse_hash("hash_", s.as_str()), Ok(h));
+
+        assert_eq!(parse_hash("addr_", s.as_str()), Err(ParseError::WrongPrefix { expected: "addr_" }));
+        assert_eq!(parse_hash("hash_", to_short("hash_", &[1, 2, 3]).as_str()), Err(ParseError::BadPayload));
+        assert!(parse_hash("hash_", &s[..s.len() - 1]).is_err());
+
+        // Swap two adjacent characters, like a typo would.
+        let mut b = s.clone().into_bytes();
+        let n = b.len();
+        b.swap(n - 2, n - 3);
+        if b != s.as_bytes() {
+            let typo = String::from_utf8(b).unwrap();
+            assert!(parse_hash("hash_", typo.as_str()).is_err());
+        }
+
+    }
+
+    #[test]
+    fn ck_hex_errors() {
+        assert_eq!(parse_hash("hash_", "abcd"), Err(ParseError::BadLength(4)));
+        assert_eq!(parse("hash_", "abc"), Err(ParseError::BadLength(3)));
+        let mut s = "0".repeat(63);
+        s.push('g');
+        assert_eq!(parse_hash("hash_", s.as_str()), Err(ParseError::BadChar('g', 63)));
+        assert_eq!(parse_hash("hash_", "F".repeat(64).as_str()), Ok(Hash::new([0xff; SHA256_WIDTH])));
+    }
+
+    quickcheck! {
+
+        fn prop_base58_roundtrip(d: Vec<u8>) -> bool {
+            from_base58(to_base58(d.as_slice()).as_str()).unwrap() == d
+        }
+
+    }
+
+}
@@ -0,0 +1,68 @@
+//! Compares checking the segment signatures in a block one at a time against doing it in a batch.
+//!
+//! Run with `cargo bench -p jiyunet-dag`.
+
+#[macro_use] extern crate criterion;
+extern crate jiyunet_core as core;
+extern crate jiyunet_dag as dag;
+
+use std::collections::HashMap;
+
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+use core::sig::{Fingerprint, Hash, Scheme, Signed, ValidationKey};
+use dag::artifact::ArtifactData;
+use dag::block::{Block, BlockHeader};
+use dag::segment::{Segment, SegmentContent, SignedSegment};
+
+fn make_block(n: usize, keys: &mut HashMap<Fingerprint, ValidationKey>) -> Block {
+
+    // A few different signers, like a real block would have.
+    let kps: Vec<_> = (0..16u8).map(|i| Scheme::Ed25519.generate(&[i; 32])).collect();
+    for kp in &kps {
+        let vk: ValidationKey = (*kp).into();
+        keys.insert(vk.into(), vk);
+    }
+
+    let segs = (0..n).map(|i| {
+        let ad = ArtifactData::new(0, (i as u64).to_be_bytes().to_vec());
+        let seg = Segment::new(SegmentContent::Artifact(ad), i as i64);
+        SignedSegment::Single(Signed::new(kps[i % kps.len()], seg))
+    }).collect();
+
+    let head = Signed::new(kps[0], BlockHeader::new(1, 0, 0, Hash::of_slice(&[]), vec![]));
+    Block::new(head, segs)
+
+}
+
+fn bench_verify(c: &mut Criterion) {
+
+    let mut group = c.benchmark_group("verify_segments");
+    group.sample_size(10);
+
+    for n in [100, 1000, 4000] {
+
+        let mut keys = HashMap::new();
+        let block = make_block(n, &mut keys);
+        group.throughput(Throughput::Elements(n as u64));
+
+        group.bench_with_input(BenchmarkId::new("individual", n), &block, |b, block| {
+            b.iter(|| {
+                for s in block.get_segments() {
+                    s.verify_with_resolver(&keys).unwrap();
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("batch", n), &block, |b, block| {
+            b.iter(|| block.verify_segments(&keys).unwrap())
+        });
+
+    }
+
+    group.finish();
+
+}
+
+criterion_group!(benches, bench_verify);
+criterion_main!(benches);
@@ -1,12 +1,11 @@
-use byteorder::*; // FIXME
-
 use core::Address;
-use core::io::{BinaryComponent, DecodeError, WrResult};
-use core::sig::{Hash, Signed};
+use core::io::DecodeLimits;
+use core::sig::{self, Hash, KeyResolver, SigVerificationError, Signed};
 
+use merkle;
 use segment::*;
 
-use DagNode;
+use {DagNode, SignedBlock};
 
 /// Block headers are lightweight peices of information that, when signed, can be passed around
 /// easily for coordinating validation between nodes, and for caching actual block data in-memory.
@@ -14,7 +13,9 @@ use DagNode;
 /// As of writing, they consume approximately 140 bytes in-memory once parsed, and less than that
 /// when serialized.  This isn't counting signature data, which is another ~128 bytes, but that can
 /// be discarded once the block is confirmed locally.
-#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+#[derive(Clone, Eq, PartialEq, Debug, Hash, BinaryComponent)]
+#[decode_limits = "BLOCK_HEADER_DECODE_LIMITS"]
+#[signing_domain = "jiyunet/block-header/v1"]
 pub struct BlockHeader {
 
     /// Version identifier.  TODO Formalize this.
@@ -36,84 +37,120 @@ pub struct BlockHeader {
 
 impl BlockHeader {
 
-    pub fn parents(&self) -> Vec<Address> {
-        self.parents.clone()
+    pub fn new(version: u32, timestamp: i64, block_height: u64, segments_merkle_root: Hash, parents: Vec<Address>) -> BlockHeader {
+        BlockHeader {
+            version,
+            timestamp,
+            block_height,
+            segments_merkle_root,
+            parents
+        }
     }
 
-}
-
-impl BinaryComponent for BlockHeader {
-
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-
-        let ver = read.read_u32::<BigEndian>()?;
-        let ts = read.read_i64::<BigEndian>()?;
-        let bh = read.read_u64::<BigEndian>()?;
-        let smr = Hash::from_reader(read)?;
-        let pars = Vec::<Address>::from_reader(read)?;
-
-        Ok(BlockHeader {
-            version: ver,
-            timestamp: ts,
-            block_height: bh,
-            segments_merkle_root: smr,
-            parents: pars
-        })
-
+    /// Millisecond UNIX time the block was made at.
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
     }
 
-    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-
-        write.write_u32::<BigEndian>(self.version)?;
-        write.write_i64::<BigEndian>(self.timestamp)?;
-        write.write_u64::<BigEndian>(self.block_height)?;
-        self.segments_merkle_root.to_writer(write)?;
-        self.parents.to_writer(write)?;
+    pub fn block_height(&self) -> u64 {
+        self.block_height
+    }
 
-        Ok(())
+    pub fn parents(&self) -> Vec<Address> {
+        self.parents.clone()
+    }
 
+    pub fn segments_merkle_root(&self) -> Hash {
+        self.segments_merkle_root
     }
 
 }
 
+/// Limits for decoding a whole block.  Blocks can have a lot of segments in them.
+pub const BLOCK_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(1 << 23, 1 << 16, 16);
+
+/// Limits for decoding just a block header.  The only list is the parents.
+pub const BLOCK_HEADER_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(1 << 16, 1 << 10, 8);
+
 /// Main type of node on the dag.  Primary unit of time and validation.
 ///
 /// Blocks have a header including their parent information.  They also contain a set of segments
 /// that represent the data actually stored in the blocks.  Segments are for the actual mid-layer
 /// validation logic.
-#[derive(Clone, Eq, PartialEq, Debug)]
-pub struct Block(Signed<BlockHeader>, Vec<Signed<Segment>>);
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[decode_limits = "BLOCK_DECODE_LIMITS"]
+#[signing_domain = "jiyunet/block/v1"]
+pub struct Block(Signed<BlockHeader>, Vec<SignedSegment>);
 
 impl Block {
 
-    pub fn get_header(&self) -> &Signed<BlockHeader> {
-        &self.0
+    pub fn new(header: Signed<BlockHeader>, segments: Vec<SignedSegment>) -> Block {
+        Block(header, segments)
     }
 
-    pub fn get_segments(&self) -> &Vec<Signed<Segment>> {
-        &self.1
+    /// Checks the signatures on all of the segments, looking up the keys with the resolver.  The
+    /// singly-signed ones are done in one batch.  Returns the indexes of the bad segments, if
+    /// there are any.  This doesn't check the header's signature.
+    pub fn verify_segments<K: KeyResolver>(&self, kr: &K) -> Result<(), Vec<(usize, SigVerificationError)>> {
+        self.verify_segments_on(kr, sig::NO_NETWORK)
     }
 
-}
-
-impl BinaryComponent for Block {
-
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    /// Like `verify_segments`, but for segments signed for the network.
+    pub fn verify_segments_on<K: KeyResolver>(&self, kr: &K, network: u32) -> Result<(), Vec<(usize, SigVerificationError)>> {
+
+        let mut idxs = Vec::with_capacity(self.1.len());
+        let mut singles = Vec::with_capacity(self.1.len());
+        let mut bad = Vec::new();
+
+        for (i, ss) in self.1.iter().enumerate() {
+            match *ss {
+                SignedSegment::Single(ref s) | SignedSegment::Stamped(ref s, _) => {
+                    idxs.push(i);
+                    singles.push(s.clone());
+                },
+                SignedSegment::Multi(ref m) => {
+                    if let Err(e) = m.verify_with_resolver_on(kr, network) {
+                        bad.push((i, e));
+                    }
+                }
+            }
+        }
+
+        if let Err(es) = sig::verify_signed_batch_on(singles.as_slice(), kr, network) {
+            bad.extend(es.into_iter().map(|(j, e)| (idxs[j], e)));
+            bad.sort_by_key(|&(i, _)| i);
+        }
+
+        match bad.is_empty() {
+            true => Ok(()),
+            false => Err(bad)
+        }
 
-        let head = Signed::<BlockHeader>::from_reader(read)?;
-        let body = Vec::<Signed<Segment>>::from_reader(read)?;
-
-        Ok(Block(head, body))
+    }
 
+    /// Computes the merkle root of the segments actually in the block.
+    pub fn merkle_root(&self) -> Hash {
+        merkle::segments_root(self.1.as_slice())
     }
 
-    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
+    /// Checks that the header's merkle root is the one for the segments in the block.
+    pub fn check_merkle_root(&self) -> bool {
+        self.0.extract_owned().segments_merkle_root == self.merkle_root()
+    }
 
-        self.0.to_writer(write)?;
-        self.1.to_writer(write)?;
+    /// Makes a proof that the segment at the index is in the block, for someone that only has
+    /// the header.
+    pub fn prove_segment(&self, index: usize) -> Option<merkle::MerkleProof> {
+        let leaves: Vec<Hash> = self.1.iter().map(merkle::leaf_hash).collect();
+        merkle::MerkleProof::generate(leaves.as_slice(), index)
+    }
 
-        Ok(())
+    pub fn get_header(&self) -> &Signed<BlockHeader> {
+        &self.0
+    }
 
+    pub fn get_segments(&self) -> &Vec<SignedSegment> {
+        &self.1
     }
 
 }
@@ -130,15 +167,70 @@ impl DagNode for Block {
 
 }
 
+impl DagNode for SignedBlock {
+
+    fn version(&self) -> u32 {
+        self.extract_owned().version()
+    }
+
+    fn timestamp(&self) -> i64 {
+        self.extract_owned().timestamp()
+    }
+
+}
+
 #[cfg(test)]
 mod test {
 
     use std::io::Cursor;
 
-    use core::sig::Hash;
+    use quickcheck::{Arbitrary, Gen};
+
+    use core::io::BinaryComponent;
+    use core::sig::{Hash, MultiSigned, Scheme, SigPolicy};
+
+    use artifact::ArtifactData;
+    use identity::Revocation;
+    use testutil::*;
 
     use super::*;
 
+    impl Arbitrary for Arb<BlockHeader> {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let pars = (0..(usize::arbitrary(g) % 4)).map(|_| gen_address(g)).collect();
+            Arb(BlockHeader {
+                version: u32::arbitrary(g),
+                timestamp: i64::arbitrary(g),
+                block_height: u64::arbitrary(g),
+                segments_merkle_root: gen_hash(g),
+                parents: pars
+            })
+        }
+    }
+
+    impl Arbitrary for Arb<Signed<Block>> {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let Arb(head) = Arb::<BlockHeader>::arbitrary(g);
+            let segs = (0..(usize::arbitrary(g) % 4)).map(|_| Arb::<SignedSegment>::arbitrary(g).0).collect();
+            let block = Block(gen_signed(g, head), segs);
+            Arb(gen_signed(g, block))
+        }
+    }
+
+    quickcheck! {
+
+        fn prop_header_canonical(h: Arb<BlockHeader>, idx: usize, byte: u8) -> bool {
+            canonical(&h.0) && mutant_canonical(&h.0, idx, byte) && strict_bounds(&h.0)
+        }
+
+        fn prop_block_canonical(b: Arb<Signed<Block>>, idx: usize, byte: u8) -> bool {
+            let inner = b.0.extract_owned();
+            canonical(&inner) && mutant_canonical(&inner, idx, byte) && strict_bounds(&inner)
+                && canonical(&b.0) && mutant_canonical(&b.0, idx, byte) && strict_bounds(&b.0)
+        }
+
+    }
+
     fn encode_and_decode<T: BinaryComponent>(t: T) -> T {
 
         let mut c = Cursor::new(Vec::new());
@@ -149,6 +241,110 @@ mod test {
 
     }
 
+    #[test]
+    fn ck_block_wire_format() {
+
+        let head = BlockHeader {
+            version: 42,
+            timestamp: 1337,
+            block_height: 64,
+            segments_merkle_root: Hash::of_slice(&[1, 2, 3]),
+            parents: vec![Address::of_slice(&[5]), Address::of_slice(&[6])]
+        };
+
+        assert_eq!(hex_blob(&head), concat!(
+            "0000002a00000000000005390000000000000040039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d",
+            "84a1a2011cfb8100000000000000021220e77b9a9ae9e30b0dbdb6f510a264ef9de781501d7b6b92ae89eb059c",
+            "5ab743db122067586e98fad27da0b9968bc039a1ef34c939b9b8e523a8bef89d478608c5ecf6"));
+
+        let kp = Scheme::Ed25519.generate(&[9; 32]);
+        let seg = Segment::new(SegmentContent::Artifact(ArtifactData::new(42, vec![65, 66, 67, 68])), 19101004);
+        let block = Block(Signed::new(kp, head), vec![Signed::new(kp, seg).into()]);
+
+        assert_eq!(hex_blob(&block.get_hash()), "316eb6d29b6553f8f6afd31d32161fbb6baad65abc050b14decfb382f97e62f2");
+
+    }
+
+    #[test]
+    fn ck_verify_segments() {
+
+        use std::collections::HashMap;
+        use core::sig::ValidationKey;
+
+        let kps: Vec<_> = (0..3u8).map(|i| Scheme::Ed25519.generate(&[i; 32])).collect();
+        let mut keys = HashMap::new();
+        for kp in &kps[..2] {
+            let vk: ValidationKey = (*kp).into();
+            keys.insert(vk.into(), vk);
+        }
+
+        let mut segs: Vec<SignedSegment> = (0..20).map(|i| {
+            let seg = Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![i])), i as i64);
+            Signed::new(kps[i as usize % 2], seg).into()
+        }).collect();
+
+        // A revocation that both of the known keys have to sign.
+        let policy = SigPolicy::new(2, vec![kps[0].into(), kps[1].into()]).unwrap();
+        let rev = Signed::new(kps[0], Revocation::new(kps[2].into(), 1));
+        let mut ms = MultiSigned::new(policy, Segment::new(SegmentContent::Revocation(rev), 7));
+        ms.sign(kps[0]).unwrap();
+        segs.push(SignedSegment::Multi(ms.clone()));
+        ms.sign(kps[1]).unwrap();
+        segs[7] = SignedSegment::Multi(ms);
+
+        let head = Signed::new(kps[0], BlockHeader::new(1, 0, 0, Hash::of_slice(&[]), vec![]));
+        assert_eq!(Block::new(head.clone(), segs[..20].to_vec()).verify_segments(&keys), Ok(()));
+
+        // Someone we don't know about, and a signature moved onto a different segment.
+        segs[4] = Signed::new(kps[2], segs[4].segment()).into();
+        let mut moved = match segs[12] {
+            SignedSegment::Single(ref s) => s.sig().to_blob(),
+            _ => unreachable!()
+        };
+        moved.extend(segs[13].segment().to_blob());
+        segs[13] = Signed::<Segment>::from_slice_exact(moved.as_slice()).unwrap().into();
+
+        assert_eq!(Block::new(head.clone(), segs).verify_segments(&keys), Err(vec![
+            (4, SigVerificationError::UnknownKey),
+            (13, SigVerificationError::KeyMismatch),
+            (20, SigVerificationError::ThresholdNotMet)
+        ]));
+
+        // Segments signed for a network only check out on that network.
+        let segs: Vec<SignedSegment> = (0..5u8).map(|i| {
+            let seg = Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![i])), 0);
+            Signed::new_on(kps[0], 3, seg).into()
+        }).collect();
+        let block = Block::new(head, segs);
+        assert_eq!(block.verify_segments_on(&keys, 3), Ok(()));
+        assert_eq!(block.verify_segments_on(&keys, 2).unwrap_err().len(), 5);
+        assert_eq!(block.verify_segments(&keys).unwrap_err().len(), 5);
+
+    }
+
+    #[test]
+    fn ck_merkle_root() {
+
+        let kp = Scheme::Ed25519.generate(&[3; 32]);
+        let segs: Vec<SignedSegment> = (0..5u8).map(|i| {
+            Signed::new(kp, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![i])), 0)).into()
+        }).collect();
+
+        let root = merkle::segments_root(segs.as_slice());
+        let head = BlockHeader::new(1, 0, 0, root, vec![]);
+        let block = Block::new(Signed::new(kp, head.clone()), segs.clone());
+        assert!(block.check_merkle_root());
+
+        let p = block.prove_segment(3).unwrap();
+        assert!(p.verify_segment(&head, &segs[3]));
+        assert_eq!(block.prove_segment(5), None);
+
+        // Dropping a segment changes the root.
+        let bad = Block::new(Signed::new(kp, head), segs[..4].to_vec());
+        assert!(!bad.check_merkle_root());
+
+    }
+
     #[test]
     fn ck_blockheader_between_blob() {
 
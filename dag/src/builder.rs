@@ -0,0 +1,272 @@
+//! Putting together new blocks out of segments, and signing them.
+
+use std::error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use core::Address;
+use core::io::BinaryComponent;
+use core::sig::{self, Keypair, Signed};
+
+use block::{Block, BlockHeader, BLOCK_DECODE_LIMITS, BLOCK_HEADER_DECODE_LIMITS};
+use merkle;
+use segment::SignedSegment;
+
+use SignedBlock;
+
+/// Where the builder gets the time to put on blocks from, in milliseconds since the UNIX epoch.
+pub trait Clock {
+    fn now_millis(&self) -> i64;
+}
+
+/// Reads the system clock.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        let dur = SystemTime::now().duration_since(UNIX_EPOCH).expect("clock is before 1970");
+        ((dur.as_secs() * 1000) + (dur.subsec_millis() as u64)) as i64
+    }
+}
+
+impl<F> Clock for F where F: Fn() -> i64 {
+    fn now_millis(&self) -> i64 {
+        self()
+    }
+}
+
+/// Why a block couldn't be built.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum BuildError {
+
+    /// The same parent was given more than once.
+    DuplicateParent(Address),
+
+    /// More parents than a header can be decoded with.
+    TooManyParents(usize),
+
+    /// More segments than a block can be decoded with.
+    TooManySegments(usize),
+
+    /// The signed block would be this many bytes, which is more than it can be decoded with.
+    TooLarge(usize),
+
+    /// The clock says it's earlier than one of the parents was made.
+    BeforeParent(i64),
+
+    /// A parent's already at the highest height there can be.
+    HeightOverflow
+
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::DuplicateParent(ref a) => write!(f, "parent {} given more than once", a.to_short_string()),
+            BuildError::TooManyParents(n) => write!(f, "too many parents ({})", n),
+            BuildError::TooManySegments(n) => write!(f, "too many segments ({})", n),
+            BuildError::TooLarge(n) => write!(f, "block would be too large ({} bytes)", n),
+            BuildError::BeforeParent(ts) => write!(f, "timestamp is before a parent's ({})", ts),
+            BuildError::HeightOverflow => write!(f, "parent is already at the highest height")
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+/// Assembles a block from its parents and segments.  The height and merkle root are worked out
+/// when it's built.
+pub struct BlockBuilder<C: Clock = SystemClock> {
+    version: u32,
+    network: u32,
+    clock: C,
+    parents: Vec<(Address, u64)>,
+    earliest: i64, // Latest timestamp of the parents we've seen, which this has to be after.
+    segments: Vec<SignedSegment>
+}
+
+impl BlockBuilder<SystemClock> {
+
+    /// Makes a builder that uses the system clock.
+    pub fn new(version: u32) -> BlockBuilder<SystemClock> {
+        BlockBuilder::with_clock(version, SystemClock)
+    }
+
+}
+
+impl<C: Clock> BlockBuilder<C> {
+
+    pub fn with_clock(version: u32, clock: C) -> BlockBuilder<C> {
+        BlockBuilder {
+            version,
+            network: sig::NO_NETWORK,
+            clock,
+            parents: Vec::new(),
+            earliest: i64::MIN,
+            segments: Vec::new()
+        }
+    }
+
+    /// Sets the network the block's signed for.  By default it isn't for any network, which is
+    /// only any good for genesis blocks.
+    pub fn network(mut self, network: u32) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Adds a parent by its address and height, for when we don't have the block itself.
+    pub fn parent(mut self, addr: Address, height: u64) -> Self {
+        self.parents.push((addr, height));
+        self
+    }
+
+    /// Adds a parent block.  The new block will also have to be timestamped after it.
+    pub fn parent_block(mut self, pb: &SignedBlock) -> Self {
+        let head = pb.extract_owned().get_header().extract_owned();
+        self.earliest = self.earliest.max(head.timestamp());
+        self.parent(Address::of_bincomp(pb), head.block_height())
+    }
+
+    /// Adds a segment, either singly or multi-signed.
+    pub fn segment<S: Into<SignedSegment>>(mut self, seg: S) -> Self {
+        self.segments.push(seg.into());
+        self
+    }
+
+    pub fn segments<S: Into<SignedSegment>, I: IntoIterator<Item = S>>(mut self, segs: I) -> Self {
+        self.segments.extend(segs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Height the block will have, which is one more than its highest parent.  Blocks without
+    /// parents are at 0.  There isn't one if a parent's at the highest height already.
+    pub fn height(&self) -> Option<u64> {
+        self.parents.iter().try_fold(0, |m, &(_, h)| Some(m.max(h.checked_add(1)?)))
+    }
+
+    /// Builds the block, signing the header and the block with the keypair.
+    pub fn build(self, kp: Keypair) -> Result<SignedBlock, BuildError> {
+
+        if self.parents.len() as u64 > BLOCK_HEADER_DECODE_LIMITS.max_len {
+            return Err(BuildError::TooManyParents(self.parents.len()));
+        }
+
+        for (i, &(a, _)) in self.parents.iter().enumerate() {
+            if self.parents[..i].iter().any(|&(p, _)| p == a) {
+                return Err(BuildError::DuplicateParent(a));
+            }
+        }
+
+        if self.segments.len() as u64 > BLOCK_DECODE_LIMITS.max_len {
+            return Err(BuildError::TooManySegments(self.segments.len()));
+        }
+
+        let height = self.height().ok_or(BuildError::HeightOverflow)?;
+        let ts = self.clock.now_millis();
+        if ts < self.earliest {
+            return Err(BuildError::BeforeParent(ts));
+        }
+
+        let root = merkle::segments_root(self.segments.as_slice());
+        let parents = self.parents.iter().map(|&(a, _)| a).collect();
+        let head = BlockHeader::new(self.version, ts, height, root, parents);
+        let head = Signed::new_on(kp, self.network, head);
+        let sb = Signed::new_on(kp, self.network, Block::new(head, self.segments));
+
+        let len = sb.to_blob().len();
+        if len as u64 > BLOCK_DECODE_LIMITS.max_bytes {
+            return Err(BuildError::TooLarge(len));
+        }
+
+        Ok(sb)
+
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::sig::Scheme;
+
+    use artifact::ArtifactData;
+    use segment::{Segment, SegmentContent};
+
+    use super::*;
+    use DagNode;
+
+    fn post(kp: Keypair, i: u8) -> Signed<Segment> {
+        Signed::new(kp, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![i])), i as i64))
+    }
+
+    #[test]
+    fn ck_build_blocks() {
+
+        let kp = Scheme::Ed25519.generate(&[1; 32]);
+
+        let genesis = BlockBuilder::with_clock(1, || 1000).build(kp).unwrap();
+        let gh = genesis.extract_owned().get_header().extract_owned();
+        assert_eq!((gh.block_height(), gh.timestamp()), (0, 1000));
+        assert!(genesis.extract_owned().check_merkle_root());
+
+        let other = Address::of_slice(&[1]);
+        let b = BlockBuilder::with_clock(1, || 2000)
+            .parent_block(&genesis)
+            .parent(other, 4)
+            .segments((0..3).map(|i| post(kp, i)))
+            .build(kp)
+            .unwrap();
+
+        let block = b.extract_owned();
+        let head = block.get_header().extract_owned();
+        assert_eq!(head.block_height(), 5);
+        assert_eq!(head.parents(), vec![Address::of_bincomp(&genesis), other]);
+        assert_eq!(block.get_segments().len(), 3);
+        assert!(block.check_merkle_root());
+        assert_eq!(b.timestamp(), 2000);
+
+        assert!(b.verify(kp.into()).is_ok());
+        assert!(block.get_header().verify(kp.into()).is_ok());
+        assert_eq!(SignedBlock::from_slice_exact(b.to_blob().as_slice()).unwrap(), b);
+
+        // Blocks for a network are only signed for that one.
+        let nb = BlockBuilder::with_clock(1, || 2000).network(3).parent_block(&genesis).build(kp).unwrap();
+        assert!(nb.verify_on(kp.into(), 3).is_ok());
+        assert!(nb.extract_owned().get_header().verify_on(kp.into(), 3).is_ok());
+        assert!(nb.verify(kp.into()).is_err());
+        assert!(nb.verify_on(kp.into(), 2).is_err());
+
+    }
+
+    #[test]
+    fn ck_build_errors() {
+
+        let kp = Scheme::Ed25519.generate(&[1; 32]);
+        let genesis = BlockBuilder::with_clock(1, || 1000).build(kp).unwrap();
+
+        let res = BlockBuilder::with_clock(1, || 999).parent_block(&genesis).build(kp);
+        assert_eq!(res, Err(BuildError::BeforeParent(999)));
+
+        let a = Address::of_slice(&[1]);
+        let res = BlockBuilder::new(1).parent(a, 0).parent(a, 1).build(kp);
+        assert_eq!(res, Err(BuildError::DuplicateParent(a)));
+
+        let b = Address::of_slice(&[2]);
+        let top = BlockBuilder::new(1).parent(a, 3).parent(b, u64::MAX);
+        assert_eq!(top.height(), None);
+        assert_eq!(top.build(kp), Err(BuildError::HeightOverflow));
+        assert_eq!(BlockBuilder::new(1).parent(a, u64::MAX - 1).height(), Some(u64::MAX));
+        assert_eq!(BlockBuilder::new(1).height(), Some(0));
+
+        // A couple of huge segments don't fit.
+        let big = |i| Signed::new(kp, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![i; 1 << 22])), 0));
+        let res = BlockBuilder::new(1).segment(big(0)).segment(big(1)).build(kp);
+        match res {
+            Err(BuildError::TooLarge(n)) => assert!(n > 1 << 23),
+            r => panic!("expected it to be too large, got {:?}", r.map(|_| ()))
+        }
+
+    }
+
+}
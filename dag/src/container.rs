@@ -1,6 +1,4 @@
-use byteorder::*;
-
-use core::io::{BinaryComponent, DecodeError, WrResult};
+use core::io::DecodeLimits;
 use core::sig::Signed;
 
 use segment::*;
@@ -10,34 +8,31 @@ use DagNode;
 /// The off-chain container.  Usually you would want to use it as a `Signed<ArtifactContainer>`.
 /// You can technically chain these infinitely as it's actually a segment container, so you could
 /// just chain `ArtifactPointer`s indefinitely.
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[decode_limits = "CONTAINER_DECODE_LIMITS"]
+#[signing_domain = "jiyunet/artifact-container/v1"]
 pub struct ArtifactContainer {
     version: u32,
     timestamp: i64,
     content: SegmentContent
 }
 
-impl BinaryComponent for ArtifactContainer {
-
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+/// Limits for decoding an artifact container.  These live off-chain so they can be pretty big.
+pub const CONTAINER_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(1 << 25, 1 << 10, 16);
 
-        let ver = read.read_u32::<BigEndian>()?;
-        let ts = read.read_i64::<BigEndian>()?;
-        let cont = SegmentContent::from_reader(read)?;
-
-        Ok(ArtifactContainer {
-            version: ver,
-            timestamp: ts,
-            content: cont
-        })
+impl ArtifactContainer {
 
+    pub fn new(version: u32, timestamp: i64, content: SegmentContent) -> ArtifactContainer {
+        ArtifactContainer {
+            version,
+            timestamp,
+            content
+        }
     }
 
-    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        write.write_u32::<BigEndian>(self.version)?;
-        write.write_i64::<BigEndian>(self.timestamp)?;
-        self.content.to_writer(write)?;
-        Ok(())
+    /// Returns the actual content of the container.
+    pub fn content(&self) -> SegmentContent {
+        self.content.clone()
     }
 
 }
@@ -53,3 +48,31 @@ impl DagNode for Signed<ArtifactContainer> {
     }
 
 }
+
+#[cfg(test)]
+mod test {
+
+    use core::sig::Signed;
+
+    use testutil::*;
+
+    use super::*;
+
+    #[test]
+    fn ck_container_wire_format() {
+        use core::Address;
+        let ac = ArtifactContainer::new(1, 1337, SegmentContent::ArtifactPointer(Address::of_slice(&[4, 2])));
+        assert_eq!(hex_blob(&ac), "000000010000000000000539021220b7586d310e5efb1b7d10a917ba5af403adbf54f4f77fe7fdcb4880a95dac7e7e");
+    }
+
+    quickcheck! {
+
+        fn prop_container_canonical(c: Arb<Signed<ArtifactContainer>>, idx: usize, byte: u8) -> bool {
+            let inner = c.0.extract_owned();
+            canonical(&inner) && mutant_canonical(&inner, idx, byte) && strict_bounds(&inner)
+                && canonical(&c.0) && mutant_canonical(&c.0, idx, byte) && strict_bounds(&c.0)
+        }
+
+    }
+
+}
@@ -0,0 +1,100 @@
+//! Delegations, where an identity vouches for some other key so that things signed by that key
+//! count as coming from the identity.  Usually the key comes from `Keypair::derive_child`, but it
+//! doesn't have to, since there's no way to check that from the public keys alone anyways.
+
+use core::sig::{Fingerprint, ValidationKey};
+
+/// Authorizes the child key to act for whoever signs this.  Only useful as a
+/// `Signed<Delegation>`, which is what goes in a segment.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[signing_domain = "jiyunet/delegation/v1"]
+pub struct Delegation {
+    child: ValidationKey,
+    expires: Option<i64>, // Millisecond UNIX time after which it stops counting, if ever.
+    seq: u64 // Has to be higher than any key change the identity's made before, so it can't be replayed.
+}
+
+impl Delegation {
+
+    pub fn new(child: ValidationKey, expires: Option<i64>, seq: u64) -> Delegation {
+        Delegation {
+            child,
+            expires,
+            seq
+        }
+    }
+
+    pub fn child(&self) -> ValidationKey {
+        self.child
+    }
+
+    pub fn child_fingerprint(&self) -> Fingerprint {
+        self.child.into()
+    }
+
+    pub fn expires(&self) -> Option<i64> {
+        self.expires
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Checks if the delegation still counts at the given time.
+    pub fn is_live_at(&self, ts: i64) -> bool {
+        match self.expires {
+            Some(e) => ts <= e,
+            None => true
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::io::BinaryComponent;
+    use core::sig::{Scheme, Signed};
+
+    use super::*;
+    use segment::{Segment, SegmentContent};
+    use testutil::*;
+
+    quickcheck! {
+
+        fn prop_delegation_canonical(seed: Vec<u8>, expires: Option<i64>, seq: u64, idx: usize, byte: u8) -> bool {
+            let master = Scheme::Ed25519.generate(seed.as_slice());
+            let d = Signed::new(master, Delegation::new(master.derive_child(0).into(), expires, seq));
+            canonical(&d) && mutant_canonical(&d, idx, byte) && strict_bounds(&d)
+        }
+
+    }
+
+    #[test]
+    fn ck_delegation_wire_format() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let child = master.derive_child(0);
+        let d = Delegation::new(child.into(), Some(1000), 7);
+        let vk: ValidationKey = child.into();
+
+        assert_eq!(hex_blob(&d), format!("{}0100000000000003e80000000000000007", hex_blob(&vk)));
+        assert_eq!(Delegation::signing_domain(), "jiyunet/delegation/v1");
+
+        let sd = Signed::new(master, d);
+        assert!(sd.verify(master.into()).is_ok());
+
+        let seg = Segment::new(SegmentContent::Delegation(sd.clone()), 5);
+        assert_eq!(hex_blob(&seg), format!("000000000000000503{}", hex_blob(&sd)));
+
+    }
+
+    #[test]
+    fn ck_delegation_expiry() {
+        let vk: ValidationKey = Scheme::Ed25519.generate(&[1; 32]).into();
+        assert!(Delegation::new(vk, None, 1).is_live_at(i64::MAX));
+        assert!(Delegation::new(vk, Some(10), 1).is_live_at(10));
+        assert!(!Delegation::new(vk, Some(10), 1).is_live_at(11));
+    }
+
+}
@@ -0,0 +1,316 @@
+//! Identity declarations, and the things an identity does to manage its own keys.
+//!
+//! An identity is always known by the fingerprint of the key it was declared with, even after
+//! that key has been rotated away, so that references to it don't break.
+
+use core::Address;
+use core::sig::{Fingerprint, SigPolicy, ValidationKey};
+
+/// Longest display name allowed, in bytes.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Longest bio allowed, in bytes.
+pub const MAX_BIO_LEN: usize = 1024;
+
+/// Set of things an identity says it does.  Bits we don't know about are kept around as-is, so
+/// that older nodes don't mangle newer declarations.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, BinaryComponent)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+
+    /// Posts things, which is what most identities are for.
+    pub const POSTER: Capabilities = Capabilities(1 << 0);
+
+    /// Hosts artifact containers for other people.
+    pub const STORAGE: Capabilities = Capabilities(1 << 1);
+
+    /// Relays blocks and segments around the network.
+    pub const RELAY: Capabilities = Capabilities(1 << 2);
+
+    /// Produces blocks.
+    pub const VALIDATOR: Capabilities = Capabilities(1 << 3);
+
+    pub fn empty() -> Capabilities {
+        Capabilities(0)
+    }
+
+    pub fn from_bits(bits: u32) -> Capabilities {
+        Capabilities(bits)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Checks if all of the capabilities in `other` are in this set.
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn with(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+}
+
+/// Declares an identity, or updates the profile of one that's already been declared.  The key is
+/// the one the identity is declared with, so it's always the same for a given identity.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+pub struct IdentityDecl {
+    key: ValidationKey,
+    #[len_prefix = "u8"]
+    name: String,
+    avatar: Option<Address>, // Artifact with their picture in it, if they have one.
+    #[len_prefix = "u16"]
+    bio: String,
+    capabilities: Capabilities
+}
+
+impl IdentityDecl {
+
+    pub fn new(key: ValidationKey, name: String, avatar: Option<Address>, bio: String, capabilities: Capabilities) -> IdentityDecl {
+        IdentityDecl {
+            key,
+            name,
+            avatar,
+            bio,
+            capabilities
+        }
+    }
+
+    pub fn key(&self) -> ValidationKey {
+        self.key
+    }
+
+    /// Fingerprint of the key, which is what the identity is known by.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.key.into()
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn avatar(&self) -> Option<Address> {
+        self.avatar
+    }
+
+    pub fn bio(&self) -> &str {
+        self.bio.as_str()
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Checks the parts the encoding doesn't, which is that the name is nonempty and neither it
+    /// nor the bio are too long or have control characters in them (other than newlines in the
+    /// bio) that could mess with whatever's displaying them.
+    pub fn is_well_formed(&self) -> bool {
+        !self.name.is_empty()
+            && self.name.len() <= MAX_NAME_LEN
+            && self.bio.len() <= MAX_BIO_LEN
+            && !self.name.chars().any(char::is_control)
+            && !self.bio.chars().any(|c| c.is_control() && c != '\n')
+    }
+
+}
+
+/// Replaces an identity's key.  Signed by the current key, which stops being valid once the
+/// rotation is on the chain.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[signing_domain = "jiyunet/key-rotation/v1"]
+pub struct KeyRotation {
+    new_key: ValidationKey,
+    seq: u64 // Like on delegations.
+}
+
+impl KeyRotation {
+
+    pub fn new(new_key: ValidationKey, seq: u64) -> KeyRotation {
+        KeyRotation {
+            new_key,
+            seq
+        }
+    }
+
+    pub fn new_key(&self) -> ValidationKey {
+        self.new_key
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+}
+
+/// Revokes one of an identity's keys.  Signed by the identity's current key, or by the key being
+/// revoked itself, so that a leaked key can still be killed off by whoever has it.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[signing_domain = "jiyunet/key-revocation/v1"]
+pub struct Revocation {
+    revoked: Fingerprint,
+    seq: u64 // Like on delegations.
+}
+
+impl Revocation {
+
+    pub fn new(revoked: Fingerprint, seq: u64) -> Revocation {
+        Revocation {
+            revoked,
+            seq
+        }
+    }
+
+    pub fn revoked(&self) -> Fingerprint {
+        self.revoked
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+}
+
+/// Puts an identity's key management under a policy, so that its delegations, rotations,
+/// revocations, and policy changes have to be multi-signed by the policy's threshold of keys
+/// instead of just its main key.  Clearing it puts things back to the main key.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[signing_domain = "jiyunet/policy-change/v1"]
+pub struct PolicyChange {
+    policy: Option<SigPolicy>,
+    seq: u64 // Like on delegations.
+}
+
+impl PolicyChange {
+
+    pub fn new(policy: Option<SigPolicy>, seq: u64) -> PolicyChange {
+        PolicyChange {
+            policy,
+            seq
+        }
+    }
+
+    pub fn policy(&self) -> Option<SigPolicy> {
+        self.policy.clone()
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::io::BinaryComponent;
+    use core::sig::{Scheme, Signed};
+
+    use super::*;
+    use segment::{Segment, SegmentContent};
+    use testutil::*;
+
+    fn decl(seed: &[u8]) -> IdentityDecl {
+        let vk: ValidationKey = Scheme::Ed25519.generate(seed).into();
+        IdentityDecl::new(vk, "alice".into(), None, "hi\nthere".into(), Capabilities::POSTER)
+    }
+
+    quickcheck! {
+
+        fn prop_decl_canonical(seed: Vec<u8>, name: String, avatar: Option<Vec<u8>>, bio: String, caps: u32, idx: usize, byte: u8) -> bool {
+            let vk: ValidationKey = Scheme::Ed25519.generate(seed.as_slice()).into();
+            let d = IdentityDecl::new(vk, name, avatar.map(|a| Address::of_slice(a.as_slice())), bio, Capabilities::from_bits(caps));
+            match d.to_writer(&mut Vec::new()) {
+                Ok(_) => canonical(&d) && mutant_canonical(&d, idx, byte) && strict_bounds(&d),
+                Err(_) => true // Name or bio too long for their prefixes.
+            }
+        }
+
+        fn prop_rotation_canonical(seed: Vec<u8>, seq: u64, idx: usize, byte: u8) -> bool {
+            let kp = Scheme::Ed25519.generate(seed.as_slice());
+            let r = Signed::new(kp, KeyRotation::new(kp.derive_child(0).into(), seq));
+            canonical(&r) && mutant_canonical(&r, idx, byte) && strict_bounds(&r)
+        }
+
+        fn prop_revocation_canonical(seed: Vec<u8>, seq: u64, idx: usize, byte: u8) -> bool {
+            let kp = Scheme::Ed25519.generate(seed.as_slice());
+            let r = Signed::new(kp, Revocation::new(kp.into(), seq));
+            canonical(&r) && mutant_canonical(&r, idx, byte) && strict_bounds(&r)
+        }
+
+        fn prop_policy_change_canonical(seed: Vec<u8>, threshold: u8, seq: u64, idx: usize, byte: u8) -> bool {
+            let kp = Scheme::Ed25519.generate(seed.as_slice());
+            let policy = SigPolicy::new(threshold % 2 + 1, vec![kp.into(), kp.derive_child(0).into()]);
+            let r = Signed::new(kp, PolicyChange::new(policy, seq));
+            canonical(&r) && mutant_canonical(&r, idx, byte) && strict_bounds(&r)
+        }
+
+    }
+
+    #[test]
+    fn ck_decl_wire_format() {
+
+        let d = decl(&[1; 32]);
+        let vk = d.key();
+        assert_eq!(hex_blob(&d), format!("{}05616c69636500000868690a746865726500000001", hex_blob(&vk)));
+
+        let d = IdentityDecl::new(vk, "bob".into(), Some(Address::of_slice(&[1, 3, 3, 7])), "".into(), Capabilities::from_bits(0x80000003));
+        assert_eq!(hex_blob(&d), format!("{}03626f62011220acb86a9cb70a84f695de89e7fe22819466205759d798d52d4a3dd95b0cdaa2a1000080000003", hex_blob(&vk)));
+        assert!(d.capabilities().contains(Capabilities::POSTER.with(Capabilities::STORAGE)));
+        assert!(!d.capabilities().contains(Capabilities::RELAY));
+
+        let seg = Segment::new(SegmentContent::IdentDecl(d.clone()), 5);
+        assert_eq!(hex_blob(&seg), format!("000000000000000500{}", hex_blob(&d)));
+
+    }
+
+    #[test]
+    fn ck_decl_well_formed() {
+
+        let d = decl(&[1; 32]);
+        assert!(d.is_well_formed());
+
+        let with = |name: &str, bio: &str| IdentityDecl::new(d.key(), name.into(), None, bio.into(), Capabilities::empty());
+        assert!(with(&"a".repeat(MAX_NAME_LEN), &"b".repeat(MAX_BIO_LEN)).is_well_formed());
+        assert!(!with("", "").is_well_formed());
+        assert!(!with(&"a".repeat(MAX_NAME_LEN + 1), "").is_well_formed());
+        assert!(!with("a", &"b".repeat(MAX_BIO_LEN + 1)).is_well_formed());
+        assert!(!with("a\nb", "").is_well_formed());
+        assert!(!with("a", "\u{1b}[2J").is_well_formed());
+
+    }
+
+    #[test]
+    fn ck_identity_wire_format() {
+
+        let kp = Scheme::Ed25519.generate(&[1; 32]);
+        let vk: ValidationKey = kp.derive_child(0).into();
+        let fp: Fingerprint = kp.into();
+
+        assert_eq!(hex_blob(&KeyRotation::new(vk, 2)), format!("{}0000000000000002", hex_blob(&vk)));
+        assert_eq!(hex_blob(&Revocation::new(fp, 3)), format!("{}0000000000000003", hex_blob(&fp)));
+        assert_eq!(KeyRotation::signing_domain(), "jiyunet/key-rotation/v1");
+        assert_eq!(Revocation::signing_domain(), "jiyunet/key-revocation/v1");
+
+        let r = Signed::new(kp, KeyRotation::new(vk, 2));
+        let seg = Segment::new(SegmentContent::KeyRotation(r.clone()), 5);
+        assert_eq!(hex_blob(&seg), format!("000000000000000504{}", hex_blob(&r)));
+
+        let r = Signed::new(kp, Revocation::new(fp, 3));
+        let seg = Segment::new(SegmentContent::Revocation(r.clone()), 5);
+        assert_eq!(hex_blob(&seg), format!("000000000000000505{}", hex_blob(&r)));
+
+        let policy = SigPolicy::new(1, vec![fp, vk.into()]).unwrap();
+        assert_eq!(hex_blob(&PolicyChange::new(Some(policy.clone()), 4)), format!("01{}0000000000000004", hex_blob(&policy)));
+        assert_eq!(hex_blob(&PolicyChange::new(None, 4)), "000000000000000004");
+        assert_eq!(PolicyChange::signing_domain(), "jiyunet/policy-change/v1");
+
+        let r = Signed::new(kp, PolicyChange::new(Some(policy), 4));
+        let seg = Segment::new(SegmentContent::PolicyChange(r.clone()), 5);
+        assert_eq!(hex_blob(&seg), format!("000000000000000506{}", hex_blob(&r)));
+
+    }
+
+}
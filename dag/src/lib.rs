@@ -1,22 +1,47 @@
 extern crate jiyunet_core as core;
+#[macro_use] extern crate jiyunet_derive;
 
 extern crate byteorder;
 extern crate crypto;
 
+#[cfg(test)]
+#[macro_use] extern crate quickcheck;
+
 use core::io::BinaryComponent;
 use core::sig::Signed;
 
 pub mod artifact;
 pub mod block;
+pub mod builder;
 pub mod container;
+pub mod delegation;
+pub mod identity;
+pub mod merkle;
+pub mod params;
+pub mod pow;
 pub mod segment;
 
+#[cfg(test)]
+mod testutil;
+
 /// Simpler way to refer to the actual block on the chain, as they need to be signed.
 pub type SignedBlock = Signed<block::Block>;
 
 /// Simpler way to refer to artifacts stored off-chain, as they *still* need to be signed.
 pub type SignedArtifactContainer = Signed<container::ArtifactContainer>;
 
+/// A delegation from an identity to another key, which is how it's always passed around.
+pub type SignedDelegation = Signed<delegation::Delegation>;
+
+/// A key rotation, signed by the key being replaced.
+pub type SignedKeyRotation = Signed<identity::KeyRotation>;
+
+/// A key revocation, signed by the identity or the key being revoked.
+pub type SignedRevocation = Signed<identity::Revocation>;
+
+/// A change to an identity's governance policy, signed by one of its keys.
+pub type SignedPolicyChange = Signed<identity::PolicyChange>;
+
 /// Represents a standalone node on the DAG.  Should always also be a `Signed<T>` something, but
 /// this does not *need* to be true.
 pub trait DagNode: BinaryComponent {
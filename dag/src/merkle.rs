@@ -0,0 +1,269 @@
+//! Merkle trees over the segments in a block, so that someone with just a block header can check
+//! that a segment is in the block without having the rest of it.
+//!
+//! The tree is shaped like the one in RFC 6962.  The left subtree is always the largest power of
+//! two that's smaller than the number of leaves, and nodes are never duplicated.  Leaves and inner
+//! nodes are hashed with different prefixes so that one can't be passed off as the other.
+
+use core::io::{BinaryComponent, DecodeLimits};
+use core::sig::{Hash, SHA256_WIDTH};
+
+use block::BlockHeader;
+use segment::SignedSegment;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Proofs are only ever a few dozen hashes long.
+pub const MERKLE_PROOF_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(1 << 12, 64, 4);
+
+/// Hash of a leaf in the tree, from the full encoding of the signed segment.
+pub fn leaf_hash(seg: &SignedSegment) -> Hash {
+    let mut buf = vec![LEAF_PREFIX];
+    buf.extend(seg.to_blob());
+    Hash::of_slice(buf.as_slice())
+}
+
+fn node_hash(left: Hash, right: Hash) -> Hash {
+    let mut buf = Vec::with_capacity(1 + SHA256_WIDTH * 2);
+    buf.push(NODE_PREFIX);
+    buf.extend_from_slice(&left.into_array());
+    buf.extend_from_slice(&right.into_array());
+    Hash::of_slice(buf.as_slice())
+}
+
+/// Size of the left subtree of a tree with `n` leaves.  `n` has to be at least 2.
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+fn subtree_root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        1 => leaves[0],
+        n => {
+            let k = split(n);
+            node_hash(subtree_root(&leaves[..k]), subtree_root(&leaves[k..]))
+        }
+    }
+}
+
+/// Computes the merkle root of the leaf hashes.  The root of an empty tree is the hash of nothing.
+pub fn root_of(leaves: &[Hash]) -> Hash {
+    match leaves.is_empty() {
+        true => Hash::of_slice(&[]),
+        false => subtree_root(leaves)
+    }
+}
+
+/// Computes the merkle root of a list of segments, which is what goes in the block header.
+pub fn segments_root(segs: &[SignedSegment]) -> Hash {
+    root_of(segs.iter().map(leaf_hash).collect::<Vec<_>>().as_slice())
+}
+
+/// Proof that some leaf is at an index in a tree of some size.  The path is the sibling hashes
+/// from the bottom of the tree to the top.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[decode_limits = "MERKLE_PROOF_DECODE_LIMITS"]
+pub struct MerkleProof {
+    index: u32,
+    leaves: u32,
+    path: Vec<Hash>
+}
+
+impl MerkleProof {
+
+    /// Makes the proof for the leaf at the index, if there is one.
+    pub fn generate(leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+
+        if index >= leaves.len() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let (mut lo, mut hi) = (0, leaves.len());
+        while hi - lo > 1 {
+            let mid = lo + split(hi - lo);
+            if index < mid {
+                path.push(subtree_root(&leaves[mid..hi]));
+                hi = mid;
+            } else {
+                path.push(subtree_root(&leaves[lo..mid]));
+                lo = mid;
+            }
+        }
+
+        // We went top down, but they're checked bottom up.
+        path.reverse();
+
+        Some(MerkleProof {
+            index: index as u32,
+            leaves: leaves.len() as u32,
+            path
+        })
+
+    }
+
+    /// Index of the leaf that this is a proof for.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// Number of leaves in the tree.
+    pub fn leaves(&self) -> usize {
+        self.leaves as usize
+    }
+
+    /// Computes what the root would be if the leaf is where the proof says it is.  Returns `None`
+    /// if the proof doesn't have the right number of hashes for the shape of the tree.
+    pub fn compute_root(&self, leaf: Hash) -> Option<Hash> {
+        if self.index >= self.leaves {
+            return None;
+        }
+        climb(self.index as usize, self.leaves as usize, leaf, self.path.as_slice())
+    }
+
+    /// Checks that the leaf is in the tree with the given root.
+    pub fn verify(&self, root: Hash, leaf: Hash) -> bool {
+        self.compute_root(leaf) == Some(root)
+    }
+
+    /// Checks that the segment is in the block that the header is for.
+    pub fn verify_segment(&self, header: &BlockHeader, seg: &SignedSegment) -> bool {
+        self.verify(header.segments_merkle_root(), leaf_hash(seg))
+    }
+
+}
+
+/// Works out the root of a subtree of `n` leaves from the leaf at `index` in it, using the hashes
+/// at the end of the path for the top of the subtree.
+fn climb(index: usize, n: usize, leaf: Hash, path: &[Hash]) -> Option<Hash> {
+
+    if n == 1 {
+        return match path.is_empty() {
+            true => Some(leaf),
+            false => None
+        };
+    }
+
+    let (sibling, rest) = match path.split_last() {
+        Some((s, r)) => (*s, r),
+        None => return None
+    };
+
+    let k = split(n);
+    match index < k {
+        true => climb(index, k, leaf, rest).map(|l| node_hash(l, sibling)),
+        false => climb(index - k, n - k, leaf, rest).map(|r| node_hash(sibling, r))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::Address;
+    use core::sig::{Scheme, Signed};
+
+    use artifact::ArtifactData;
+    use segment::{Segment, SegmentContent};
+    use testutil::*;
+
+    use super::*;
+
+    fn leaves(n: u32) -> Vec<Hash> {
+        (0..n).map(|i| Hash::of_slice(&i.to_be_bytes())).collect()
+    }
+
+    quickcheck! {
+
+        fn prop_proofs_verify(n: u8, idx: u8) -> bool {
+            let ls = leaves(n as u32 % 40 + 1);
+            let i = idx as usize % ls.len();
+            let root = root_of(ls.as_slice());
+            let p = MerkleProof::generate(ls.as_slice(), i).unwrap();
+            p.verify(root, ls[i]) && !p.verify(root, Hash::of_slice(b"nope")) && canonical(&p)
+        }
+
+        fn prop_proofs_only_fit_their_index(n: u8, idx: u8, other: u8) -> bool {
+            let ls = leaves(n as u32 % 40 + 2);
+            let (i, j) = (idx as usize % ls.len(), other as usize % ls.len());
+            let root = root_of(ls.as_slice());
+            let mut p = MerkleProof::generate(ls.as_slice(), i).unwrap();
+            p.index = j as u32;
+            i == j || !p.verify(root, ls[i])
+        }
+
+    }
+
+    #[test]
+    fn ck_tree_shape() {
+
+        let ls = leaves(3);
+        let expected = node_hash(node_hash(ls[0], ls[1]), ls[2]);
+        assert_eq!(root_of(ls.as_slice()), expected);
+        assert_eq!(root_of(&ls[..1]), ls[0]);
+        assert_eq!(root_of(&[]), Hash::of_slice(&[]));
+
+        let p = MerkleProof::generate(ls.as_slice(), 2).unwrap();
+        assert_eq!(p.path, vec![node_hash(ls[0], ls[1])]);
+        assert_eq!(MerkleProof::generate(ls.as_slice(), 3), None);
+
+        // Leaves get their own prefix, so they aren't just the hash of the segment.
+        let seg: SignedSegment = Signed::new(Scheme::Ed25519.generate(&[1; 32]), Segment::new(SegmentContent::ArtifactPointer(Address::of_slice(&[1])), 0)).into();
+        assert!(leaf_hash(&seg) != Hash::of_slice(seg.to_blob().as_slice()));
+
+    }
+
+    #[test]
+    fn ck_bad_proofs() {
+
+        let ls = leaves(5);
+        let root = root_of(ls.as_slice());
+        let good = MerkleProof::generate(ls.as_slice(), 4).unwrap();
+        assert!(good.verify(root, ls[4]));
+
+        let mut short = good.clone();
+        short.path.pop();
+        assert_eq!(short.compute_root(ls[4]), None);
+
+        let mut long = good.clone();
+        long.path.push(ls[0]);
+        assert_eq!(long.compute_root(ls[4]), None);
+
+        // Claiming the tree is a different size.
+        let mut resized = good.clone();
+        resized.leaves = 6;
+        assert!(!resized.verify(root, ls[4]));
+        resized.leaves = 4;
+        assert_eq!(resized.compute_root(ls[4]), None);
+
+    }
+
+    #[test]
+    fn ck_segment_proofs() {
+
+        let kp = Scheme::Ed25519.generate(&[7; 32]);
+        let segs: Vec<SignedSegment> = (0..6u8).map(|i| {
+            let seg = Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![i])), i as i64);
+            Signed::new(kp, seg).into()
+        }).collect();
+
+        let hs: Vec<Hash> = segs.iter().map(leaf_hash).collect();
+        let head = BlockHeader::new(1, 0, 0, segments_root(segs.as_slice()), vec![]);
+
+        for i in 0..segs.len() {
+            let p = MerkleProof::generate(hs.as_slice(), i).unwrap();
+            assert!(p.verify_segment(&head, &segs[i]));
+            assert!(!p.verify_segment(&head, &segs[(i + 1) % segs.len()]));
+        }
+
+        // Headers commit to this, so it can't ever change.
+        assert_eq!(hex_blob(&segments_root(segs.as_slice())), "dada26f88557eae8c474ae6f0e691ab10af5cdaabba8203d5ed0e18dc7a8cc98");
+
+    }
+
+}
@@ -0,0 +1,366 @@
+//! Network parameters, which say which DAG we're on and the rules it's validated under.  Every
+//! network starts from its own genesis block, and that's what tells them apart.  Everything after
+//! genesis is signed for the network's id, so it can't be replayed onto another network.
+
+use core::Address;
+use core::io::DecodeLimits;
+use core::sig::{self, Keypair, Scheme, Signed};
+
+use artifact::ArtifactData;
+use block::{BLOCK_DECODE_LIMITS, BLOCK_HEADER_DECODE_LIMITS};
+use builder::{BlockBuilder, BuildError};
+use identity::{Capabilities, IdentityDecl};
+use pow::MAX_STAMP_BITS;
+use segment::{Segment, SegmentContent};
+
+use SignedBlock;
+
+/// Block version that the built in networks start with.
+pub const BLOCK_VERSION: u32 = 1;
+
+/// Network ids below this are for the built in networks, custom ones have to be above it.
+pub const RESERVED_NETWORK_IDS: u32 = 0x100;
+
+pub const MAINNET_ID: u32 = 0x01;
+pub const TESTNET_ID: u32 = 0x02;
+pub const REGTEST_ID: u32 = 0x03;
+
+/// Seed for the key the built in genesis blocks are signed with.  It's public on purpose, since
+/// the genesis block is trusted by its address and not by who signed it.  That means anyone can
+/// sign as it, so only regtest's genesis block declares it as an identity.
+const GENESIS_SEED: &[u8] = b"jiyunet/genesis/v1";
+
+/// 2018-01-01T00:00:00Z
+const GENESIS_TIMESTAMP: i64 = 1514764800000;
+
+/// Segments that cost up to this much can be paid for with `CreditPolicy::stamp_bits` of work.
+pub const STAMP_BASE_COST: u64 = 64;
+
+/// Same as for a block, with a little room for the rest of the parameters.
+pub const PARAMS_DECODE_LIMITS: DecodeLimits = DecodeLimits::new((1 << 23) + (1 << 12), 1 << 16, 17);
+
+/// How many credits each kind of segment costs to post.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, BinaryComponent)]
+pub struct SegmentCosts {
+    pub ident: u64,
+    pub artifact_ptr: u64,
+    pub delegation: u64,
+    pub key_rotation: u64,
+    pub revocation: u64 // Cheap, since it's what you do in an emergency.
+}
+
+impl SegmentCosts {
+
+    pub const DEFAULT: SegmentCosts = SegmentCosts {
+        ident: 1000,
+        artifact_ptr: 50,
+        delegation: 100,
+        key_rotation: 100,
+        revocation: 10
+    };
+
+}
+
+/// How identities earn credits.  Every identity regenerates credits with each block that's
+/// applied, up to a cap, and whoever signs a block pays for it so they can't be spammed for free.
+/// Segments stamped with enough proof of work don't cost anything, but the work it takes goes up
+/// with what they'd cost.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, BinaryComponent)]
+pub struct CreditPolicy {
+    pub initial: u64, // What new identities start with.
+    pub founding: u64, // What the identities declared in the genesis block start with.
+    pub regen: u64, // Per block.
+    pub regen_cap: u64, // Balances above this don't regenerate, but aren't cut down either.
+    pub block_cost: u64,
+    pub stamp_bits: u8 // Work a stamp needs on the cheapest segments, 0 if they aren't accepted.
+}
+
+impl CreditPolicy {
+
+    pub const DEFAULT: CreditPolicy = CreditPolicy {
+        initial: 0,
+        founding: 10000,
+        regen: 1,
+        regen_cap: 10000,
+        block_cost: 10,
+        stamp_bits: 20
+    };
+
+    /// What a balance has grown to after some number of blocks.
+    pub fn regenerate(&self, balance: u64, blocks: u64) -> u64 {
+        match balance >= self.regen_cap {
+            true => balance,
+            false => balance.saturating_add(self.regen.saturating_mul(blocks)).min(self.regen_cap)
+        }
+    }
+
+    /// Work a stamp needs to pay for something that costs that many credits.  It's another bit
+    /// each time the cost doubles past `STAMP_BASE_COST`, so the work doubles with it.
+    pub fn stamp_bits_for(&self, cost: u64) -> u32 {
+        let extra = match cost / STAMP_BASE_COST {
+            0 => 0,
+            n => 64 - n.leading_zeros()
+        };
+        self.stamp_bits as u32 + extra
+    }
+
+}
+
+/// How big blocks are allowed to be.  These can be tighter than what blocks can be decoded with,
+/// but not looser.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, BinaryComponent)]
+pub struct BlockLimits {
+    pub max_bytes: u64,
+    pub max_segments: u64,
+    pub max_parents: u64
+}
+
+impl BlockLimits {
+
+    pub const DEFAULT: BlockLimits = BlockLimits {
+        max_bytes: BLOCK_DECODE_LIMITS.max_bytes,
+        max_segments: BLOCK_DECODE_LIMITS.max_len,
+        max_parents: BLOCK_HEADER_DECODE_LIMITS.max_len
+    };
+
+    /// If the limits fit within what blocks can be decoded with.
+    pub fn is_sane(&self) -> bool {
+        self.max_bytes <= BLOCK_DECODE_LIMITS.max_bytes
+            && self.max_segments <= BLOCK_DECODE_LIMITS.max_len
+            && self.max_parents <= BLOCK_HEADER_DECODE_LIMITS.max_len
+    }
+
+}
+
+/// Everything that identifies a network and sets its rules.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[decode_limits = "PARAMS_DECODE_LIMITS"]
+pub struct NetworkParams {
+    network_id: u32,
+    #[len_prefix = "u8"]
+    name: String,
+    version: u32,
+    costs: SegmentCosts,
+    credits: CreditPolicy,
+    limits: BlockLimits,
+    genesis: SignedBlock
+}
+
+impl NetworkParams {
+
+    /// Makes parameters for a custom network, with the default costs and limits.
+    pub fn new(network_id: u32, name: String, genesis: SignedBlock) -> NetworkParams {
+        NetworkParams {
+            network_id,
+            name,
+            version: BLOCK_VERSION,
+            costs: SegmentCosts::DEFAULT,
+            credits: CreditPolicy::DEFAULT,
+            limits: BlockLimits::DEFAULT,
+            genesis
+        }
+    }
+
+    pub fn with_costs(mut self, costs: SegmentCosts) -> NetworkParams {
+        self.costs = costs;
+        self
+    }
+
+    pub fn with_credits(mut self, credits: CreditPolicy) -> NetworkParams {
+        self.credits = credits;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: BlockLimits) -> NetworkParams {
+        self.limits = limits;
+        self
+    }
+
+    pub fn mainnet() -> NetworkParams {
+        NetworkParams::new(MAINNET_ID, "mainnet".into(), builtin_genesis("mainnet", 0, false))
+    }
+
+    pub fn testnet() -> NetworkParams {
+        NetworkParams::new(TESTNET_ID, "testnet".into(), builtin_genesis("testnet", 1, false))
+    }
+
+    /// For local testing.  Identities are cheap, and start with enough to post a bit.  The genesis
+    /// block's key is an identity too, which anyone can sign as.
+    pub fn regtest() -> NetworkParams {
+        let costs = SegmentCosts { ident: 1, ..SegmentCosts::DEFAULT };
+        let credits = CreditPolicy { initial: 100, block_cost: 1, stamp_bits: 4, ..CreditPolicy::DEFAULT };
+        NetworkParams::new(REGTEST_ID, "regtest".into(), builtin_genesis("regtest", 2, true)).with_costs(costs).with_credits(credits)
+    }
+
+    /// Looks up one of the built in networks by name.
+    pub fn by_name(name: &str) -> Option<NetworkParams> {
+        match name {
+            "mainnet" => Some(NetworkParams::mainnet()),
+            "testnet" => Some(NetworkParams::testnet()),
+            "regtest" => Some(NetworkParams::regtest()),
+            _ => None
+        }
+    }
+
+    /// What blocks and segments on the network are signed for, with `Signed::new_on`.
+    pub fn network_id(&self) -> u32 {
+        self.network_id
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Block version that new blocks are made with.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn costs(&self) -> SegmentCosts {
+        self.costs
+    }
+
+    pub fn credits(&self) -> CreditPolicy {
+        self.credits
+    }
+
+    pub fn limits(&self) -> BlockLimits {
+        self.limits
+    }
+
+    pub fn genesis(&self) -> &SignedBlock {
+        &self.genesis
+    }
+
+    /// Address of the genesis block, which is what the first blocks on the network point to.
+    pub fn genesis_address(&self) -> Address {
+        Address::of_bincomp(&self.genesis)
+    }
+
+    /// If the parameters make sense.  The network needs an id that signatures can be bound to,
+    /// stamps have to be possible to mint, and the genesis block has to be at height 0 with no
+    /// parents, and has to have the right merkle root.
+    pub fn is_sane(&self) -> bool {
+
+        if self.network_id == sig::NO_NETWORK || !self.limits.is_sane() {
+            return false;
+        }
+
+        if self.credits.stamp_bits > MAX_STAMP_BITS {
+            return false;
+        }
+
+        let block = self.genesis.extract_owned();
+        let head = block.get_header().extract_owned();
+        head.block_height() == 0 && head.parents().is_empty() && block.check_merkle_root()
+
+    }
+
+}
+
+/// Makes the genesis block for a new network.  It has a segment with the message in it, so that
+/// networks made at the same time by the same key still have different genesis blocks, and one
+/// declaring the key's identity, which is the first one on the network.
+pub fn make_genesis(kp: Keypair, version: u32, timestamp: i64, message: &str) -> Result<SignedBlock, BuildError> {
+    let decl = IdentityDecl::new(kp.into(), "genesis".into(), None, String::new(), Capabilities::VALIDATOR);
+    BlockBuilder::with_clock(version, move || timestamp)
+        .segment(genesis_message(kp, timestamp, message))
+        .segment(Signed::new(kp, Segment::new(SegmentContent::IdentDecl(decl), timestamp)))
+        .build(kp)
+}
+
+fn genesis_message(kp: Keypair, timestamp: i64, message: &str) -> Signed<Segment> {
+    let ad = ArtifactData::new(0, message.as_bytes().to_vec());
+    Signed::new(kp, Segment::new(SegmentContent::Artifact(ad), timestamp))
+}
+
+fn builtin_genesis(name: &str, offset: i64, declare: bool) -> SignedBlock {
+    let kp = Scheme::Ed25519.generate(GENESIS_SEED);
+    let msg = format!("jiyunet {} genesis", name);
+    let ts = GENESIS_TIMESTAMP + offset;
+    match declare {
+        true => make_genesis(kp, BLOCK_VERSION, ts, msg.as_str()).unwrap(),
+        false => BlockBuilder::with_clock(BLOCK_VERSION, move || ts).segment(genesis_message(kp, ts, msg.as_str())).build(kp).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::io::BinaryComponent;
+
+    use testutil::*;
+
+    use super::*;
+
+    #[test]
+    fn ck_builtin_networks() {
+
+        let nets = [NetworkParams::mainnet(), NetworkParams::testnet(), NetworkParams::regtest()];
+        for (i, n) in nets.iter().enumerate() {
+            assert!(n.is_sane());
+            assert!(n.network_id() < RESERVED_NETWORK_IDS);
+            assert_eq!(NetworkParams::by_name(n.name()).as_ref(), Some(n));
+            assert!(canonical(n));
+            for m in &nets[i + 1..] {
+                assert!(n.genesis_address() != m.genesis_address());
+            }
+        }
+
+        assert_eq!(NetworkParams::by_name("nope"), None);
+
+        // Everyone has to agree on these forever.
+        assert_eq!(NetworkParams::mainnet().genesis_address().to_string(), "1220e84f3d36731bfd5cf280ffa386d1b9867f1458a9626c0a80edaffa4b232beae4");
+
+    }
+
+    #[test]
+    fn ck_custom_network() {
+
+        let kp = Scheme::Secp256k1.generate(&[5; 32]);
+        let genesis = make_genesis(kp, 7, 1234, "hello").unwrap();
+        let limits = BlockLimits { max_bytes: 1 << 20, ..BlockLimits::DEFAULT };
+        let p = NetworkParams::new(0x1234, "private".into(), genesis.clone()).with_limits(limits);
+
+        assert!(p.is_sane());
+        assert_eq!(p.genesis(), &genesis);
+        assert_eq!(NetworkParams::from_slice_exact(p.to_blob().as_slice()).unwrap(), p);
+
+        assert!(!NetworkParams::new(sig::NO_NETWORK, "private".into(), genesis.clone()).is_sane());
+
+        let loose = BlockLimits { max_bytes: BLOCK_DECODE_LIMITS.max_bytes + 1, ..BlockLimits::DEFAULT };
+        assert!(!p.clone().with_limits(loose).is_sane());
+
+        // Nobody could ever stamp anything.
+        let hard = CreditPolicy { stamp_bits: MAX_STAMP_BITS + 1, ..CreditPolicy::DEFAULT };
+        assert!(!p.clone().with_credits(hard).is_sane());
+
+        // Genesis blocks can't have parents.
+        let child = BlockBuilder::with_clock(7, || 2000).parent_block(&genesis).build(kp).unwrap();
+        assert!(!NetworkParams::new(0x1234, "private".into(), child).is_sane());
+
+    }
+
+    #[test]
+    fn ck_regeneration() {
+
+        let cp = CreditPolicy { regen: 3, regen_cap: 100, ..CreditPolicy::DEFAULT };
+        assert_eq!(cp.regenerate(10, 0), 10);
+        assert_eq!(cp.regenerate(10, 5), 25);
+        assert_eq!(cp.regenerate(90, 5), 100);
+        assert_eq!(cp.regenerate(150, 5), 150);
+        assert_eq!(cp.regenerate(0, u64::MAX), 100);
+
+        // Twice the cost, twice the work.
+        let cp = CreditPolicy { stamp_bits: 10, ..CreditPolicy::DEFAULT };
+        assert_eq!(cp.stamp_bits_for(0), 10);
+        assert_eq!(cp.stamp_bits_for(STAMP_BASE_COST - 1), 10);
+        assert_eq!(cp.stamp_bits_for(STAMP_BASE_COST), 11);
+        assert_eq!(cp.stamp_bits_for(STAMP_BASE_COST * 2 - 1), 11);
+        assert_eq!(cp.stamp_bits_for(STAMP_BASE_COST * 2), 12);
+        assert_eq!(cp.stamp_bits_for(STAMP_BASE_COST * 1024), 21);
+        assert_eq!(cp.stamp_bits_for(u64::MAX), 68);
+
+    }
+
+}
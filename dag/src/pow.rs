@@ -0,0 +1,157 @@
+//! Proof-of-work stamps on segments, so that someone without any credits can still post by doing
+//! some work instead.  A stamp is a nonce that, hashed along with the whole signed segment, gives
+//! a hash that starts with enough zero bits.  Since the signature's in there too, a stamp can't be
+//! moved onto someone else's segment.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use core::io::BinaryComponent;
+use core::sig::{Hash, Signed};
+
+use segment::{Segment, SignedSegment};
+
+/// Mixed into stamp hashes so the work can't be reused for anything else.
+const STAMP_DOMAIN: &[u8] = b"jiyunet/pow-stamp/v1";
+
+/// Most work a stamp can be minted with.  Past this there might not be a nonce that works.
+pub const MAX_STAMP_BITS: u8 = 64;
+
+/// A nonce that proves some amount of work was done on a signed segment.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, BinaryComponent)]
+pub struct PowStamp {
+    nonce: u64
+}
+
+impl PowStamp {
+
+    pub fn new(nonce: u64) -> PowStamp {
+        PowStamp { nonce }
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Finds the first stamp for the segment with at least the given number of bits of work.
+    /// Takes about `2^bits` tries.  Asking for more than `MAX_STAMP_BITS` gets nothing.
+    pub fn mint(seg: &Signed<Segment>, bits: u8) -> Option<PowStamp> {
+
+        if bits > MAX_STAMP_BITS {
+            return None;
+        }
+
+        let mut buf = stamp_buf(seg);
+        let mut nonce = 0;
+        loop {
+            if leading_zeros(stamp_hash(&mut buf, nonce)) >= bits as u32 {
+                return Some(PowStamp::new(nonce));
+            }
+            nonce = nonce.checked_add(1).expect("ran out of nonces");
+        }
+
+    }
+
+    /// How many bits of work the stamp proves for the segment, which is the number of leading zero
+    /// bits in its hash.
+    pub fn work(&self, seg: &Signed<Segment>) -> u32 {
+        leading_zeros(stamp_hash(&mut stamp_buf(seg), self.nonce))
+    }
+
+}
+
+impl SignedSegment {
+
+    /// Stamps the signed segment with at least the given number of bits of work, if it's no more
+    /// than `MAX_STAMP_BITS`.
+    pub fn mint_stamped(seg: Signed<Segment>, bits: u8) -> Option<SignedSegment> {
+        PowStamp::mint(&seg, bits).map(|stamp| SignedSegment::Stamped(seg, stamp))
+    }
+
+    /// How much work the segment's stamp proves, if it has one.
+    pub fn stamp_work(&self) -> Option<u32> {
+        match *self {
+            SignedSegment::Stamped(ref s, ref stamp) => Some(stamp.work(s)),
+            _ => None
+        }
+    }
+
+}
+
+/// The domain and the segment, with room for the nonce at the end.
+fn stamp_buf(seg: &Signed<Segment>) -> Vec<u8> {
+    let mut buf = STAMP_DOMAIN.to_vec();
+    buf.extend(seg.to_blob());
+    buf.extend_from_slice(&[0; 8]);
+    buf
+}
+
+fn stamp_hash(buf: &mut Vec<u8>, nonce: u64) -> Hash {
+    let n = buf.len();
+    BigEndian::write_u64(&mut buf[n - 8..], nonce);
+    Hash::of_slice(buf.as_slice())
+}
+
+fn leading_zeros(h: Hash) -> u32 {
+    let mut bits = 0;
+    for b in h.into_array().iter() {
+        bits += b.leading_zeros();
+        if *b != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::sig::Scheme;
+
+    use artifact::ArtifactData;
+    use segment::SegmentContent;
+
+    use super::*;
+
+    fn post(i: u8) -> Signed<Segment> {
+        let kp = Scheme::Ed25519.generate(&[i; 32]);
+        Signed::new(kp, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![i])), 0))
+    }
+
+    #[test]
+    fn ck_mint_stamps() {
+
+        let seg = post(1);
+        let stamp = PowStamp::mint(&seg, 12).unwrap();
+        assert!(stamp.work(&seg) >= 12);
+
+        // It's the first one that works, so none of the ones before it do.
+        assert!((0..stamp.nonce()).all(|n| PowStamp::new(n).work(&seg) < 12));
+
+        // And it's no good on anything else.
+        assert!(stamp.work(&post(2)) < 12);
+
+        let ss = SignedSegment::mint_stamped(seg.clone(), 8).unwrap();
+        assert!(ss.stamp_work().unwrap() >= 8);
+        assert_eq!(ss.segment(), seg.extract_owned());
+        assert_eq!(SignedSegment::from_slice_exact(ss.to_blob().as_slice()).unwrap(), ss);
+        assert_eq!(SignedSegment::Single(seg.clone()).stamp_work(), None);
+
+        // There might not be a nonce for that much.
+        assert_eq!(PowStamp::mint(&seg, MAX_STAMP_BITS + 1), None);
+        assert_eq!(SignedSegment::mint_stamped(seg, 200), None);
+
+    }
+
+    #[test]
+    fn ck_leading_zeros() {
+
+        let mut a = [0xff; 32];
+        assert_eq!(leading_zeros(Hash::new(a)), 0);
+        a[0] = 0x00;
+        a[1] = 0x1f;
+        assert_eq!(leading_zeros(Hash::new(a)), 11);
+        assert_eq!(leading_zeros(Hash::new([0; 32])), 256);
+
+    }
+
+}
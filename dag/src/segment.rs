@@ -1,62 +1,40 @@
-use byteorder::*; // FIXME
+use std::io::Read;
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
 
 use core::Address;
-use core::io::{BinaryComponent, DecodeError, WrResult};
-use core::sig;
+use core::io::{BinaryComponent, DecodeError, DecodeErrorKind, Decoder, WrResult, encode_frame};
+use core::sig::{KeyResolver, MultiSigned, SigVerificationError, Signed};
 
 use artifact::*;
+use identity::IdentityDecl;
+use pow::PowStamp;
+use {SignedDelegation, SignedKeyRotation, SignedPolicyChange, SignedRevocation};
 
 /// Any kind of data that can be stored in a segment.
 ///
-/// * IdentDecl - Used to declare identities on the network.
+/// * IdentDecl - Used to declare identities on the network, or update their profiles.
 /// * Artifact - Actual on-chain artifact.
 /// * ArtifactPointer - Pointer to an artifact container, off-chain.
-#[derive(Clone, Eq, PartialEq, Debug)]
+/// * Delegation - Lets another key sign things on behalf of an identity.
+/// * KeyRotation - Replaces an identity's key with a new one.
+/// * Revocation - Stops one of an identity's keys from being used.
+/// * PolicyChange - Sets the policy of keys that have to sign for an identity's governance.
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
 pub enum SegmentContent {
-    IdentDecl(sig::Hash),
-    Artifact(ArtifactData),
-    ArtifactPointer(Address)
-}
-
-impl BinaryComponent for SegmentContent {
-
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        use self::SegmentContent::*;
-        match read.read_u8()? {
-            0x00 => Ok(IdentDecl(sig::Hash::from_reader(read)?)),
-            0x01 => Ok(Artifact(ArtifactData::from_reader(read)?)),
-            0x02 => Ok(ArtifactPointer(Address::from_reader(read)?)),
-            _ => Err(DecodeError)
-        }
-    }
-
-    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-
-        use self::SegmentContent::*;
-
-        // Write the specifier.
-        write.write_u8(match *self {
-            IdentDecl(_) => 0x00,
-            Artifact(_) => 0x01,
-            ArtifactPointer(_) => 0x02
-        })?;
-
-        // Write the actual content.
-        match self {
-            &IdentDecl(h) => h.to_writer(write)?,
-            &Artifact(ref ad) => ad.to_writer(write)?,
-            &ArtifactPointer(ap) => ap.to_writer(write)?
-        }
-
-        Ok(())
-
-    }
-
+    #[tag = 0x00] IdentDecl(IdentityDecl),
+    #[tag = 0x01] Artifact(ArtifactData),
+    #[tag = 0x02] ArtifactPointer(Address),
+    #[tag = 0x03] Delegation(SignedDelegation),
+    #[tag = 0x04] KeyRotation(SignedKeyRotation),
+    #[tag = 0x05] Revocation(SignedRevocation),
+    #[tag = 0x06] PolicyChange(SignedPolicyChange)
 }
 
 /// A segment itself, with a timestamp.  See the documentation for Block for more information.  You
 /// probably want to use a `Signed<Segment>` if you're just working with them.
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[signing_domain = "jiyunet/segment/v1"]
 pub struct Segment {
     timestamp: i64,
     content: SegmentContent
@@ -64,6 +42,13 @@ pub struct Segment {
 
 impl Segment {
 
+    pub fn new(content: SegmentContent, ts: i64) -> Segment {
+        Segment {
+            timestamp: ts,
+            content
+        }
+    }
+
     pub fn new_artifact_seg(ad: ArtifactData, ts: i64) -> Segment {
         Segment {
             timestamp: ts,
@@ -71,6 +56,11 @@ impl Segment {
         }
     }
 
+    /// Millisecond UNIX time the segment was made at.
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
     /// Returns the actual segment content.
     pub fn content(&self) -> SegmentContent {
         self.content.clone()
@@ -78,24 +68,101 @@ impl Segment {
 
 }
 
-impl BinaryComponent for Segment {
+impl SegmentContent {
 
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+    /// If it's the kind of segment that manages identities, rather than posting things.  These
+    /// are the only ones that can be multi-signed.
+    pub fn is_governance(&self) -> bool {
+        use self::SegmentContent::*;
+        matches!(*self, Delegation(_) | KeyRotation(_) | Revocation(_) | PolicyChange(_))
+    }
 
-        let ts = read.read_i64::<BigEndian>()?;
-        let cont = SegmentContent::from_reader(read)?;
+}
 
-        Ok(Segment {
-            timestamp: ts,
-            content: cont
-        })
+/// A segment as it's included in a block.  Governance segments can be signed by a threshold of
+/// keys instead of just one, and singly-signed ones can be stamped with proof of work to pay for
+/// them instead of credits.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SignedSegment {
+    Single(Signed<Segment>),
+    Multi(MultiSigned<Segment>),
+    Stamped(Signed<Segment>, PowStamp)
+}
 
+impl SignedSegment {
+
+    /// Returns the segment itself.
+    pub fn segment(&self) -> Segment {
+        match *self {
+            SignedSegment::Single(ref s) => s.extract_owned(),
+            SignedSegment::Multi(ref m) => m.extract_owned(),
+            SignedSegment::Stamped(ref s, _) => s.extract_owned()
+        }
+    }
+
+    /// Checks the signature, or enough of the signatures, looking the keys up with the resolver.
+    pub fn verify_with_resolver<K: KeyResolver>(&self, kr: &K) -> Result<(), SigVerificationError> {
+        match *self {
+            SignedSegment::Single(ref s) => s.verify_with_resolver(kr),
+            SignedSegment::Multi(ref m) => m.verify_with_resolver(kr),
+            SignedSegment::Stamped(ref s, _) => s.verify_with_resolver(kr)
+        }
+    }
+
+    /// Like `verify_with_resolver`, but for signatures made for the network.
+    pub fn verify_with_resolver_on<K: KeyResolver>(&self, kr: &K, network: u32) -> Result<(), SigVerificationError> {
+        match *self {
+            SignedSegment::Single(ref s) => s.verify_with_resolver_on(kr, network),
+            SignedSegment::Multi(ref m) => m.verify_with_resolver_on(kr, network),
+            SignedSegment::Stamped(ref s, _) => s.verify_with_resolver_on(kr, network)
+        }
+    }
+
+}
+
+impl From<Signed<Segment>> for SignedSegment {
+    fn from(s: Signed<Segment>) -> Self {
+        SignedSegment::Single(s)
+    }
+}
+
+impl BinaryComponent for SignedSegment {
+
+    fn decode<R: Read>(read: &mut Decoder<R>) -> Result<Self, DecodeError> {
+        read.frame(|read| {
+            match read.read_u8()? {
+                0x00 => Ok(SignedSegment::Single(Signed::decode(read)?)),
+                0x01 => {
+                    let m = MultiSigned::<Segment>::decode(read)?;
+                    if !m.extract_owned().content().is_governance() {
+                        return Err(DecodeErrorKind::InvalidValue.into());
+                    }
+                    Ok(SignedSegment::Multi(m))
+                },
+                0x02 => Ok(SignedSegment::Stamped(Signed::decode(read)?, PowStamp::decode(read)?)),
+                t => Err(DecodeErrorKind::UnknownDiscriminator(t).into())
+            }
+        })
     }
 
     fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        write.write_i64::<BigEndian>(self.timestamp)?;
-        self.content.to_writer(write)?;
-        Ok(())
+        encode_frame::<Self, _>(|| {
+            match *self {
+                SignedSegment::Single(ref s) => {
+                    write.write_u8(0x00)?;
+                    s.to_writer(write)
+                },
+                SignedSegment::Multi(ref m) => {
+                    write.write_u8(0x01)?;
+                    m.to_writer(write)
+                },
+                SignedSegment::Stamped(ref s, ref stamp) => {
+                    write.write_u8(0x02)?;
+                    s.to_writer(write)?;
+                    stamp.to_writer(write)
+                }
+            }
+        })
     }
 
 }
@@ -106,13 +173,21 @@ mod test {
     use std::io::Cursor;
 
     use core::Address;
-    use core::sig::Hash;
+    use core::io::BinaryComponent;
+    use core::sig::{Scheme, Signed};
 
     use super::*;
 
     use artifact::ArtifactData;
+    use identity::Capabilities;
     use segment::Segment;
     use segment::SegmentContent::*;
+    use testutil::*;
+
+    fn alice() -> IdentityDecl {
+        let kp = Scheme::Ed25519.generate(&[1; 32]);
+        IdentityDecl::new(kp.into(), "alice".into(), None, "".into(), Capabilities::POSTER)
+    }
 
     fn encode_and_decode<T: BinaryComponent>(t: T) -> T {
 
@@ -124,12 +199,37 @@ mod test {
 
     }
 
+    quickcheck! {
+
+        fn prop_content_canonical(c: Arb<SegmentContent>, idx: usize, byte: u8) -> bool {
+            canonical(&c.0) && mutant_canonical(&c.0, idx, byte) && strict_bounds(&c.0)
+        }
+
+        fn prop_segment_canonical(s: Arb<Segment>, idx: usize, byte: u8) -> bool {
+            canonical(&s.0) && mutant_canonical(&s.0, idx, byte) && strict_bounds(&s.0)
+        }
+
+        fn prop_signed_segment_canonical(s: Arb<Signed<Segment>>, idx: usize, byte: u8) -> bool {
+            canonical(&s.0) && mutant_canonical(&s.0, idx, byte) && strict_bounds(&s.0)
+        }
+
+        fn prop_signed_segment_enum_canonical(s: Arb<SignedSegment>, idx: usize, byte: u8) -> bool {
+            canonical(&s.0) && mutant_canonical(&s.0, idx, byte) && strict_bounds(&s.0)
+        }
+
+        fn prop_artifact_canonical(spec: u16, body: Vec<u8>, idx: usize, byte: u8) -> bool {
+            let ad = ArtifactData::new(spec, body);
+            canonical(&ad) && mutant_canonical(&ad, idx, byte) && strict_bounds(&ad)
+        }
+
+    }
+
     #[test]
     fn ck_segment_between_blob_1() {
 
         let seg = Segment {
             timestamp: 123456789,
-            content: IdentDecl(Hash::of_slice(&[1, 2, 3, 4]))
+            content: IdentDecl(alice())
         };
 
         assert_eq!(seg, encode_and_decode(seg.clone()));
@@ -160,4 +260,88 @@ mod test {
 
     }
 
+    #[test]
+    fn ck_segment_wire_format() {
+
+        let s1 = Segment::new(IdentDecl(alice()), 123456789);
+        let s2 = Segment::new(Artifact(ArtifactData::new(42, vec![65, 66, 67, 68])), 19101004);
+        let s3 = Segment::new(ArtifactPointer(Address::of_slice(&[1, 3, 3, 7])), 80);
+
+        assert_eq!(hex_blob(&s1), concat!(
+            "00000000075bcd1500008a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c",
+            "05616c69636500000000000001"));
+        assert_eq!(hex_blob(&s2), "000000000123754c01002a000000000000000441424344");
+        assert_eq!(hex_blob(&s3), "0000000000000050021220acb86a9cb70a84f695de89e7fe22819466205759d798d52d4a3dd95b0cdaa2a1");
+
+    }
+
+    #[test]
+    fn ck_segment_bad_tag_error() {
+
+        use core::io::DecodeErrorKind;
+
+        let good = Segment {
+            timestamp: 80,
+            content: ArtifactPointer(Address::of_slice(&[1, 3, 3, 7]))
+        };
+
+        // Corrupt the content tag of the second segment in the list.
+        let mut blob = vec![good.clone(), good.clone()].to_blob();
+        let seg_len = good.to_blob().len();
+        blob[8 + seg_len + 8] = 0x7f;
+
+        let err = Vec::<Segment>::from_slice(blob.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::UnknownDiscriminator(0x7f));
+        assert_eq!(err.offset(), Some((8 + seg_len + 9) as u64));
+        assert_eq!(err.path(), &["Vec<Segment>[1]", "Segment", "SegmentContent"]);
+
+    }
+
+    #[test]
+    fn ck_segment_length_bombs() {
+
+        use core::io::{DecodeErrorKind, Limit};
+
+        // An artifact claiming to be ~16 EiB long.
+        let mut blob = vec![0, 0, 0, 0, 0, 0, 0, 80, 0x01, 0, 42];
+        blob.extend_from_slice(&[0xff; 8]);
+        let err = Segment::from_slice(blob.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::LimitExceeded(Limit::Bytes));
+
+        // A list claiming to have 2^64 - 1 segments in it.
+        let err = Vec::<Segment>::from_slice(&[0xff; 9]).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::LimitExceeded(Limit::Length));
+
+    }
+
+    #[test]
+    fn ck_multisigned_governance_only() {
+
+        use core::io::DecodeErrorKind;
+        use core::sig::{MultiSigned, SigPolicy};
+        use identity::Revocation;
+
+        let kps: Vec<_> = (0..2u8).map(|i| Scheme::Ed25519.generate(&[i; 32])).collect();
+        let policy = SigPolicy::new(2, kps.iter().map(|kp| (*kp).into()).collect()).unwrap();
+
+        let rev = Signed::new(kps[0], Revocation::new(kps[1].into(), 1));
+        let mut ms = MultiSigned::new(policy.clone(), Segment::new(Revocation(rev), 0));
+        ms.sign(kps[0]).unwrap();
+        let gov = SignedSegment::Multi(ms);
+        assert_eq!(SignedSegment::from_slice_exact(gov.to_blob().as_slice()).unwrap(), gov);
+
+        // Posts still need to be signed by just the one key.
+        let post = MultiSigned::new(policy, Segment::new(Artifact(ArtifactData::new(0, vec![1])), 0));
+        let err = SignedSegment::from_slice_exact(SignedSegment::Multi(post).to_blob().as_slice()).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::InvalidValue);
+
+        let single: SignedSegment = Signed::new(kps[0], Segment::new(ArtifactPointer(Address::of_slice(&[1])), 0)).into();
+        let mut blob = single.to_blob();
+        assert_eq!(blob[0], 0x00);
+        blob[0] = 0x03;
+        let err = SignedSegment::from_slice_exact(blob.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), DecodeErrorKind::UnknownDiscriminator(0x03));
+
+    }
+
 }
@@ -0,0 +1,121 @@
+//! Helpers for the canonical encoding property tests.
+
+use quickcheck::{Arbitrary, Gen};
+
+use core::Address;
+use core::io::BinaryComponent;
+use core::sig::{Hash, HashAlgo, Keypair, MultiSigned, Scheme, SigPolicy, Signed};
+
+use artifact::ArtifactData;
+use container::ArtifactContainer;
+use delegation;
+use identity;
+use pow::PowStamp;
+use segment::{Segment, SegmentContent, SignedSegment};
+
+pub use core::testutil::{canonical, hex_blob, mutant_canonical, strict_bounds};
+
+/// Wrapper so we can generate arbitrary instances of things for quickcheck.
+#[derive(Clone, Debug)]
+pub struct Arb<T>(pub T);
+
+pub fn gen_hash(g: &mut Gen) -> Hash {
+    Hash::of_slice(Vec::<u8>::arbitrary(g).as_slice())
+}
+
+pub fn gen_address(g: &mut Gen) -> Address {
+    let algo = *g.choose(&[HashAlgo::Sha256, HashAlgo::Blake2b256, HashAlgo::Blake3]).unwrap();
+    Address::with_algo(algo, gen_hash(g))
+}
+
+pub fn gen_keypair(g: &mut Gen) -> Keypair {
+    Scheme::Ed25519.generate(Vec::<u8>::arbitrary(g).as_slice())
+}
+
+pub fn gen_content(g: &mut Gen) -> SegmentContent {
+    use segment::SegmentContent::*;
+    match u8::arbitrary(g) % 7 {
+        0 => {
+            let avatar = match bool::arbitrary(g) {
+                true => Some(gen_address(g)),
+                false => None
+            };
+            let name: String = String::arbitrary(g).chars().take(identity::MAX_NAME_LEN / 4).collect();
+            let bio: String = String::arbitrary(g).chars().take(identity::MAX_BIO_LEN / 4).collect();
+            let caps = identity::Capabilities::from_bits(u32::arbitrary(g));
+            IdentDecl(identity::IdentityDecl::new(gen_keypair(g).into(), name, avatar, bio, caps))
+        },
+        1 => Artifact(ArtifactData::new(u16::arbitrary(g), Vec::arbitrary(g))),
+        2 => ArtifactPointer(gen_address(g)),
+        3 => {
+            let d = delegation::Delegation::new(gen_keypair(g).into(), Option::arbitrary(g), u64::arbitrary(g));
+            Delegation(gen_signed(g, d))
+        },
+        4 => {
+            let r = identity::KeyRotation::new(gen_keypair(g).into(), u64::arbitrary(g));
+            KeyRotation(gen_signed(g, r))
+        },
+        5 => {
+            let r = identity::Revocation::new(gen_keypair(g).into(), u64::arbitrary(g));
+            Revocation(gen_signed(g, r))
+        },
+        _ => {
+            let kps: Vec<Keypair> = (0..(u8::arbitrary(g) % 3 + 1)).map(|_| gen_keypair(g)).collect();
+            let policy = SigPolicy::new(u8::arbitrary(g) % kps.len() as u8 + 1, kps.iter().map(|kp| (*kp).into()).collect());
+            let r = identity::PolicyChange::new(policy.filter(|_| bool::arbitrary(g)), u64::arbitrary(g));
+            PolicyChange(gen_signed(g, r))
+        }
+    }
+}
+
+pub fn gen_signed<T: BinaryComponent>(g: &mut Gen, t: T) -> Signed<T> {
+    Signed::new(gen_keypair(g), t)
+}
+
+impl Arbitrary for Arb<SegmentContent> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Arb(gen_content(g))
+    }
+}
+
+impl Arbitrary for Arb<Segment> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Arb(Segment::new(gen_content(g), i64::arbitrary(g)))
+    }
+}
+
+impl Arbitrary for Arb<Signed<Segment>> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let Arb(seg) = Arb::<Segment>::arbitrary(g);
+        Arb(gen_signed(g, seg))
+    }
+}
+
+/// Makes a multi-signed segment with some of its signers, so it might not meet its threshold.
+pub fn gen_multisigned(g: &mut Gen, seg: Segment) -> MultiSigned<Segment> {
+    let kps: Vec<Keypair> = (0..(u8::arbitrary(g) % 4 + 1)).map(|_| gen_keypair(g)).collect();
+    let policy = SigPolicy::new(u8::arbitrary(g) % kps.len() as u8 + 1, kps.iter().map(|kp| (*kp).into()).collect()).unwrap();
+    let mut ms = MultiSigned::new(policy, seg);
+    for kp in kps.iter().filter(|_| bool::arbitrary(g)) {
+        ms.sign(*kp).unwrap();
+    }
+    ms
+}
+
+impl Arbitrary for Arb<SignedSegment> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let Arb(seg) = Arb::<Segment>::arbitrary(g);
+        match seg.content().is_governance() {
+            true if bool::arbitrary(g) => Arb(SignedSegment::Multi(gen_multisigned(g, seg))),
+            _ if bool::arbitrary(g) => Arb(SignedSegment::Stamped(gen_signed(g, seg), PowStamp::new(u64::arbitrary(g)))),
+            _ => Arb(SignedSegment::Single(gen_signed(g, seg)))
+        }
+    }
+}
+
+impl Arbitrary for Arb<Signed<ArtifactContainer>> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let ac = ArtifactContainer::new(u32::arbitrary(g), i64::arbitrary(g), gen_content(g));
+        Arb(gen_signed(g, ac))
+    }
+}
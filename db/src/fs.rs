@@ -3,7 +3,6 @@ use std::fs;
 use std::io::{Read, Write};
 
 use core::Address;
-use core::io::BinaryComponent;
 use BlobSource;
 
 /// Stores blobs using some directory, with a root specified.
@@ -40,14 +39,11 @@ impl BlobSource for FsBlobSource {
     fn put(&self, addr: Address, blob: Vec<u8>) -> Result<(), ()> {
 
         let path = addr_to_path(self.root.clone(), addr);
-        if !fs::metadata(path.clone()).is_ok() {
-            match fs::File::create(path.clone()) {
-                Ok(_) => {}, // Intentionally do nothing.
-                Err(_) => return Err(()) // FIXME Make this better.
-            }
+        if let Some(Err(_)) = path.parent().map(fs::create_dir_all) {
+            return Err(()); // FIXME Make this better.
         }
 
-        match fs::File::open(path) { // Don't need a .clone() as this is the last use.
+        match fs::File::create(path) { // Don't need a .clone() as this is the last use.
             Ok(mut f) => match f.write_all(blob.as_slice()) {
                 Ok(_) => Ok(()),
                 Err(_) => Err(()) // FIXME Make this better.c
@@ -61,10 +57,13 @@ impl BlobSource for FsBlobSource {
 
 const BTREE_SPLIT: usize = 4; // sqrt(sizeof(sha256_hash)).  Also not technically for a B-Tree.
 
+/// Each hash algorithm gets its own directory, like `root/sha2-256/cafebabe/...`, so that the
+/// same digest from different algorithms can't collide.
 fn addr_to_path(root: PathBuf, addr: Address) -> PathBuf {
 
     let mut path = root.clone();
-    let hex = addr.get_hash().into_array();
+    let hex = addr.digest().into_array();
+    path.push(addr.algo().name());
     path.push(slice_to_hexadecimal(&hex[..BTREE_SPLIT]));
     path.push(slice_to_hexadecimal(&hex[BTREE_SPLIT..]));
     path
@@ -105,7 +104,43 @@ fn slice_to_hexadecimal(slice: &[u8]) -> String {
 #[cfg(test)]
 mod test {
 
+    use std::env;
+    use std::path::PathBuf;
+
+    use core::Address;
+    use core::sig::HashAlgo;
+
     use fs;
+    use BlobSource;
+
+    #[test]
+    fn ck_addr_to_path_layout() {
+
+        let a = Address::of_slice_with(HashAlgo::Blake3, b"abc");
+        let p = fs::addr_to_path(PathBuf::from("/db"), a);
+        assert_eq!(p, PathBuf::from("/db/blake3/6437b3ac/38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"));
+
+        let b = Address::with_algo(HashAlgo::Sha256, a.digest());
+        assert!(fs::addr_to_path(PathBuf::from("/db"), b) != p);
+
+    }
+
+    #[test]
+    fn ck_put_get() {
+
+        let root = env::temp_dir().join(format!("jiyu-fs-test-{}", ::std::process::id()));
+        let src = fs::FsBlobSource::new(root.clone());
+
+        for algo in [HashAlgo::Sha256, HashAlgo::Blake2b256, HashAlgo::Blake3] {
+            let a = Address::of_slice_with(algo, b"some data");
+            src.put(a, b"some data".to_vec()).unwrap();
+            assert_eq!(src.get(a), Some(b"some data".to_vec()));
+        }
+
+        assert_eq!(src.get(Address::of_slice(b"other data")), None);
+        ::std::fs::remove_dir_all(root).unwrap();
+
+    }
 
     #[test]
     fn test_slice_to_hexadecimal_1() {
@@ -2,6 +2,10 @@ extern crate jiyunet_core as core;
 extern crate jiyunet_dag as dag;
 
 pub mod fs;
+pub mod traverse;
+
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
 
 use core::Address;
 use dag::DagNode;
@@ -23,8 +27,10 @@ pub struct NodeSource<S> where S: BlobSource {
 }
 
 /// Some kind of error in finding a node from the datastore.
+#[derive(Debug)]
 pub enum NodeGetError {
     NotFound,
+    AddressMismatch,
     DecodeError(core::io::DecodeError)
 }
 
@@ -35,10 +41,12 @@ impl<S> NodeSource<S> where S: BlobSource {
         NodeSource { source: src }
     }
 
-    /// Returns the node with the given address, if possible.
+    /// Returns the node with the given address, if possible.  Fails if what the source gave back
+    /// doesn't actually hash to the address.
     pub fn get<N: DagNode>(&self, addr: Address) -> Result<N, NodeGetError> {
         match self.source.get(addr) {
-            Some(b) => N::from_slice(b.as_slice()).map_err(|e| NodeGetError::DecodeError(e)),
+            Some(ref b) if !addr.verify(b.as_slice()) => Err(NodeGetError::AddressMismatch),
+            Some(b) => N::from_slice_exact(b.as_slice()).map_err(NodeGetError::DecodeError),
             None => Err(NodeGetError::NotFound)
         }
     }
@@ -0,0 +1,75 @@
+//! An in-memory blob store, and helpers for building DAGs in it to test against.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use core::Address;
+use core::sig::Scheme;
+use dag::{DagNode, SignedBlock};
+use dag::builder::BlockBuilder;
+use dag::params::REGTEST_ID;
+use dag::segment::SignedSegment;
+
+use {BlobSource, NodeSource};
+
+/// Keeps blobs in a `HashMap`.
+pub struct MemBlobSource(RefCell<HashMap<Address, Vec<u8>>>);
+
+impl MemBlobSource {
+
+    pub fn new() -> MemBlobSource {
+        MemBlobSource(RefCell::new(HashMap::new()))
+    }
+
+}
+
+impl Default for MemBlobSource {
+    fn default() -> Self {
+        MemBlobSource::new()
+    }
+}
+
+impl BlobSource for MemBlobSource {
+
+    fn get(&self, addr: Address) -> Option<Vec<u8>> {
+        self.0.borrow().get(&addr).cloned()
+    }
+
+    fn put(&self, addr: Address, blob: Vec<u8>) -> Result<(), ()> {
+        self.0.borrow_mut().insert(addr, blob);
+        Ok(())
+    }
+
+}
+
+/// Builds a DAG, where each block is given by the indexes of its parents in the list.  If there's
+/// a genesis block it's used for the first one.  Blocks are signed for regtest.
+pub fn build(genesis: Option<SignedBlock>, shape: &[&[usize]]) -> (NodeSource<MemBlobSource>, Vec<SignedBlock>, Vec<Address>) {
+    build_with(genesis, shape, |_| Vec::new())
+}
+
+/// Like `build`, but with the segments for each block from the function.
+pub fn build_with<F>(genesis: Option<SignedBlock>, shape: &[&[usize]], segs: F) -> (NodeSource<MemBlobSource>, Vec<SignedBlock>, Vec<Address>)
+        where F: Fn(usize) -> Vec<SignedSegment> {
+
+    let kp = Scheme::Ed25519.generate(&[1; 32]);
+    let src = NodeSource::new(MemBlobSource::new());
+    let mut blocks: Vec<SignedBlock> = Vec::new();
+    let base = genesis.as_ref().map_or(0, |g| g.timestamp());
+
+    for (i, ps) in shape.iter().enumerate() {
+        let b = match genesis {
+            Some(ref g) if i == 0 => g.clone(),
+            _ => ps.iter().fold(BlockBuilder::with_clock(1, move || base + i as i64).network(REGTEST_ID), |bb, p| bb.parent_block(&blocks[*p]))
+                .segments(segs(i))
+                .build(kp)
+                .unwrap()
+        };
+        src.put(b.clone()).unwrap();
+        blocks.push(b);
+    }
+
+    let addrs = blocks.iter().map(Address::of_bincomp).collect();
+    (src, blocks, addrs)
+
+}
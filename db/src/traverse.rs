@@ -0,0 +1,431 @@
+//! Walking the DAG backwards through block parents.
+//!
+//! Everything here only needs the block headers, so they're cached once they've been decoded.
+//! Heights are used to cut searches short, since a block's parents are always lower than it.  They
+//! don't come from the headers, since the blocks might not have been validated yet, and a header
+//! could say anything.  Instead they're worked out from the parents and cached too.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use core::Address;
+use dag::SignedBlock;
+use dag::block::BlockHeader;
+
+use {BlobSource, NodeGetError, NodeSource};
+
+/// Which order ancestors are visited in.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Order {
+
+    /// Closest first, one generation at a time.
+    BreadthFirst,
+
+    /// All the way down one line of parents before the next.
+    DepthFirst
+
+}
+
+/// A block that couldn't be read while traversing, and why.
+#[derive(Debug)]
+pub struct TraverseError(pub Address, pub NodeGetError);
+
+/// Answers questions about the shape of the DAG, reading blocks from a `NodeSource`.
+pub struct Traverser<'a, S> where S: BlobSource + 'a {
+    source: &'a NodeSource<S>,
+    headers: RefCell<HashMap<Address, BlockHeader>>,
+    heights: RefCell<HashMap<Address, u64>>
+}
+
+impl<'a, S> Traverser<'a, S> where S: BlobSource {
+
+    pub fn new(source: &'a NodeSource<S>) -> Traverser<'a, S> {
+        Traverser {
+            source,
+            headers: RefCell::new(HashMap::new()),
+            heights: RefCell::new(HashMap::new())
+        }
+    }
+
+    /// Gets the header of the block at the address, from the cache if we've seen it before.
+    pub fn header(&self, addr: Address) -> Result<BlockHeader, TraverseError> {
+
+        if let Some(h) = self.headers.borrow().get(&addr) {
+            return Ok(h.clone());
+        }
+
+        let block: SignedBlock = self.source.get(addr).map_err(|e| TraverseError(addr, e))?;
+        let head = block.extract().get_header().extract_owned();
+        self.headers.borrow_mut().insert(addr, head.clone());
+        Ok(head)
+
+    }
+
+    pub fn parents(&self, addr: Address) -> Result<Vec<Address>, TraverseError> {
+        self.header(addr).map(|h| h.parents())
+    }
+
+    /// The block's actual height, which is one more than its highest parent, whatever its header
+    /// says.  Blocks without parents are at 0.
+    pub fn height(&self, addr: Address) -> Result<u64, TraverseError> {
+
+        // Parents have to be worked out first, so keep going down until we get to ones we know.
+        let mut stack = vec![addr];
+        while let Some(&b) = stack.last() {
+
+            if self.heights.borrow().contains_key(&b) {
+                stack.pop();
+                continue;
+            }
+
+            let parents = self.parents(b)?;
+            let heights = self.heights.borrow();
+            let missing: Vec<Address> = parents.iter().filter(|p| !heights.contains_key(p)).cloned().collect();
+            if !missing.is_empty() {
+                drop(heights);
+                stack.extend(missing);
+                continue;
+            }
+
+            let h = parents.iter().map(|p| heights[p] + 1).max().unwrap_or(0);
+            drop(heights);
+            self.heights.borrow_mut().insert(b, h);
+            stack.pop();
+
+        }
+
+        Ok(self.heights.borrow()[&addr])
+
+    }
+
+    /// Number of headers in the cache.
+    pub fn cached(&self) -> usize {
+        self.headers.borrow().len()
+    }
+
+    /// Iterates over the ancestors of the block, not including itself, with how many generations
+    /// back they are.  Each one only comes up once.  With a depth limit, ancestors further back
+    /// than it aren't visited.
+    pub fn ancestors(&self, start: Address, order: Order, max_depth: Option<u32>) -> Ancestors<'_, 'a, S> {
+        Ancestors {
+            trav: self,
+            order,
+            max_depth,
+            frontier: vec![(start, 0)].into_iter().collect(),
+            seen: vec![start].into_iter().collect(),
+            skip_start: true,
+            failed: false
+        }
+    }
+
+    /// Checks if `a` is an ancestor of `b`.  Blocks aren't their own ancestors.
+    pub fn is_ancestor(&self, a: Address, b: Address) -> Result<bool, TraverseError> {
+
+        let ah = self.height(a)?;
+        let mut frontier = vec![b];
+        let mut seen = HashSet::new();
+
+        while let Some(n) = frontier.pop() {
+            for p in self.parents(n)? {
+                if p == a {
+                    return Ok(true);
+                }
+                // Anything at or below a's height can't have it as an ancestor.
+                if seen.insert(p) && self.height(p)? > ah {
+                    frontier.push(p);
+                }
+            }
+        }
+
+        Ok(false)
+
+    }
+
+    /// Finds all of the ancestors of the block, including itself.
+    pub fn past(&self, start: Address) -> Result<HashSet<Address>, TraverseError> {
+        let mut set = HashSet::new();
+        set.insert(start);
+        for r in self.ancestors(start, Order::DepthFirst, None) {
+            set.insert(r?.0);
+        }
+        Ok(set)
+    }
+
+    /// Finds the lowest common ancestors of two blocks, which are the blocks that are ancestors of
+    /// both (or are one of them) but aren't ancestors of any other common ancestor.  In a DAG
+    /// there can be more than one.  They're sorted by address.
+    pub fn lowest_common_ancestors(&self, a: Address, b: Address) -> Result<Vec<Address>, TraverseError> {
+
+        let aa = self.past(a)?;
+        let common: HashSet<Address> = self.past(b)?.into_iter().filter(|x| aa.contains(x)).collect();
+
+        // Common ancestors have all of their ancestors in common too, so anything that's a parent
+        // of one of them can't be lowest.
+        let mut covered = HashSet::new();
+        for c in &common {
+            covered.extend(self.parents(*c)?);
+        }
+
+        let mut lcas: Vec<Address> = common.into_iter().filter(|c| !covered.contains(c)).collect();
+        lcas.sort();
+        Ok(lcas)
+
+    }
+
+    /// Orders the blocks from the heads back, up to the depth limit, so that parents always come
+    /// before their children.  Blocks at the same height are ordered by address, so everyone
+    /// gets the same order.
+    pub fn topological_order(&self, heads: &[Address], max_depth: Option<u32>) -> Result<Vec<Address>, TraverseError> {
+
+        let mut blocks: HashSet<Address> = heads.iter().cloned().collect();
+        for h in heads {
+            for r in self.ancestors(*h, Order::BreadthFirst, max_depth) {
+                blocks.insert(r?.0);
+            }
+        }
+
+        let mut keyed = Vec::with_capacity(blocks.len());
+        for b in blocks {
+            keyed.push((self.height(b)?, b));
+        }
+
+        keyed.sort();
+        Ok(keyed.into_iter().map(|(_, b)| b).collect())
+
+    }
+
+    /// Finds the tips among the blocks, which are the ones that aren't an ancestor of any of the
+    /// others.  They're sorted by address.
+    pub fn tips(&self, blocks: &[Address]) -> Result<Vec<Address>, TraverseError> {
+
+        let set: HashSet<Address> = blocks.iter().cloned().collect();
+        let mut covered = HashSet::new();
+        let mut floor = u64::MAX;
+        for b in &set {
+            floor = floor.min(self.height(*b)?);
+        }
+
+        // Only have to look as far back as the lowest of them.
+        for b in &set {
+            let mut frontier = vec![*b];
+            while let Some(n) = frontier.pop() {
+                for p in self.parents(n)? {
+                    if covered.insert(p) && self.height(p)? > floor {
+                        frontier.push(p);
+                    }
+                }
+            }
+        }
+
+        let mut tips: Vec<Address> = set.into_iter().filter(|b| !covered.contains(b)).collect();
+        tips.sort();
+        Ok(tips)
+
+    }
+
+}
+
+/// Iterator over a block's ancestors, made by `Traverser::ancestors`.  It stops after the first
+/// block it can't get.
+pub struct Ancestors<'t, 'a, S> where S: BlobSource + 'a, 'a: 't {
+    trav: &'t Traverser<'a, S>,
+    order: Order,
+    max_depth: Option<u32>,
+    frontier: VecDeque<(Address, u32)>,
+    seen: HashSet<Address>,
+    skip_start: bool,
+    failed: bool
+}
+
+impl<'t, 'a, S> Iterator for Ancestors<'t, 'a, S> where S: BlobSource {
+    type Item = Result<(Address, u32), TraverseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+
+        if self.failed {
+            return None;
+        }
+
+        loop {
+
+            let (addr, depth) = match self.order {
+                Order::BreadthFirst => self.frontier.pop_front()?,
+                Order::DepthFirst => self.frontier.pop_back()?
+            };
+
+            if self.max_depth.is_none_or(|m| depth < m) {
+                let parents = match self.trav.parents(addr) {
+                    Ok(ps) => ps,
+                    Err(e) => {
+                        self.failed = true;
+                        return Some(Err(e));
+                    }
+                };
+
+                // Pushed backwards so that depth first goes down the first parent first.
+                let new: Vec<_> = parents.into_iter().filter(|p| self.seen.insert(*p)).collect();
+                match self.order {
+                    Order::BreadthFirst => self.frontier.extend(new.into_iter().map(|p| (p, depth + 1))),
+                    Order::DepthFirst => self.frontier.extend(new.into_iter().rev().map(|p| (p, depth + 1)))
+                }
+            }
+
+            if self.skip_start {
+                self.skip_start = false;
+                continue;
+            }
+
+            return Some(Ok((addr, depth)));
+
+        }
+
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::Address;
+    use core::sig::Scheme;
+    use dag::builder::BlockBuilder;
+
+    use super::*;
+    use testutil::build;
+
+    //     0
+    //    / \
+    //   1   2
+    //   |\ /|
+    //   | X |
+    //   |/ \|
+    //   3   4
+    //   |   |
+    //   5   6
+    const DIAMOND: &[&[usize]] = &[&[], &[0], &[0], &[1, 2], &[1, 2], &[3], &[4]];
+
+    #[test]
+    fn ck_ancestors() {
+
+        let (src, _, a) = build(None, DIAMOND);
+        let t = Traverser::new(&src);
+
+        let bfs: Vec<_> = t.ancestors(a[5], Order::BreadthFirst, None).map(Result::unwrap).collect();
+        assert_eq!(bfs, vec![(a[3], 1), (a[1], 2), (a[2], 2), (a[0], 3)]);
+
+        let dfs: Vec<_> = t.ancestors(a[5], Order::DepthFirst, None).map(|r| r.unwrap().0).collect();
+        assert_eq!(dfs, vec![a[3], a[1], a[0], a[2]]);
+
+        let near: Vec<_> = t.ancestors(a[5], Order::BreadthFirst, Some(2)).map(|r| r.unwrap().0).collect();
+        assert_eq!(near, vec![a[3], a[1], a[2]]);
+        assert_eq!(t.ancestors(a[0], Order::BreadthFirst, None).count(), 0);
+
+        assert_eq!(t.cached(), 5);
+
+    }
+
+    #[test]
+    fn ck_is_ancestor() {
+
+        let (src, _, a) = build(None, DIAMOND);
+        let t = Traverser::new(&src);
+
+        assert!(t.is_ancestor(a[0], a[6]).unwrap());
+        assert!(t.is_ancestor(a[2], a[5]).unwrap());
+        assert!(!t.is_ancestor(a[5], a[0]).unwrap());
+        assert!(!t.is_ancestor(a[3], a[6]).unwrap());
+        assert!(!t.is_ancestor(a[3], a[3]).unwrap());
+
+    }
+
+    #[test]
+    fn ck_lowest_common_ancestors() {
+
+        let (src, _, a) = build(None, DIAMOND);
+        let t = Traverser::new(&src);
+
+        // Criss-crossing means there's two of them.
+        let mut both = vec![a[1], a[2]];
+        both.sort();
+        assert_eq!(t.lowest_common_ancestors(a[5], a[6]).unwrap(), both);
+        assert_eq!(t.lowest_common_ancestors(a[3], a[4]).unwrap(), both);
+        assert_eq!(t.lowest_common_ancestors(a[1], a[2]).unwrap(), vec![a[0]]);
+        assert_eq!(t.lowest_common_ancestors(a[1], a[5]).unwrap(), vec![a[1]]);
+
+    }
+
+    #[test]
+    fn ck_topological_order_and_tips() {
+
+        let (src, _, a) = build(None, DIAMOND);
+        let t = Traverser::new(&src);
+
+        let order = t.topological_order(&[a[5], a[6]], None).unwrap();
+        assert_eq!(order.len(), 7);
+        for (i, b) in order.iter().enumerate() {
+            for p in t.parents(*b).unwrap() {
+                assert!(order[..i].contains(&p));
+            }
+        }
+
+        assert_eq!(t.topological_order(&[a[5]], Some(1)).unwrap(), vec![a[3], a[5]]);
+
+        let mut tips = vec![a[5], a[6]];
+        tips.sort();
+        assert_eq!(t.tips(&a).unwrap(), tips);
+        assert_eq!(t.tips(&[a[0], a[1], a[4]]).unwrap(), vec![a[4]]);
+        let mut tips = vec![a[3], a[4]];
+        tips.sort();
+        assert_eq!(t.tips(&[a[3], a[4], a[0]]).unwrap(), tips);
+
+    }
+
+    #[test]
+    fn ck_lying_heights() {
+
+        let (src, _, a) = build(None, DIAMOND);
+        let kp = Scheme::Ed25519.generate(&[1; 32]);
+
+        // Says it's at height 1, when it's really on top of 3.  Its child says 2.
+        let liar = BlockBuilder::with_clock(1, || 9).parent(a[3], 0).build(kp).unwrap();
+        let la = Address::of_bincomp(&liar);
+        let child = BlockBuilder::with_clock(1, || 10).parent(la, 1).build(kp).unwrap();
+        let ca = Address::of_bincomp(&child);
+        src.put(liar).unwrap();
+        src.put(child).unwrap();
+        let t = Traverser::new(&src);
+
+        assert_eq!(t.header(la).unwrap().block_height(), 1);
+        assert_eq!(t.height(la).unwrap(), 3);
+        assert_eq!(t.height(ca).unwrap(), 4);
+
+        // None of these get cut short by what the headers say.
+        assert!(t.is_ancestor(a[1], ca).unwrap());
+        assert_eq!(t.tips(&[a[1], ca]).unwrap(), vec![ca]);
+        let order = t.topological_order(&[ca, a[6]], None).unwrap();
+        for (i, b) in order.iter().enumerate() {
+            for p in t.parents(*b).unwrap() {
+                assert!(order[..i].contains(&p));
+            }
+        }
+
+    }
+
+    #[test]
+    fn ck_missing_blocks() {
+
+        let (src, _, a) = build(None, DIAMOND);
+        let kp = Scheme::Ed25519.generate(&[1; 32]);
+        let orphan = BlockBuilder::with_clock(1, || 9).parent(a[0], 0).parent(Address::of_slice(b"missing"), 0).build(kp).unwrap();
+        src.put(orphan.clone()).unwrap();
+        let t = Traverser::new(&src);
+
+        let res: Vec<_> = t.ancestors(Address::of_bincomp(&orphan), Order::BreadthFirst, None).collect();
+        assert_eq!(res.len(), 2);
+        match res.last() {
+            Some(&Err(TraverseError(_, NodeGetError::NotFound))) => {},
+            r => panic!("expected the walk to stop at the missing block, got {:?}", r)
+        }
+
+    }
+
+}
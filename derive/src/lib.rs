@@ -0,0 +1,277 @@
+//! `#[derive(BinaryComponent)]` for structs and enums that are just made of other components.
+//!
+//! Fields are encoded in the order they're declared in, with no padding or field names, which is
+//! exactly what the hand-written impls have always done.  Enums are written as a `u8` tag followed
+//! by the fields of the variant, and every variant needs an explicit tag:
+//!
+//! ```ignore
+//! #[derive(BinaryComponent)]
+//! #[decode_limits = "SOME_LIMITS"]    // optional, see `BinaryComponent::decode_limits`
+//! #[signing_domain = "jiyunet/thing/v1"]  // optional, see `BinaryComponent::signing_domain`
+//! pub enum Thing {
+//!     #[tag = 0x00] Foo(Hash),
+//!     #[tag = 0x01] Bar { #[len_prefix = "u16"] names: Vec<String> }
+//! }
+//! ```
+//!
+//! `#[len_prefix = "..."]` changes the width of the length prefix on a `LengthPrefixed` field
+//! (like a `Vec` or `String`) from the default `u64`.
+//!
+//! The generated code names `jiyunet_core` itself, so it works no matter what the crate using it
+//! calls it.  It can't be used inside of `jiyunet_core`.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use] extern crate quote;
+extern crate syn;
+
+use std::collections::HashSet;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Literal, Span, TokenStream as TokenStream2};
+use syn::{Attribute, Data, DeriveInput, Expr, Fields, Lit, Meta, Type};
+
+#[proc_macro_derive(BinaryComponent, attributes(tag, len_prefix, decode_limits, signing_domain))]
+pub fn derive_binary_component(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(ts) => ts.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+
+    let name = &input.ident;
+
+    // Every type parameter needs to be a component too.
+    let mut generics = input.generics.clone();
+    for tp in generics.type_params_mut() {
+        tp.bounds.push(syn::parse_quote!(_jiyu::io::BinaryComponent));
+    }
+    let (impl_gen, ty_gen, where_clause) = generics.split_for_impl();
+
+    let (dec_body, enc_body) = match input.data {
+        Data::Struct(ref ds) => expand_struct(&ds.fields)?,
+        Data::Enum(ref de) => expand_enum(de)?,
+        Data::Union(_) => return Err(syn::Error::new_spanned(name, "BinaryComponent can't be derived for unions"))
+    };
+
+    let limits = match find_str_attr(&input.attrs, "decode_limits")? {
+        Some(s) => {
+            let e: Expr = s.parse()?;
+            quote! {
+                fn decode_limits() -> _jiyu::io::DecodeLimits {
+                    #e
+                }
+            }
+        },
+        None => quote! {}
+    };
+
+    let domain = match find_str_attr(&input.attrs, "signing_domain")? {
+        Some(s) => quote! {
+            fn signing_domain() -> &'static str {
+                #s
+            }
+        },
+        None => quote! {}
+    };
+
+    Ok(quote! {
+        #[allow(unused_qualifications)]
+        const _: () = {
+
+            extern crate jiyunet_core as _jiyu;
+
+            impl #impl_gen _jiyu::io::BinaryComponent for #name #ty_gen #where_clause {
+
+                #limits
+
+                #domain
+
+                fn decode<R: ::std::io::Read>(read: &mut _jiyu::io::Decoder<R>) -> ::std::result::Result<Self, _jiyu::io::DecodeError> {
+                    read.frame(|read| {
+                        #dec_body
+                    })
+                }
+
+                fn to_writer<W: _jiyu::io::WriteBytesExt>(&self, write: &mut W) -> _jiyu::io::WrResult {
+                    _jiyu::io::encode_frame::<Self, _>(|| {
+                        #enc_body
+                        ::std::result::Result::Ok(())
+                    })
+                }
+
+            }
+
+        };
+    })
+
+}
+
+fn expand_struct(fields: &Fields) -> syn::Result<(TokenStream2, TokenStream2)> {
+
+    let names = binding_names(fields);
+    let decs = decode_fields(fields, &names)?;
+    let cons = construct(quote!(Self), fields, &names);
+
+    let mut encs = Vec::new();
+    for (i, f) in fields.iter().enumerate() {
+        let access = match f.ident {
+            Some(ref id) => quote!(&self.#id),
+            None => {
+                let idx = syn::Index::from(i);
+                quote!(&self.#idx)
+            }
+        };
+        encs.push(encode_field(f, access)?);
+    }
+
+    let dec = quote! {
+        #(#decs)*
+        ::std::result::Result::Ok(#cons)
+    };
+
+    Ok((dec, quote!(#(#encs)*)))
+
+}
+
+fn expand_enum(de: &syn::DataEnum) -> syn::Result<(TokenStream2, TokenStream2)> {
+
+    let mut seen = HashSet::new();
+    let mut dec_arms = Vec::new();
+    let mut enc_arms = Vec::new();
+
+    for v in de.variants.iter() {
+
+        let vname = &v.ident;
+        let tag = match find_attr(&v.attrs, "tag") {
+            Some(Lit::Int(li)) => li.base10_parse::<u8>()?,
+            Some(other) => return Err(syn::Error::new_spanned(other, "tag must be an integer that fits in a u8")),
+            None => return Err(syn::Error::new_spanned(v, "every variant needs a #[tag = ...] attribute"))
+        };
+
+        if !seen.insert(tag) {
+            return Err(syn::Error::new_spanned(v, format!("duplicate tag 0x{:02x}", tag)));
+        }
+
+        let tag_lit = Literal::u8_suffixed(tag);
+        let names = binding_names(&v.fields);
+        let decs = decode_fields(&v.fields, &names)?;
+        let cons = construct(quote!(Self::#vname), &v.fields, &names);
+
+        dec_arms.push(quote! {
+            #tag_lit => {
+                #(#decs)*
+                ::std::result::Result::Ok(#cons)
+            }
+        });
+
+        let mut encs = Vec::new();
+        for (f, n) in v.fields.iter().zip(names.iter()) {
+            encs.push(encode_field(f, quote!(#n))?);
+        }
+
+        let pat = construct(quote!(Self::#vname), &v.fields, &names);
+        enc_arms.push(quote! {
+            #pat => {
+                _jiyu::io::BinaryComponent::to_writer(&#tag_lit, write)?;
+                #(#encs)*
+            }
+        });
+
+    }
+
+    let dec = quote! {
+        match <u8 as _jiyu::io::BinaryComponent>::decode(read)? {
+            #(#dec_arms)*
+            t => ::std::result::Result::Err(_jiyu::io::DecodeErrorKind::UnknownDiscriminator(t).into())
+        }
+    };
+
+    let enc = quote! {
+        match self {
+            #(#enc_arms)*
+        }
+    };
+
+    Ok((dec, enc))
+
+}
+
+/// Local variable names for each of the fields, in order.
+fn binding_names(fields: &Fields) -> Vec<Ident> {
+    (0..fields.len()).map(|i| Ident::new(&format!("__f{}", i), Span::call_site())).collect()
+}
+
+/// Builds `path { a: __f0, ... }`, `path(__f0, ...)`, or just `path`.  Works as a pattern too.
+fn construct(path: TokenStream2, fields: &Fields, names: &[Ident]) -> TokenStream2 {
+    match *fields {
+        Fields::Named(ref fs) => {
+            let ids = fs.named.iter().map(|f| f.ident.clone().unwrap());
+            quote!(#path { #(#ids: #names),* })
+        },
+        Fields::Unnamed(_) => quote!(#path(#(#names),*)),
+        Fields::Unit => path
+    }
+}
+
+fn decode_fields(fields: &Fields, names: &[Ident]) -> syn::Result<Vec<TokenStream2>> {
+
+    let mut out = Vec::new();
+    for (f, n) in fields.iter().zip(names.iter()) {
+        let ty = &f.ty;
+        out.push(match len_prefix(f)? {
+            Some(lt) => quote! {
+                let #n = _jiyu::io::decode_prefixed::<#lt, #ty, _>(read)?;
+            },
+            None => quote! {
+                let #n = <#ty as _jiyu::io::BinaryComponent>::decode(read)?;
+            }
+        });
+    }
+
+    Ok(out)
+
+}
+
+fn encode_field(f: &syn::Field, access: TokenStream2) -> syn::Result<TokenStream2> {
+    let ty = &f.ty;
+    Ok(match len_prefix(f)? {
+        Some(lt) => quote! {
+            _jiyu::io::encode_prefixed::<#lt, #ty, _>(#access, write)?;
+        },
+        None => quote! {
+            _jiyu::io::BinaryComponent::to_writer(#access, write)?;
+        }
+    })
+}
+
+fn len_prefix(f: &syn::Field) -> syn::Result<Option<Type>> {
+    match find_str_attr(&f.attrs, "len_prefix")? {
+        Some(s) => Ok(Some(s.parse()?)),
+        None => Ok(None)
+    }
+}
+
+fn find_str_attr(attrs: &[Attribute], name: &str) -> syn::Result<Option<syn::LitStr>> {
+    match find_attr(attrs, name) {
+        Some(Lit::Str(s)) => Ok(Some(s)),
+        Some(other) => Err(syn::Error::new_spanned(other, format!("{} must be a string", name))),
+        None => Ok(None)
+    }
+}
+
+fn find_attr(attrs: &[Attribute], name: &str) -> Option<Lit> {
+    for a in attrs {
+        if let Meta::NameValue(ref nv) = a.meta {
+            if nv.path.is_ident(name) {
+                if let Expr::Lit(ref el) = nv.value {
+                    return Some(el.lit.clone());
+                }
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,94 @@
+extern crate jiyunet_core as core;
+#[macro_use] extern crate jiyunet_derive;
+
+use core::io::{BinaryComponent, DecodeErrorKind, DecodeLimits, Limit};
+use core::sig::Hash;
+
+const TINY_LIMITS: DecodeLimits = DecodeLimits::new(16, 4, 4);
+
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+struct Named {
+    a: u32,
+    b: Option<String>,
+    #[len_prefix = "u8"]
+    c: Vec<u16>
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+struct Tuple(i64, Hash);
+
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+enum Choice {
+    #[tag = 0x00] Nothing,
+    #[tag = 0x01] One(u8),
+    #[tag = 0x7f] Lots { x: u64, #[len_prefix = "u16"] y: String }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+struct Wrapper<T>(T);
+
+#[derive(Clone, Eq, PartialEq, Debug, BinaryComponent)]
+#[decode_limits = "TINY_LIMITS"]
+#[signing_domain = "test/limited/v1"]
+struct Limited(Vec<u8>);
+
+#[test]
+fn ck_named_format() {
+    let n = Named { a: 7, b: None, c: vec![1, 2] };
+    assert_eq!(n.to_blob(), vec![0, 0, 0, 7, 0, 2, 0, 1, 0, 2]);
+    assert_eq!(Named::from_slice_exact(n.to_blob().as_slice()).unwrap(), n);
+}
+
+#[test]
+fn ck_tuple_format() {
+    let t = Tuple(-1, Hash::new([3; 32]));
+    let mut expected = vec![0xff; 8];
+    expected.extend_from_slice(&[3; 32]);
+    assert_eq!(t.to_blob(), expected);
+    assert_eq!(Tuple::from_slice_exact(expected.as_slice()).unwrap(), t);
+}
+
+#[test]
+fn ck_enum_format() {
+
+    assert_eq!(Choice::Nothing.to_blob(), vec![0x00]);
+    assert_eq!(Choice::One(9).to_blob(), vec![0x01, 9]);
+
+    let l = Choice::Lots { x: 1, y: "hi".into() };
+    assert_eq!(l.to_blob(), vec![0x7f, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 104, 105]);
+
+    for c in [Choice::Nothing, Choice::One(9), l] {
+        assert_eq!(Choice::from_slice_exact(c.to_blob().as_slice()).unwrap(), c);
+    }
+
+}
+
+#[test]
+fn ck_enum_bad_tag() {
+    let err = Choice::from_slice(&[0x02]).unwrap_err();
+    assert_eq!(err.kind(), DecodeErrorKind::UnknownDiscriminator(0x02));
+    assert_eq!(err.path(), &["Choice"]);
+}
+
+#[test]
+fn ck_error_path() {
+    let err = Wrapper::<Named>::from_slice(&[0, 0, 0, 7, 0, 3]).unwrap_err();
+    assert_eq!(err.kind(), DecodeErrorKind::Truncated);
+    assert_eq!(err.path(), &["Wrapper<Named>", "Named", "Vec<u16>[0]"]);
+}
+
+#[test]
+fn ck_prefix_overflow() {
+    let n = Named { a: 0, b: None, c: vec![0; 0x100] };
+    let mut out = Vec::new();
+    assert!(n.to_writer(&mut out).is_err());
+}
+
+#[test]
+fn ck_decode_limits() {
+    assert_eq!(Limited::decode_limits(), TINY_LIMITS);
+    assert_eq!(Limited::signing_domain(), "test/limited/v1");
+    assert_eq!(Named::signing_domain(), "");
+    let err = Limited::from_slice(&[0, 0, 0, 0, 0, 0, 0, 5, 1, 2, 3, 4, 5]).unwrap_err();
+    assert_eq!(err.kind(), DecodeErrorKind::LimitExceeded(Limit::Length));
+}
@@ -1,19 +1,22 @@
 extern crate jiyunet_core as core;
+#[macro_use] extern crate jiyunet_derive;
 
 extern crate byteorder;
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(test)]
+#[macro_use] extern crate quickcheck;
 
 use core::sig;
-use core::io::{DecodeError, WrResult};
+use core::io::DecodeLimits;
 
 pub mod db;
 
 /// Represents a method of connecting to a remote peer.
-#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, BinaryComponent)]
 pub enum ConnectionScheme {
 
     /// A TCP connection.  The string can be an IP address (IPv4 or IPv6) or a hostname.  `(host, port)`
+    #[tag = 0x00]
     Tcp(String, u16)
 
 }
@@ -21,44 +24,12 @@ pub enum ConnectionScheme {
 /// The ping, in milliseconds, between our own node and the other node.
 pub type Ping = Option<u32>;
 
-impl core::io::BinaryComponent for ConnectionScheme {
-
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
-        match read.read_u8()? {
-            0x00 => {
-                let host = String::from_reader(read)?;
-                let port = read.read_u16::<BigEndian>()?;
-                Ok(ConnectionScheme::Tcp(host, port))
-            }
-            _ => Err(DecodeError)
-        }
-    }
-
-    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-
-        use self::ConnectionScheme::*;
-
-        write.write_u8(match self {
-            &Tcp(_, _) => 0x00
-        })?;
-
-        match self {
-            &Tcp(ref host, port) => {
-                host.to_writer(write)?;
-                write.write_u16::<BigEndian>(port)?;
-            }
-        }
-
-        Ok(())
-
-    }
-
-}
-
 /// A record in our local copy of the DHT of some peer we can connect to.
 ///
 /// TODO Develop a method of revoking records.
-#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, BinaryComponent)]
+#[decode_limits = "PEER_RECORD_DECODE_LIMITS"]
+#[signing_domain = "jiyunet/peer-record/v1"]
 pub struct PeerRecord {
 
     /// The self-identified name of this peer.
@@ -75,36 +46,49 @@ pub struct PeerRecord {
 
 }
 
-impl core::io::BinaryComponent for PeerRecord {
-
-    fn from_reader<R: ReadBytesExt>(read: &mut R) -> Result<Self, DecodeError> {
+/// Limits for decoding a peer record.  These get passed around a lot, so they should be small.
+pub const PEER_RECORD_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(1 << 12, 1 << 8, 8);
 
-        let n = Option::<String>::from_reader(read)?;
-        let ep = ConnectionScheme::from_reader(read)?;
-        let expr = read.read_u64::<BigEndian>()?;
-        let pk = sig::ValidationKey::from_reader(read)?;
-
-        Ok(PeerRecord {
-            name: n,
-            endpoint: ep,
-            expiration: expr,
-            pubkey: pk
-        })
+/// Verifies that a signed peer record matches the data it says it should have.
+pub fn verify_peer_record(spr: &sig::Signed<PeerRecord>) -> Result<(), sig::SigVerificationError> {
+    let pk = spr.extract_owned().pubkey;
+    sig::verify_signed(spr, pk)
+}
 
+#[cfg(test)]
+mod test {
+
+    use core::sig::Scheme;
+    use core::testutil::*;
+
+    use super::*;
+
+    #[test]
+    fn ck_peer_record_wire_format() {
+        let pr = PeerRecord {
+            name: Some("node".into()),
+            endpoint: ConnectionScheme::Tcp("127.0.0.1".into(), 8200),
+            expiration: 1500000000000,
+            pubkey: sig::ValidationKey::Ed25519([7; 32])
+        };
+        assert_eq!(hex_blob(&pr), concat!(
+            "0100000000000000046e6f64650000000000000000093132372e302e302e3120080000015d3ef798000007",
+            "07070707070707070707070707070707070707070707070707070707070707"));
     }
 
-    fn to_writer<W: WriteBytesExt>(&self, write: &mut W) -> WrResult {
-        self.name.to_writer(write)?;
-        self.endpoint.to_writer(write)?;
-        write.write_u64::<BigEndian>(self.expiration)?;
-        self.pubkey.to_writer(write)?;
-        Ok(())
-    }
+    quickcheck! {
+
+        fn prop_peer_record_canonical(name: Option<String>, host: String, port: u16, expr: u64, seed: Vec<u8>, idx: usize, byte: u8) -> bool {
+            let pr = PeerRecord {
+                name,
+                endpoint: ConnectionScheme::Tcp(host, port),
+                expiration: expr,
+                pubkey: Scheme::Ed25519.generate(seed.as_slice()).into()
+            };
+            canonical(&pr.endpoint) && mutant_canonical(&pr.endpoint, idx, byte) && strict_bounds(&pr.endpoint)
+                && canonical(&pr) && mutant_canonical(&pr, idx, byte) && strict_bounds(&pr)
+        }
 
-}
+    }
 
-/// Verifies that a signed peer record matches the data it says it should have.
-pub fn verify_peer_record(spr: &sig::Signed<PeerRecord>) -> Result<(), sig::SigVerificationError> {
-    let pk = spr.extract_owned().pubkey;
-    sig::verify_signed(spr, pk)
 }
@@ -10,43 +10,112 @@ use rand::Rng;
 use rand::os::OsRng;
 
 use core::io::BinaryComponent;
+use core::mnemonic::{self, Mnemonic};
 use core::sig;
 
+mod passphrase;
+
 fn main() {
 
     let matches = clap_app!(jiyu_keygen =>
         (version: "0.1.0")
         (author: "treyzania <treyzania@gmail.com>")
         (about: "Generates a Jiyunet keypair.")
-        (@arg dest: "File to write to.  Default: jiyu-keypair.bin"))
+        (@arg scheme: -s --scheme +takes_value "Signature scheme, ed25519 or secp256k1.  Default: ed25519")
+        (@arg no_passphrase: --("no-passphrase") "Write the keypair unencrypted.")
+        (@arg mnemonic: -m --mnemonic "Derive the keypair from a new mnemonic phrase and print it, so it can be restored later.")
+        (@arg seed_passphrase: --("seed-passphrase") "Prompt for an extra passphrase that's mixed in with the mnemonic.")
+        (@arg dest: "File to write to.  Default: jiyu-keypair.bin")
+        (@subcommand restore =>
+            (about: "Regenerates a keypair from its mnemonic phrase.")
+            (@arg scheme: -s --scheme +takes_value "Signature scheme, ed25519 or secp256k1.  Default: ed25519")
+            (@arg no_passphrase: --("no-passphrase") "Write the keypair unencrypted.")
+            (@arg seed_passphrase: --("seed-passphrase") "Prompt for the extra passphrase that was used with the mnemonic.")
+            (@arg dest: "File to write to.  Default: jiyu-keypair.bin")))
         .get_matches();
 
-    let dest = matches.value_of("dest").unwrap_or("jiyu-keypair.bin".into());
-    let mut df = fs::File::create(path::PathBuf::from(dest.clone()).as_path())
-                            .expect("unable to create destination file.");
+    let (matches, restoring) = match matches.subcommand_matches("restore") {
+        Some(m) => (m, true),
+        None => (&matches, false)
+    };
 
-    let mut seed = [0; 4096];
-    let mut rng = match OsRng::new() {
-        Ok(r) => r,
-        Err(e) => panic!("could not initialize RNG: {}", e)
+    let scheme = match sig::Scheme::from_name(matches.value_of("scheme").unwrap_or("ed25519")) {
+        Some(s) => s,
+        None => panic!("unknown signature scheme")
     };
 
-    rng.fill_bytes(&mut seed);
+    // Ask before creating the file so we don't leave an empty one around if they typo it.
+    let pass = match matches.is_present("no_passphrase") {
+        true => None,
+        false => match passphrase::get_passphrase(true) {
+            Ok(ref p) if p.is_empty() => panic!("empty passphrase, pass --no-passphrase if you really want that"),
+            Ok(p) => Some(p),
+            Err(e) => panic!("{}", e)
+        }
+    };
 
-    // Actually generate the seed.
-    let kp = sig::Scheme::Ed25519.generate(&seed);
-    match kp.to_writer(&mut df) {
+    let seed_pass = match matches.is_present("seed_passphrase") {
+        true => match passphrase::prompt_secret("seed passphrase: ", !restoring) {
+            Ok(p) => p,
+            Err(e) => panic!("{}", e)
+        },
+        false => String::new()
+    };
+
+    let kp = if restoring {
+        let phrase = match passphrase::prompt_secret("mnemonic: ", false) {
+            Ok(p) => p,
+            Err(e) => panic!("{}", e)
+        };
+        match phrase.parse::<Mnemonic>() {
+            Ok(m) => m.to_keypair(scheme, seed_pass.as_str()),
+            Err(e) => panic!("invalid mnemonic: {}", e)
+        }
+    } else if matches.is_present("mnemonic") {
+        let m = match Mnemonic::generate(mnemonic::DEFAULT_WORDS) {
+            Ok(m) => m,
+            Err(e) => panic!("could not generate mnemonic: {}", e)
+        };
+        println!("mnemonic: {}", m);
+        println!("write this down and keep it somewhere safe, anyone who has it has your identity");
+        m.to_keypair(scheme, seed_pass.as_str())
+    } else {
+        let mut seed = [0; 4096];
+        let mut rng = match OsRng::new() {
+            Ok(r) => r,
+            Err(e) => panic!("could not initialize RNG: {}", e)
+        };
+        rng.fill_bytes(&mut seed);
+        scheme.generate(&seed)
+    };
+
+    let dest = matches.value_of("dest").unwrap_or("jiyu-keypair.bin");
+    let mut df = fs::File::create(path::PathBuf::from(dest).as_path())
+                            .expect("unable to create destination file.");
+
+    let res = match pass {
+        Some(ref p) => kp.to_encrypted_writer(&mut df, p.as_bytes()).map_err(|e| e.to_string()),
+        None => kp.to_writer(&mut df).map_err(|e| e.to_string())
+    };
+
+    match res {
         Ok(_) => println!("keypair saved to {}", dest),
-        Err(_) => println!("unable to write to destination file")
+        Err(e) => println!("unable to write to destination file: {}", e)
     }
 
-    match kp {
-        sig::Keypair::Ed25519(k, p) => {
-            println!("scheme: ed25519");
-            println!("private key: {}", u8_slice_to_string(&k));
-            println!("public key: {}", u8_slice_to_string(&p));
-        }
+    let fp: sig::Fingerprint = kp.into();
+    println!("scheme: {}", kp.scheme().name());
+    println!("fingerprint: {}", fp.to_short_string());
+    let (k, p) = match kp {
+        sig::Keypair::Ed25519(k, p) => (k.to_vec(), p.to_vec()),
+        sig::Keypair::Secp256k1(k, p) => (k.to_vec(), p.to_vec())
+    };
+
+    // No point encrypting it if we're going to print it anyways.
+    if pass.is_none() {
+        println!("private key: {}", u8_slice_to_string(&k));
     }
+    println!("public key: {}", u8_slice_to_string(&p));
 
 }
 
@@ -9,8 +9,11 @@ use std::io::Read;
 use core::io::BinaryComponent;
 use core::sig::Signed;
 use dag::artifact;
+use dag::params::NetworkParams;
+use dag::pow::MAX_STAMP_BITS;
 use dag::segment;
 
+mod passphrase;
 mod util;
 
 fn main() {
@@ -21,7 +24,9 @@ fn main() {
         (about: "Packages an file into a signed Jiyunet segment.  Note that the segment is not likely to be valid on the blockchain due to noncing, etc.")
         (@arg src: +required "Source file to package.")
         (@arg dest: +required "Output file.")
-        (@arg artifact_type: -a +takes_value "Artifact type.  Default: 0x0000"))
+        (@arg artifact_type: -a +takes_value "Artifact type.  Default: 0x0000")
+        (@arg network: -n --network +takes_value "Name of the network the segment is signed for.  Default: mainnet")
+        (@arg pow: --pow +takes_value "Stamps the segment with proof of work so it can be posted without credits.  Either the bits of work, up to 64, or the name of a network to use its difficulty for a segment that size.  The output is then a stamped segment."))
         .get_matches();
 
     let src = matches.value_of("src").unwrap();
@@ -32,6 +37,11 @@ fn main() {
         None => 0x0000
     };
 
+    let network = match NetworkParams::by_name(matches.value_of("network").unwrap_or("mainnet")) {
+        Some(np) => np,
+        None => panic!("unknown network")
+    };
+
     // Read the source data, convert to artifact.
     let data = {
         let mut f: fs::File = fs::File::open(src).unwrap();
@@ -41,14 +51,38 @@ fn main() {
     };
 
     let art = artifact::ArtifactData::new(atype, data);
+
+    // Artifacts cost a credit a byte, and stamps for them take more work the bigger they are.
+    let cost = art.to_blob().len() as u64;
+    let pow_bits = matches.value_of("pow").map(|v| match (v.parse::<u32>(), NetworkParams::by_name(v)) {
+        (Ok(b), _) => b,
+        (Err(_), Some(np)) => np.credits().stamp_bits_for(cost),
+        (Err(_), None) => panic!("unable to parse bits of work as number or network name")
+    });
+    if let Some(bits) = pow_bits {
+        if bits > MAX_STAMP_BITS as u32 {
+            panic!("{} bits of work is more than a stamp can have (at most {})", bits, MAX_STAMP_BITS);
+        }
+    }
+
     let seg = segment::Segment::new_artifact_seg(art, util::timestamp());
 
     // Load the keypair, then sign.
-    let kp = util::load_user_keypair().expect("keypair not found");
-    let signed_seg = Signed::<segment::Segment>::new(kp, seg);
+    let kp = match util::load_user_keypair() {
+        Ok(kp) => kp,
+        Err(e) => panic!("unable to load keypair: {}", e)
+    };
+    let signed_seg = Signed::<segment::Segment>::new_on(kp, network.network_id(), seg);
 
-    // Write the signed artifact segment.
+    // Write the signed artifact segment, stamping it first if we need to.
     let mut out = fs::File::create(dest).expect("unable to create destination");
-    signed_seg.to_writer(&mut out).expect("unable to write to destination")
+    match pow_bits {
+        Some(bits) => {
+            let stamped = segment::SignedSegment::mint_stamped(signed_seg, bits as u8).expect("too much work to stamp with");
+            stamped.to_writer(&mut out).expect("unable to write to destination");
+            println!("work: {} bits", stamped.stamp_work().unwrap());
+        },
+        None => signed_seg.to_writer(&mut out).expect("unable to write to destination")
+    }
 
 }
@@ -0,0 +1,81 @@
+extern crate jiyunet_core as core;
+extern crate jiyunet_dag as dag;
+
+#[macro_use] extern crate clap;
+
+use std::fs;
+
+use core::io::BinaryComponent;
+use dag::params::{self, BlockLimits, NetworkParams};
+
+mod passphrase;
+mod util;
+
+fn main() {
+
+    let matches = clap_app!(jiyu_mkgenesis =>
+        (version: "0.1.0")
+        (author: "treyzania <treyzania@gmail.com>")
+        (about: "Makes the genesis block and parameters for a private Jiyunet network.")
+        (@arg id: +required "Network id.  Has to be at least 256, lower ones are for the built in networks.")
+        (@arg name: +required "Name of the network.")
+        (@arg dest: +required "File to write the network parameters to.")
+        (@arg keypair: -k --keypair +takes_value "Keypair to sign the genesis block with.  Default: your keypair")
+        (@arg message: -m --message +takes_value "Message to put in the genesis block.  Default: the network name")
+        (@arg timestamp: -t --timestamp +takes_value "Millisecond UNIX time of the genesis block.  Default: now")
+        (@arg max_bytes: --("max-bytes") +takes_value "Largest a block can be, in bytes.")
+        (@arg max_segments: --("max-segments") +takes_value "Most segments a block can have."))
+        .get_matches();
+
+    let id = match matches.value_of("id").unwrap().parse::<u32>() {
+        Ok(id) if id >= params::RESERVED_NETWORK_IDS => id,
+        Ok(_) => panic!("network ids below {} are reserved", params::RESERVED_NETWORK_IDS),
+        Err(_) => panic!("unable to parse network id as number")
+    };
+
+    let name = matches.value_of("name").unwrap();
+    if name.len() > u8::MAX as usize {
+        panic!("network name is too long");
+    }
+
+    let ts = match matches.value_of("timestamp").map(str::parse) {
+        Some(Ok(t)) => t,
+        Some(Err(_)) => panic!("unable to parse timestamp as number"),
+        None => util::timestamp()
+    };
+
+    let mut limits = BlockLimits::DEFAULT;
+    if let Some(v) = matches.value_of("max_bytes") {
+        limits.max_bytes = v.parse().expect("unable to parse max bytes as number");
+    }
+    if let Some(v) = matches.value_of("max_segments") {
+        limits.max_segments = v.parse().expect("unable to parse max segments as number");
+    }
+
+    let kp = match matches.value_of("keypair") {
+        Some(p) => util::load_keypair(p),
+        None => util::load_user_keypair()
+    };
+    let kp = match kp {
+        Ok(kp) => kp,
+        Err(e) => panic!("unable to load keypair: {}", e)
+    };
+
+    let msg = matches.value_of("message").unwrap_or(name);
+    let genesis = match params::make_genesis(kp, params::BLOCK_VERSION, ts, msg) {
+        Ok(g) => g,
+        Err(e) => panic!("unable to make genesis block: {}", e)
+    };
+
+    let np = NetworkParams::new(id, name.into(), genesis).with_limits(limits);
+    if !np.is_sane() {
+        panic!("block limits are larger than blocks can be");
+    }
+
+    let mut out = fs::File::create(matches.value_of("dest").unwrap()).expect("unable to create destination");
+    np.to_writer(&mut out).expect("unable to write to destination");
+
+    println!("network: {} ({})", np.name(), np.network_id());
+    println!("genesis: {}", np.genesis_address());
+
+}
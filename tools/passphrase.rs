@@ -0,0 +1,33 @@
+extern crate rpassword;
+
+use std::env;
+
+/// Environment variable that the passphrase is taken from instead of prompting, if it's set.
+pub const PASSPHRASE_VAR: &str = "JIYU_PASSPHRASE";
+
+/// Gets the keystore passphrase, from the environment or by prompting.  When `confirm` is set the
+/// user has to type it twice.
+pub fn get_passphrase(confirm: bool) -> Result<String, String> {
+
+    if let Ok(p) = env::var(PASSPHRASE_VAR) {
+        return Ok(p);
+    }
+
+    prompt_secret("passphrase: ", confirm)
+
+}
+
+/// Prompts for something without echoing it.  When `confirm` is set the user has to type it twice.
+pub fn prompt_secret(prompt: &str, confirm: bool) -> Result<String, String> {
+
+    let p = rpassword::prompt_password(prompt).map_err(|e| format!("unable to read input: {}", e))?;
+    if confirm {
+        let again = rpassword::prompt_password(format!("confirm {}", prompt)).map_err(|e| format!("unable to read input: {}", e))?;
+        if p != again {
+            return Err("inputs don't match".into());
+        }
+    }
+
+    Ok(p)
+
+}
@@ -2,13 +2,34 @@ extern crate time;
 
 use std::env;
 use std::fs;
+use std::io::Read;
 use std::path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use core::io::BinaryComponent;
+use core::keystore;
 use core::sig;
 
-pub fn load_user_keypair() -> Option<sig::Keypair> {
+use passphrase::get_passphrase;
+
+/// Reads a keypair file, unlocking it if it's encrypted.  Unencrypted ones are still accepted.
+pub fn load_keypair<P: AsRef<path::Path>>(p: P) -> Result<sig::Keypair, String> {
+
+    let mut data = Vec::new();
+    fs::File::open(p.as_ref())
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("unable to read {}: {}", p.as_ref().display(), e))?;
+
+    if !keystore::is_encrypted(data.as_slice()) {
+        return sig::Keypair::from_slice_exact(data.as_slice()).map_err(|e| format!("invalid keypair: {}", e));
+    }
+
+    let pass = get_passphrase(false)?;
+    sig::Keypair::from_encrypted_reader(&mut data.as_slice(), pass.as_bytes()).map_err(|e| e.to_string())
+
+}
+
+pub fn load_user_keypair() -> Result<sig::Keypair, String> {
 
     let kpp = match env::home_dir() {
         Some(mut p) => {
@@ -19,15 +40,7 @@ pub fn load_user_keypair() -> Option<sig::Keypair> {
         None => path::PathBuf::from(".")
     };
 
-    let mut f = match fs::File::open(kpp) {
-        Ok(o) => o,
-        Err(_) => return None
-    };
-
-    match sig::Keypair::from_reader(&mut f) {
-        Ok(kp) => Some(kp),
-        Err(_) => None
-    }
+    load_keypair(kpp)
 
 }
 
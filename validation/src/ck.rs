@@ -1,72 +1,2244 @@
-//! This is the core of the validation code for Jiyunet.  It's highly unfinished, but it will end
-//! up looking not unlike the Ethereum validation code.  The first phase is where blocks only have
-//! their signatures and fingerprints validated (actual credit checking is ignored), before being
-//! added to the full queue, where the rest of the signature data is applied, then the changes to
-//! the blockchain state is committed to storage before the next block goes through the full check.
+//! This is the core of the validation code for Jiyunet.  It will end up looking not unlike the
+//! Ethereum validation code.  Blocks go through two phases.  The first phase, `check_block`, is
+//! everything that doesn't depend on where the block is in the DAG: its size, merkle root,
+//! timestamps, and signatures (actual credit checking is ignored).  Blocks that pass are added to
+//! the queue, and in the second phase they're checked against the blockchain state (their parents,
+//! height, signers, and credits) and applied, before the next block goes through.
+//!
+//! Applying a block costs whoever signed it, and each segment costs whoever posted it, out of
+//! their identity's credits.  Identities regenerate credits with every block that's applied, up
+//! to a cap set by the network.  Balances after each block are kept so they can be looked up
+//! later.  Segments stamped with enough proof of work are paid for with that instead, which is
+//! how new identities get going before they have any credits.
 //!
 //! It's based somewhat on the Parity validation code:
 //! * https://github.com/paritytech/parity/blob/master/ethcore/src/verification/verification.rs
 
-use std::collections::{HashMap, LinkedList};
+use std::collections::{HashMap, LinkedList, VecDeque};
 
 use core::Address;
 use core::io::BinaryComponent;
 use core::sig;
-use core::sig::{Fingerprint, ValidationKey};
+use core::sig::{Fingerprint, KeyResolver, SigPolicy, SigVerificationError, ValidationKey};
 use core::sig::Signed;
 
 use dag::block;
-use dag::segment;
+use dag::builder::{Clock, SystemClock};
+use dag::identity::IdentityDecl;
+use dag::params::{CreditPolicy, NetworkParams};
+use dag::segment::{self, SignedSegment};
+use db::{BlobSource, NodeGetError, NodeSource};
+use db::traverse::{TraverseError, Traverser};
+use dag::{SignedDelegation, SignedKeyRotation, SignedPolicyChange, SignedRevocation};
 
+use order::{HeaviestSubDag, OrderingRule};
+use registry::{IdentityRecord, IdentityRegistry};
 use ValidationError;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+/// An identity, which is always known by the fingerprint of the key it was declared with.
+#[derive(Clone, Eq, PartialEq, Debug)]
 struct IdentData {
+    key: ValidationKey, // Current key, which changes when it's rotated.
+    credits: u64,
+    credits_at: u64, // Height the balance was last updated at.
+    profiles: Vec<(u64, IdentityDecl)>, // Every profile it's declared, with the heights, in order.
+    delegates: Vec<Fingerprint>, // Every key it's delegated to.
+    key_seq: u64, // Of the last delegation, rotation, revocation, or policy change it's signed.
+    policy: Option<SigPolicy> // Keys that have to sign for its governance, if it's not just its main key.
+}
+
+/// Any key that's ever been able to sign for an identity, whether as its main key or delegated to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct KeyData {
     key: ValidationKey,
-    credits: u64
+    ident: Fingerprint,
+    delegated: bool,
+    issuer: Option<Fingerprint>, // Key that delegated to it, which it goes down with.
+    expires: Option<i64>, // Only for delegated keys.
+    added_at: u64,
+    revoked_at: Option<u64> // Height of the block that revoked or rotated it away.
 }
 
 type VBlock = Signed<block::Block>;
 
-#[derive(Clone)]
+/// What we need to know about a block in the history to check its children.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Applied {
+    seq: usize,
+    height: u64,
+    timestamp: i64
+}
+
+/// What a block changed, as the values things had before it.  Entries that didn't exist yet are
+/// `None`.  Undoing the block is just putting them all back.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+struct BlockDiff {
+    idents: HashMap<Fingerprint, Option<IdentData>>,
+    keys: HashMap<Fingerprint, Option<KeyData>>,
+    ledger: Vec<Fingerprint> // Identities that had a balance recorded.
+}
+
+impl BlockDiff {
+    fn is_empty(&self) -> bool {
+        self.idents.is_empty() && self.keys.is_empty() && self.ledger.is_empty()
+    }
+}
+
+/// Number of blocks back that we can undo by default.
+pub const DEFAULT_UNDO_DEPTH: usize = 1000;
+
+/// How far ahead of our clock a block's timestamp can be, in milliseconds.
+pub const MAX_CLOCK_DRIFT: i64 = 2 * 60 * 60 * 1000;
+
+/// Number of blocks that can wait on their parents by default.
+pub const DEFAULT_MAX_STRAYS: usize = 1000;
+
+/// How long a block can wait on its parents by default, in milliseconds.
+pub const DEFAULT_MAX_STRAY_AGE: i64 = 10 * 60 * 1000;
+
+#[derive(Clone, Debug)]
 struct BlockchainState {
     idents: HashMap<Fingerprint, IdentData>,
+    keys: HashMap<Fingerprint, KeyData>,
+    ledger: HashMap<Fingerprint, Vec<(usize, u64, u64)>>, // Sequence number, height and balance after each block that touched them.
+    network: u32, // What everything has to be signed for.
+    policy: CreditPolicy,
+    seq: usize, // Sequence number of the block being applied, which is where it is in the history.
+    height: u64, // Height of the block being applied, which is what credits regenerate with.
+    pending: BlockDiff // Changes since the last call to `take_diff`.
 }
 
 impl BlockchainState {
+
+    fn new(network: u32, policy: CreditPolicy) -> BlockchainState {
+        BlockchainState {
+            idents: HashMap::new(),
+            keys: HashMap::new(),
+            ledger: HashMap::new(),
+            network: network,
+            policy: policy,
+            seq: 0,
+            height: 0,
+            pending: BlockDiff::default()
+        }
+    }
+
+    /// Remembers what the identity was before it's changed, if it hasn't been already.
+    fn touch_ident(&mut self, fp: Fingerprint) {
+        if !self.pending.idents.contains_key(&fp) {
+            let old = self.idents.get(&fp).cloned();
+            self.pending.idents.insert(fp, old);
+        }
+    }
+
+    fn touch_key(&mut self, fp: Fingerprint) {
+        if !self.pending.keys.contains_key(&fp) {
+            let old = self.keys.get(&fp).cloned();
+            self.pending.keys.insert(fp, old);
+        }
+    }
+
+    /// Returns everything that's changed since the last time this was called.
+    fn take_diff(&mut self) -> BlockDiff {
+        ::std::mem::replace(&mut self.pending, BlockDiff::default())
+    }
+
+    /// Puts back everything that the diff changed.  Any changes since it was taken have to be
+    /// undone first.
+    fn undo(&mut self, diff: BlockDiff) {
+        for (fp, old) in diff.idents {
+            match old {
+                Some(id) => self.idents.insert(fp, id),
+                None => self.idents.remove(&fp)
+            };
+        }
+        for (fp, old) in diff.keys {
+            match old {
+                Some(kd) => self.keys.insert(fp, kd),
+                None => self.keys.remove(&fp)
+            };
+        }
+        for fp in diff.ledger {
+            let empty = match self.ledger.get_mut(&fp) {
+                Some(l) => {
+                    l.pop();
+                    l.is_empty()
+                },
+                None => false
+            };
+            if empty {
+                self.ledger.remove(&fp);
+            }
+        }
+    }
+
+    /// Throws away whatever's been changed since the last diff was taken.
+    fn rollback(&mut self) {
+        let diff = self.take_diff();
+        self.undo(diff);
+    }
+
     fn find_identity(&self, fp: &Fingerprint) -> Option<IdentData> {
         self.idents.get(fp).cloned()
     }
+
+    fn add_identity(&mut self, key: ValidationKey, credits: u64, height: u64) {
+        let fp: Fingerprint = key.into();
+        self.touch_ident(fp);
+        self.idents.insert(fp, IdentData {
+            key: key,
+            credits: credits,
+            credits_at: self.height,
+            profiles: Vec::new(),
+            delegates: Vec::new(),
+            key_seq: 0,
+            policy: None
+        });
+        self.add_key(key, fp, None, None, height);
+        self.record_balance(fp);
+    }
+
+    /// The identity's balance as of the block being applied, with what it's regenerated since it
+    /// was last updated.  Blocks lower than that don't regenerate anything.
+    fn balance(&self, ident: &Fingerprint) -> Option<u64> {
+        self.idents.get(ident).map(|id| self.policy.regenerate(id.credits, self.height.saturating_sub(id.credits_at)))
+    }
+
+    /// The identity's balance as of the block with the sequence number and height, if it existed
+    /// then.
+    fn balance_at(&self, ident: &Fingerprint, seq: usize, height: u64) -> Option<u64> {
+        let l = self.ledger.get(ident)?;
+        let &(_, at, bal) = l.iter().rev().find(|&&(s, _, _)| s <= seq)?;
+        Some(self.policy.regenerate(bal, height.saturating_sub(at)))
+    }
+
+    fn spend(&mut self, ident: Fingerprint, amount: u64) -> Result<(), ValidationError> {
+
+        let bal = match self.balance(&ident) {
+            Some(b) if b >= amount => b,
+            Some(_) => return Err(ValidationError::InsufficientCredits),
+            None => return Err(ValidationError::UnknownSigner(ident))
+        };
+
+        self.touch_ident(ident);
+        let id = self.idents.get_mut(&ident).expect("balance for missing identity");
+        id.credits = bal - amount;
+        id.credits_at = id.credits_at.max(self.height);
+        Ok(())
+
+    }
+
+    /// Writes down the identity's balance in the ledger for the block being applied.
+    fn record_balance(&mut self, ident: Fingerprint) {
+
+        let (bal, at) = match (self.balance(&ident), self.idents.get(&ident)) {
+            (Some(b), Some(id)) => (b, id.credits_at.max(self.height)),
+            _ => return
+        };
+
+        let seq = self.seq;
+        let l = self.ledger.entry(ident).or_insert_with(Vec::new);
+        match l.last_mut() {
+            Some(e) if e.0 == seq => *e = (seq, at, bal),
+            _ => {
+                l.push((seq, at, bal));
+                self.pending.ledger.push(ident);
+            }
+        }
+
+    }
+
+    fn add_key(&mut self, key: ValidationKey, ident: Fingerprint, issuer: Option<Fingerprint>, expires: Option<i64>, height: u64) {
+        self.touch_key(key.into());
+        self.keys.insert(key.into(), KeyData {
+            key: key,
+            ident: ident,
+            delegated: issuer.is_some(),
+            issuer: issuer,
+            expires: expires,
+            added_at: height,
+            revoked_at: None
+        });
+    }
+
+    /// Finds the key for the fingerprint and the identity that's on the hook for what it signs, as
+    /// of a block at the given timestamp and height.  Keys are still good in the block that revokes
+    /// them, just not after.  It's the block's timestamp and not the segment's, since whoever signs
+    /// a segment can put whatever they like in it.
+    fn find_signer(&self, fp: Fingerprint, ts: i64, height: u64) -> Result<(ValidationKey, Fingerprint), ValidationError> {
+
+        let kd = match self.keys.get(&fp) {
+            Some(kd) => kd,
+            None => return Err(ValidationError::UnknownSigner(fp))
+        };
+
+        if let Some(h) = kd.revoked_at {
+            if height > h {
+                return Err(ValidationError::KeyRevoked(fp));
+            }
+        }
+
+        if let Some(e) = kd.expires {
+            if ts > e {
+                return Err(ValidationError::DelegationExpired(fp));
+            }
+        }
+
+        Ok((kd.key, kd.ident))
+
+    }
+
+    /// Like `find_signer`, but also requires that it's the identity's current main key, since
+    /// that's the only one that can update the identity's profile.
+    fn find_main_signer(&self, fp: Fingerprint, ts: i64, height: u64) -> Result<(ValidationKey, Fingerprint), ValidationError> {
+        let (key, ident) = self.find_signer(fp, ts, height)?;
+        match self.idents.get(&ident) {
+            Some(id) if id.key == key => Ok((key, ident)),
+            _ => Err(ValidationError::NotMainKey(fp))
+        }
+    }
+
+    /// Finds the identity that a governance segment signed with the key manages.  Without a policy
+    /// only its main key can do that.  With one any of its keys can, but only if the segment's
+    /// multi-signed under the policy, which `by_policy` says it's been checked to be.
+    fn find_manager(&self, fp: Fingerprint, ts: i64, height: u64, by_policy: bool) -> Result<(ValidationKey, Fingerprint), ValidationError> {
+        let (key, ident) = self.find_signer(fp, ts, height)?;
+        match self.idents.get(&ident) {
+            Some(id) if id.policy.is_some() => match by_policy {
+                true => Ok((key, ident)),
+                false => Err(ValidationError::WrongPolicy(ident))
+            },
+            Some(id) if id.key == key => Ok((key, ident)),
+            _ => Err(ValidationError::NotMainKey(fp))
+        }
+    }
+
+    /// Moves the identity's key change sequence number up to the one given, if it's higher than
+    /// the last one.  Otherwise it's an old one being replayed.
+    fn bump_key_seq(&mut self, ident: Fingerprint, seq: u64) -> Result<(), ValidationError> {
+        match self.idents.get(&ident) {
+            Some(id) if seq > id.key_seq => {},
+            _ => return Err(ValidationError::StaleKeyChange(seq))
+        }
+        self.touch_ident(ident);
+        self.idents.get_mut(&ident).expect("identity went missing").key_seq = seq;
+        Ok(())
+    }
+
+    /// Records a delegation.  Only an identity's main key can delegate, so they don't chain, and
+    /// an identity can re-issue a delegation to change when it expires.
+    fn apply_delegation(&mut self, sd: &SignedDelegation, ts: i64, height: u64, by_policy: bool) -> Result<(), ValidationError> {
+
+        let signer = sd.sig().into_fingerprint();
+        let (key, ident) = self.find_manager(signer, ts, height, by_policy)?;
+        sd.verify_on(key, self.network).map_err(ValidationError::BadSignature)?;
+
+        let d = sd.extract_owned();
+        let child = d.child_fingerprint();
+        match self.keys.get(&child) {
+            Some(prev) if prev.ident == ident && prev.delegated && prev.revoked_at.is_none() => {},
+            Some(_) => return Err(ValidationError::KeyInUse(child)),
+            None => {}
+        }
+
+        self.bump_key_seq(ident, d.seq())?;
+        if self.keys.contains_key(&child) {
+            self.touch_key(child);
+            self.keys.get_mut(&child).expect("key went missing").expires = d.expires();
+            return Ok(());
+        }
+
+        self.add_key(d.child(), ident, Some(signer), d.expires(), height);
+        self.touch_ident(ident);
+        self.idents.get_mut(&ident).expect("key for missing identity").delegates.push(child);
+        Ok(())
+
+    }
+
+    /// Replaces an identity's main key.  The old one is revoked as of this block, along with
+    /// everything it delegated to.
+    fn apply_rotation(&mut self, sr: &SignedKeyRotation, ts: i64, height: u64, by_policy: bool) -> Result<(), ValidationError> {
+
+        let (key, ident) = self.find_manager(sr.sig().into_fingerprint(), ts, height, by_policy)?;
+        sr.verify_on(key, self.network).map_err(ValidationError::BadSignature)?;
+        let old: Fingerprint = self.idents.get(&ident).expect("key for missing identity").key.into();
+
+        let r = sr.extract_owned();
+        let new_key = r.new_key();
+        let new_fp: Fingerprint = new_key.into();
+        if self.keys.contains_key(&new_fp) {
+            return Err(ValidationError::KeyInUse(new_fp));
+        }
+
+        self.bump_key_seq(ident, r.seq())?;
+        self.revoke(old, height);
+        self.add_key(new_key, ident, None, None, height);
+        self.touch_ident(ident);
+        self.idents.get_mut(&ident).expect("key for missing identity").key = new_key;
+        Ok(())
+
+    }
+
+    /// Revokes a key as of this block, along with everything it delegated to.  If it's the
+    /// identity's main key then the identity is stuck without one for good, which is what you want
+    /// if it's been stolen.  A key revoking itself doesn't use up a sequence number, otherwise a
+    /// delegate could burn through all of them and lock the main key out.
+    fn apply_revocation(&mut self, sr: &SignedRevocation, ts: i64, height: u64, by_policy: bool) -> Result<(), ValidationError> {
+
+        let signer = sr.sig().into_fingerprint();
+        let r = sr.extract_owned();
+        let target = r.revoked();
+
+        let (key, ident) = match signer == target {
+            true => self.find_signer(signer, ts, height)?,
+            false => self.find_manager(signer, ts, height, by_policy)?
+        };
+
+        sr.verify_on(key, self.network).map_err(ValidationError::BadSignature)?;
+
+        match self.keys.get(&target) {
+            Some(kd) if kd.ident == ident => {},
+            _ => return Err(ValidationError::UnknownSigner(target))
+        }
+
+        if signer != target {
+            self.bump_key_seq(ident, r.seq())?;
+        }
+        self.revoke(target, height);
+        Ok(())
+
+    }
+
+    /// Sets or clears the identity's policy.  The first one is set by its main key, and after that
+    /// it takes the policy to change it.
+    fn apply_policy_change(&mut self, sp: &SignedPolicyChange, ts: i64, height: u64, by_policy: bool) -> Result<(), ValidationError> {
+
+        let (key, ident) = self.find_manager(sp.sig().into_fingerprint(), ts, height, by_policy)?;
+        sp.verify_on(key, self.network).map_err(ValidationError::BadSignature)?;
+
+        let pc = sp.extract_owned();
+        self.bump_key_seq(ident, pc.seq())?;
+        self.touch_ident(ident);
+        self.idents.get_mut(&ident).expect("key for missing identity").policy = pc.policy();
+        Ok(())
+
+    }
+
+    /// Declares a new identity, or updates the profile of an existing one.  New ones have to sign
+    /// their own declarations, and start with `credits`.  Updates have to be signed by the
+    /// identity's main key.
+    fn apply_ident_decl(&mut self, signer: Fingerprint, d: IdentityDecl, ts: i64, height: u64, credits: u64) -> Result<(), ValidationError> {
+
+        if !d.is_well_formed() {
+            return Err(ValidationError::BadIdentityDecl);
+        }
+
+        let fp = d.fingerprint();
+        if !self.keys.contains_key(&fp) {
+            if signer != fp {
+                return Err(ValidationError::BadSignature(sig::SigVerificationError::FingerprintMismatch));
+            }
+            self.add_identity(d.key(), credits, height);
+        } else if self.find_main_signer(signer, ts, height)?.1 != fp {
+            return Err(ValidationError::KeyInUse(fp));
+        }
+
+        self.touch_ident(fp);
+        match self.idents.get_mut(&fp) {
+            Some(id) => id.profiles.push((height, d)),
+            None => return Err(ValidationError::KeyInUse(fp)) // Some identity's other key.
+        }
+
+        Ok(())
+
+    }
+
+    /// Revokes the key as of the height, and the keys it delegated to, unless they already were.
+    fn revoke(&mut self, fp: Fingerprint, height: u64) {
+
+        self.touch_key(fp);
+        let ident = match self.keys.get_mut(&fp) {
+            Some(kd) => {
+                if kd.revoked_at.map_or(true, |h| h > height) {
+                    kd.revoked_at = Some(height);
+                }
+                kd.ident
+            },
+            None => return
+        };
+
+        let issued: Vec<Fingerprint> = match self.idents.get(&ident) {
+            Some(id) => id.delegates.iter().filter(|c| self.keys.get(c).and_then(|kd| kd.issuer) == Some(fp)).cloned().collect(),
+            None => Vec::new()
+        };
+
+        for c in issued {
+            self.revoke(c, height);
+        }
+
+    }
+
 }
 
 #[derive(Clone)]
-struct ValdiationState {
+pub struct ValdiationState {
+    params: NetworkParams,
     history: LinkedList<(Address, VBlock)>,
-    stray_uncles: HashMap<Address, VBlock>,
-    data_state: BlockchainState
+    applied: HashMap<Address, Applied>,
+    data_state: BlockchainState,
+    undo_log: VecDeque<BlockDiff>, // For the last few blocks in the history, oldest first.
+    undo_limit: usize
 }
 
 impl ValdiationState {
 
-    pub fn verify_block(&mut self, block: VBlock) -> Result<(), ValidationError> {
-        Ok(()) // TODO This isn't correct.  We need to actually check things.
+    /// Starts from the network's genesis block, with the identities it declares.  They start with
+    /// the founding credits, and don't pay for declaring themselves.
+    pub fn new(params: NetworkParams) -> ValdiationState {
+
+        let mut history = LinkedList::new();
+        history.push_back((params.genesis_address(), params.genesis().clone()));
+        let mut applied = HashMap::new();
+        applied.insert(params.genesis_address(), applied_info(0, params.genesis()));
+
+        // The genesis block's trusted by its address, so its signatures aren't checked, and any
+        // declarations in it that don't make sense are just left out.
+        let mut data_state = BlockchainState::new(params.network_id(), params.credits());
+        let (_, ts) = node_info(params.genesis());
+        let founding = params.credits().founding;
+        for seg in params.genesis().extract_owned().get_segments() {
+            if let segment::SegmentContent::IdentDecl(d) = seg.segment().content() {
+                let _ = data_state.apply_ident_decl(poster(seg), d, ts, 0, founding);
+            }
+        }
+        data_state.take_diff();
+
+        ValdiationState {
+            params: params,
+            history: history,
+            applied: applied,
+            data_state: data_state,
+            undo_log: VecDeque::new(),
+            undo_limit: DEFAULT_UNDO_DEPTH
+        }
+
+    }
+
+    pub fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
+    /// Sets how many blocks back can be undone.  Anything older than that is final.
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        self.undo_limit = limit;
+        while self.undo_log.len() > limit {
+            self.undo_log.pop_front();
+        }
+    }
+
+    /// If the block's already in the history.
+    pub fn is_applied(&self, addr: Address) -> bool {
+        self.applied.contains_key(&addr)
+    }
+
+    /// The identity's balance as of the last block applied.
+    pub fn balance(&self, ident: Fingerprint) -> Option<u64> {
+        self.data_state.balance(&ident)
+    }
+
+    /// The identity's balance right after the block was applied, if they both exist.
+    pub fn balance_at(&self, ident: Fingerprint, block: Address) -> Option<u64> {
+        let a = self.applied.get(&block)?;
+        self.data_state.balance_at(&ident, a.seq, a.height)
+    }
+
+    /// The second phase, for everything about the block itself that depends on the state.  Its
+    /// parents have to be applied already, and it has to be made after them, at the right height,
+    /// by a key that could sign at the time.  Segments are checked as they're applied.  Returns the
+    /// identity that signed it.
+    pub fn verify_block(&self, block: &VBlock) -> Result<Fingerprint, ValidationError> {
+
+        let inner = block.extract_owned();
+        let head = inner.get_header().extract_owned();
+
+        // Only genesis can go without parents, and it's already applied.
+        let mut height = None;
+        for p in head.parents() {
+            match self.applied.get(&p) {
+                Some(a) if a.timestamp > head.timestamp() => return Err(ValidationError::BadTimestamp(head.timestamp())),
+                Some(a) => match a.height.checked_add(1) {
+                    Some(h) => height = height.max(Some(h)),
+                    None => return Err(ValidationError::BadHeight(head.block_height()))
+                },
+                None => return Err(ValidationError::NodeNotFound(p))
+            }
+        }
+
+        if height != Some(head.block_height()) {
+            return Err(ValidationError::BadHeight(head.block_height()));
+        }
+
+        // Newcomers can sign blocks that declare them, if they've done the work.
+        let fp = block.sig().into_fingerprint();
+        let (key, ident) = match self.data_state.find_signer(fp, head.timestamp(), head.block_height()) {
+            Err(ValidationError::UnknownSigner(_)) => match self.newcomer_key(&inner, fp) {
+                Some(k) => (k, fp),
+                None => return Err(ValidationError::UnknownSigner(fp))
+            },
+            r => r?
+        };
+        let net = self.params.network_id();
+        block.verify_on(key, net).map_err(ValidationError::BadSignature)?;
+        inner.get_header().verify_on(key, net).map_err(ValidationError::BadSignature)?;
+
+        Ok(ident)
+
+    }
+
+    /// The key of a block signer we don't know yet, if the block has their declaration in it with a
+    /// stamp that pays for the block too.
+    fn newcomer_key(&self, block: &block::Block, fp: Fingerprint) -> Option<ValidationKey> {
+        let block_cost = self.params.credits().block_cost;
+        for seg in block.get_segments() {
+            if let segment::SegmentContent::IdentDecl(d) = seg.segment().content() {
+                if d.fingerprint() == fp && poster(seg) == fp && paid_by_work(&self.params, seg, block_cost) {
+                    return Some(d.key());
+                }
+            }
+        }
+        None
     }
 
+    /// Finds the key for any fingerprint we know about.  This doesn't know what time it is, so it
+    /// doesn't check for expiry or revocation, `verify_segment` does that.
     pub fn find_key(&self, fp: Fingerprint) -> Option<ValidationKey> {
-        self.data_state.idents.get(&fp).map(|id| id.key)
+        self.data_state.keys.get(&fp).map(|kd| kd.key)
+    }
+
+    /// Checks the signature on a segment in a block at the given height and timestamp, returning
+    /// the identity it's on behalf of.
+    pub fn verify_segment(&self, seg: &Signed<segment::Segment>, height: u64, ts: i64) -> Result<Fingerprint, ValidationError> {
+        let fp = seg.sig().into_fingerprint();
+        let (key, ident) = self.data_state.find_signer(fp, ts, height)?;
+        seg.verify_on(key, self.params.network_id()).map_err(ValidationError::BadSignature)?;
+        Ok(ident)
+    }
+
+    /// Verifies the segment and applies any changes it makes to identities and their keys.
+    pub fn apply_segment(&mut self, seg: &Signed<segment::Segment>, height: u64, ts: i64) -> Result<(), ValidationError> {
+
+        use dag::segment::SegmentContent::*;
+
+        let s = seg.extract_owned();
+        let signer = seg.sig().into_fingerprint();
+
+        match s.content() {
+            // New identities sign their own declarations, so there's no key to look up yet.
+            IdentDecl(ref d) if !self.data_state.keys.contains_key(&signer) => {
+                seg.verify_on(d.key(), self.params.network_id()).map_err(ValidationError::BadSignature)?;
+            },
+            _ => {
+                self.verify_segment(seg, height, ts)?;
+            }
+        }
+
+        self.apply_content(signer, s, height, ts, false)
+
+    }
+
+    /// Like `apply_segment`, but the segment can also be stamped, or multi-signed if it's for
+    /// governance.  Multi-signed ones have to be under the policy the identity they're managing
+    /// has on the chain, by keys that could sign at the time.
+    pub fn apply_signed_segment(&mut self, seg: &SignedSegment, height: u64, ts: i64) -> Result<(), ValidationError> {
+        match seg {
+            &SignedSegment::Single(ref s) | &SignedSegment::Stamped(ref s, _) => self.apply_segment(s, height, ts),
+            &SignedSegment::Multi(ref m) => {
+
+                let s = m.extract_owned();
+                let signer = match governance_signer(&s.content()) {
+                    Some(fp) => fp,
+                    None => return Err(ValidationError::MultiSignedPost)
+                };
+
+                let ident = self.data_state.find_signer(signer, ts, height)?.1;
+                match self.data_state.idents.get(&ident).and_then(|id| id.policy.as_ref()) {
+                    Some(p) if p == m.policy() => {},
+                    _ => return Err(ValidationError::WrongPolicy(ident))
+                }
+
+                let live = LiveKeys { state: &self.data_state, ts: ts, height: height };
+                m.verify_with_resolver_on(&live, self.params.network_id()).map_err(ValidationError::BadSignature)?;
+                self.apply_content(poster(seg), s, height, ts, true)
+
+            }
+        }
+    }
+
+    fn apply_content(&mut self, signer: Fingerprint, s: segment::Segment, height: u64, ts: i64, by_policy: bool) -> Result<(), ValidationError> {
+
+        use dag::segment::SegmentContent::*;
+
+        let st = &mut self.data_state;
+        match s.content() {
+            IdentDecl(d) => {
+                let initial = st.policy.initial;
+                st.apply_ident_decl(signer, d, ts, height, initial)
+            },
+            Delegation(sd) => st.apply_delegation(&sd, ts, height, by_policy),
+            KeyRotation(sr) => st.apply_rotation(&sr, ts, height, by_policy),
+            Revocation(sr) => st.apply_revocation(&sr, ts, height, by_policy),
+            PolicyChange(sp) => st.apply_policy_change(&sp, ts, height, by_policy),
+            _ => Ok(())
+        }
+
+    }
+
+    /// Checks the block and applies all of its segments, then adds it to the history.  If any of
+    /// it is bad then none of it is applied.  This is only the second phase, the block should've
+    /// been through `check_block` already.
+    pub fn apply_block(&mut self, addr: Address, block: VBlock) -> Result<(), ValidationError> {
+
+        let signer = self.verify_block(&block)?;
+
+        // Anything changed outside of a block can't be undone.
+        self.data_state.take_diff();
+
+        let (seq, last) = (self.history.len(), self.data_state.height);
+        self.data_state.seq = seq;
+        self.data_state.height = node_info(&block).0;
+        if let Err(e) = self.apply_segments(signer, &block.extract_owned()) {
+            self.data_state.rollback();
+            self.data_state.seq = seq - 1;
+            self.data_state.height = last;
+            return Err(e);
+        }
+
+        self.applied.insert(addr, applied_info(seq, &block));
+        self.history.push_back((addr, block));
+        self.undo_log.push_back(self.data_state.take_diff());
+        if self.undo_log.len() > self.undo_limit {
+            self.undo_log.pop_front();
+        }
+
+        Ok(())
+
+    }
+
+    /// Undoes the last block in the history, returning its address.  Fails if it's too far
+    /// back to undo, which the genesis block always is.
+    pub fn revert_block(&mut self) -> Result<Address, ValidationError> {
+        self.pop_block().map(|(addr, _)| addr)
+    }
+
+    /// Like `revert_block`, but hands back the block too.
+    fn pop_block(&mut self) -> Result<(Address, VBlock), ValidationError> {
+
+        let diff = match self.undo_log.pop_back() {
+            Some(d) => d,
+            None => return Err(ValidationError::OrderConflict(self.history.back().expect("history is never empty").0))
+        };
+
+        self.data_state.undo(diff);
+        let (addr, block) = self.history.pop_back().expect("undo log longer than history");
+        self.applied.remove(&addr);
+        let last = self.history.back().expect("history is never empty").0;
+        self.data_state.seq = self.history.len() - 1;
+        self.data_state.height = self.applied[&last].height;
+        Ok((addr, block))
+
+    }
+
+    /// Applies the segments and charges for them, unless they're paid for with work, and charges
+    /// the block's signer for the block, unless they're a newcomer that paid for it with work.
+    /// Then the balances of everyone it touched are written down in the ledger.
+    fn apply_segments(&mut self, signer: Fingerprint, block: &block::Block) -> Result<(), ValidationError> {
+
+        let head = block.get_header().extract_owned();
+        let (height, ts) = (head.block_height(), head.timestamp());
+        if self.data_state.find_identity(&signer).is_some() {
+            let block_cost = self.params.credits().block_cost;
+            self.data_state.spend(signer, block_cost)?;
+        }
+
+        for seg in block.get_segments() {
+            // Newcomers can't pay for declaring themselves, so whoever signed the block does,
+            // unless it's stamped.
+            let newcomer = !self.data_state.keys.contains_key(&poster(seg));
+            self.apply_signed_segment(seg, height, ts)?;
+            let ident = match newcomer {
+                true => signer,
+                false => self.data_state.keys.get(&poster(seg)).expect("applied segment by unknown key").ident
+            };
+            if !paid_by_work(&self.params, seg, 0) {
+                self.data_state.spend(ident, calc_segment_cost(&self.params, seg.segment()))?;
+            }
+        }
+
+        let touched: Vec<Fingerprint> = self.data_state.pending.idents.keys().cloned().collect();
+        for fp in touched {
+            self.data_state.record_balance(fp);
+        }
+
+        Ok(())
+
+    }
+
+    /// Orders everything behind the tips with the rule, then checks and applies the blocks that
+    /// haven't been yet.  Blocks in the source should've been through `check_block` when they
+    /// were received.  If the order's changed then blocks are undone back to where it starts to
+    /// differ first.  If that's too far back, nothing is changed.  Rules like `HeaviestSubDag`
+    /// cache what they've worked out, so it's best to pass the same one every time.
+    ///
+    /// If a block fails then the blocks before it in the order stay applied.  But if that was
+    /// after undoing some blocks, then it's all called off and the old order stays as it was.
+    pub fn apply_ordered<S: BlobSource, R: OrderingRule>(&mut self, src: &NodeSource<S>, rule: &R, tips: &[Address]) -> Result<(), ValidationError> {
+
+        let order = rule.order(&Traverser::new(src), tips).map_err(node_error)?;
+
+        let same = self.history.iter().zip(order.iter()).take_while(|&(&(h, _), o)| h == *o).count();
+        let undo = self.history.len() - same;
+        if undo > self.undo_log.len() {
+            let first = self.history.iter().nth(same).expect("history shorter than expected").0;
+            return Err(ValidationError::OrderConflict(first));
+        }
+
+        if undo == 0 {
+            return self.apply_all(src, &order[same..]);
+        }
+
+        // Reorgs are done in place, but what's taken off is kept so that it can be put back if a
+        // block on the new side is bad.  The new side's diffs have to be kept to undo it too.
+        let mut popped = Vec::with_capacity(undo);
+        for _ in 0..undo {
+            popped.push(self.pop_block().expect("undo log shorter than it was"));
+        }
+
+        let limit = self.undo_limit;
+        self.undo_limit = usize::MAX;
+        let res = self.apply_all(src, &order[same..]);
+        if res.is_err() {
+            while self.history.len() > same {
+                self.pop_block().expect("couldn't undo a block from the new order");
+            }
+            for (addr, block) in popped.into_iter().rev() {
+                self.apply_block(addr, block).expect("couldn't put back a block from the old order");
+            }
+        }
+
+        self.set_undo_limit(limit);
+        res
+
+    }
+
+    /// Applies the blocks in order, stopping at the first one that fails.
+    fn apply_all<S: BlobSource>(&mut self, src: &NodeSource<S>, order: &[Address]) -> Result<(), ValidationError> {
+
+        for addr in order {
+            let block: VBlock = src.get(*addr).map_err(|e| node_error(TraverseError(*addr, e)))?;
+            self.apply_block(*addr, block)?;
+        }
+
+        Ok(())
+
     }
 
 }
 
-type SegmentCost = u64;
-const IDENT_COST: SegmentCost = 1000;
-const ARTIFACT_PTR_COST: SegmentCost = 50;
+fn node_error(e: TraverseError) -> ValidationError {
+    match e {
+        TraverseError(a, NodeGetError::NotFound) => ValidationError::NodeNotFound(a),
+        TraverseError(a, _) => ValidationError::DecodeError(a)
+    }
+}
+
+fn node_info(block: &VBlock) -> (u64, i64) {
+    let head = block.extract_owned().get_header().extract_owned();
+    (head.block_height(), head.timestamp())
+}
 
-fn calc_segment_cost(seg: segment::Segment) -> SegmentCost {
+fn applied_info(seq: usize, block: &VBlock) -> Applied {
+    let (height, ts) = node_info(block);
+    Applied { seq: seq, height: height, timestamp: ts }
+}
+
+/// Whoever's on the hook for the segment.  Multi-signed ones are on the identity they're
+/// managing, so it's whoever signed the payload, not any of the co-signers.
+fn poster(seg: &SignedSegment) -> Fingerprint {
+    match seg {
+        &SignedSegment::Single(ref s) | &SignedSegment::Stamped(ref s, _) => s.sig().into_fingerprint(),
+        &SignedSegment::Multi(ref m) => match governance_signer(&m.extract_owned().content()) {
+            Some(fp) => fp,
+            None => m.sigs()[0].into_fingerprint()
+        }
+    }
+}
+
+/// The key that signed the payload of a governance segment, which is how we know which identity
+/// it's managing.
+fn governance_signer(content: &segment::SegmentContent) -> Option<Fingerprint> {
     use dag::segment::SegmentContent::*;
-    match seg.content() {
-        IdentDecl(_) => IDENT_COST,
-        Artifact(ad) => ad.to_blob().len() as SegmentCost, // TODO Make this more mathy.
-        ArtifactPointer(_) => ARTIFACT_PTR_COST
+    match content {
+        &Delegation(ref sd) => Some(sd.sig().into_fingerprint()),
+        &KeyRotation(ref sr) => Some(sr.sig().into_fingerprint()),
+        &Revocation(ref sr) => Some(sr.sig().into_fingerprint()),
+        &PolicyChange(ref sp) => Some(sp.sig().into_fingerprint()),
+        _ => None
+    }
+}
+
+/// Keys that can sign as of a block, so revoked and expired ones don't count towards a policy.
+struct LiveKeys<'a> {
+    state: &'a BlockchainState,
+    ts: i64,
+    height: u64
+}
+
+impl<'a> KeyResolver for LiveKeys<'a> {
+    fn resolve(&self, fp: Fingerprint) -> Option<ValidationKey> {
+        self.state.find_signer(fp, self.ts, self.height).ok().map(|(k, _)| k)
+    }
+}
+
+/// If the segment's stamped with enough work that it doesn't cost any credits, and that it pays
+/// for something else that costs `extra` too.  The more it would cost, the more work it takes.
+fn paid_by_work(params: &NetworkParams, seg: &SignedSegment, extra: u64) -> bool {
+    let credits = params.credits();
+    let cost = calc_segment_cost(params, seg.segment()).saturating_add(extra);
+    credits.stamp_bits > 0 && seg.stamp_work().map_or(false, |w| w >= credits.stamp_bits_for(cost))
+}
+
+/// Decodes a block that we've been sent.  If it doesn't decode then it's known by the hash of
+/// whatever we got.
+pub fn decode_block(blob: &[u8]) -> Result<(Address, VBlock), ValidationError> {
+    match VBlock::from_slice_exact(blob) {
+        Ok(b) => Ok((Address::of_bincomp(&b), b)),
+        Err(_) => Err(ValidationError::DecodeError(Address::of_slice(blob)))
+    }
+}
+
+/// The first phase, for everything about a block that doesn't depend on where it is in the DAG.
+/// Signatures by keys that the resolver doesn't know about are left for the second phase, since
+/// they could be from blocks that haven't been applied yet.
+pub fn check_block<K: KeyResolver>(params: &NetworkParams, kr: &K, block: &VBlock, now: i64) -> Result<(), ValidationError> {
+
+    let limits = params.limits();
+    let inner = block.extract_owned();
+    let head = inner.get_header().extract_owned();
+    let blob = block.to_blob();
+    if blob.len() as u64 > limits.max_bytes
+            || inner.get_segments().len() as u64 > limits.max_segments
+            || head.parents().len() as u64 > limits.max_parents {
+        return Err(ValidationError::ComponentTooLarge(sig::Hash::of_slice(blob.as_slice())));
+    }
+
+    if !inner.check_merkle_root() {
+        return Err(ValidationError::BadMerkleRoot);
+    }
+
+    if head.timestamp() > now + MAX_CLOCK_DRIFT {
+        return Err(ValidationError::BadTimestamp(head.timestamp()));
+    }
+
+    for seg in inner.get_segments() {
+        let ts = seg.segment().timestamp();
+        if ts > head.timestamp() {
+            return Err(ValidationError::BadTimestamp(ts));
+        }
+        if seg.stamp_work().is_some() && !paid_by_work(params, seg, 0) {
+            return Err(ValidationError::InsufficientWork);
+        }
+    }
+
+    // The header's signed by whoever made the block.
+    let fp = block.sig().into_fingerprint();
+    if inner.get_header().sig().into_fingerprint() != fp {
+        return Err(ValidationError::BadSignature(SigVerificationError::FingerprintMismatch));
+    }
+
+    if let Some(vk) = kr.resolve(fp) {
+        block.verify_on(vk, params.network_id()).map_err(ValidationError::BadSignature)?;
+        inner.get_header().verify_on(vk, params.network_id()).map_err(ValidationError::BadSignature)?;
+    }
+
+    let dk = DeclaredKeys::new(kr, &inner);
+    if let Err(bad) = inner.verify_segments_on(&dk, params.network_id()) {
+        if let Some(&(_, e)) = bad.iter().find(|&&(_, e)| e != SigVerificationError::UnknownKey) {
+            return Err(ValidationError::BadSignature(e));
+        }
+    }
+
+    Ok(())
+
+}
+
+/// The keys a resolver knows, plus the ones declared in the block by new identities, since those
+/// sign their own declarations.
+struct DeclaredKeys<'a, K: 'a> {
+    known: &'a K,
+    declared: HashMap<Fingerprint, ValidationKey>
+}
+
+impl<'a, K: KeyResolver> DeclaredKeys<'a, K> {
+    fn new(known: &'a K, block: &block::Block) -> DeclaredKeys<'a, K> {
+        let mut declared = HashMap::new();
+        for seg in block.get_segments() {
+            if let segment::SegmentContent::IdentDecl(d) = seg.segment().content() {
+                declared.insert(d.fingerprint(), d.key());
+            }
+        }
+        DeclaredKeys { known: known, declared: declared }
     }
 }
+
+impl<'a, K: KeyResolver> KeyResolver for DeclaredKeys<'a, K> {
+    fn resolve(&self, fp: Fingerprint) -> Option<ValidationKey> {
+        self.known.resolve(fp).or_else(|| self.declared.get(&fp).cloned())
+    }
+}
+
+/// Block outcomes, in the order they were processed.
+pub type BlockResults = Vec<(Address, Result<(), ValidationError>)>;
+
+/// Blocks go in one end as they're received, through the first phase, and wait in the queue for
+/// the second.  Blocks whose parents we don't have yet are set aside until we do, for a while.
+pub struct Pipeline<S: BlobSource, C: Clock = SystemClock> {
+    state: ValdiationState,
+    src: NodeSource<S>,
+    rule: HeaviestSubDag,
+    tips: Vec<Address>,
+    clock: C,
+    queue: VecDeque<(Address, VBlock)>,
+    strays: HashMap<Address, (VBlock, i64)>, // Blocks waiting on their parents, and when they came.
+    max_strays: usize,
+    max_stray_age: i64
+}
+
+impl<S: BlobSource> Pipeline<S, SystemClock> {
+
+    pub fn new(state: ValdiationState, src: NodeSource<S>) -> Pipeline<S, SystemClock> {
+        Pipeline::with_clock(state, src, SystemClock)
+    }
+
+}
+
+impl<S: BlobSource, C: Clock> Pipeline<S, C> {
+
+    /// Blocks that are already in the state's history are put into the source, so it can start
+    /// from wherever the state is.
+    pub fn with_clock(state: ValdiationState, src: NodeSource<S>, clock: C) -> Pipeline<S, C> {
+
+        let mut tips = Vec::new();
+        for &(a, ref b) in state.history.iter() {
+            // If this fails then so does everything on top of it, with `NodeNotFound`.
+            let _ = src.put(b.clone());
+            let ps = parents_of(b);
+            tips.retain(|t| !ps.contains(t));
+            tips.push(a);
+        }
+
+        tips.sort();
+        Pipeline {
+            state: state,
+            src: src,
+            rule: HeaviestSubDag::new(),
+            tips: tips,
+            clock: clock,
+            queue: VecDeque::new(),
+            strays: HashMap::new(),
+            max_strays: DEFAULT_MAX_STRAYS,
+            max_stray_age: DEFAULT_MAX_STRAY_AGE
+        }
+
+    }
+
+    pub fn state(&self) -> &ValdiationState {
+        &self.state
+    }
+
+    /// The blocks that nothing we've applied is built on yet.
+    pub fn tips(&self) -> &[Address] {
+        self.tips.as_slice()
+    }
+
+    /// Number of blocks waiting for the second phase, not counting ones waiting on parents.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Sets how many blocks can wait on their parents, and for how long in milliseconds.  When
+    /// there's too many the ones that have been waiting longest are dropped.
+    pub fn set_stray_limits(&mut self, count: usize, age: i64) {
+        self.max_strays = count;
+        self.max_stray_age = age;
+        self.trim_strays();
+    }
+
+    /// Runs the first phase on a block we've been sent and queues it.  Blocks we've already got
+    /// are let through without being queued again.
+    pub fn submit(&mut self, blob: &[u8]) -> Result<Address, ValidationError> {
+
+        let (addr, block) = decode_block(blob)?;
+        if self.state.is_applied(addr)
+                || self.strays.contains_key(&addr)
+                || self.queue.iter().any(|&(a, _)| a == addr) {
+            return Ok(addr);
+        }
+
+        check_block(&self.state.params, &self.state, &block, self.clock.now_millis())?;
+        self.queue.push_back((addr, block));
+        Ok(addr)
+
+    }
+
+    /// Runs the second phase on everything in the queue.  Each block is added to the tips and
+    /// everything's applied in the order the fork-choice rule gives, so it doesn't matter what
+    /// order they came in.  Blocks with missing parents are reported as `NodeNotFound` and tried
+    /// again once they show up, so those can be in the results twice.  If they fail the first
+    /// phase when they're tried again, that's in the results too.
+    pub fn process(&mut self) -> BlockResults {
+
+        let mut results = Vec::new();
+        while let Some((addr, block)) = self.queue.pop_front() {
+
+            let res = self.connect(addr, &block);
+            results.push((addr, res));
+            match res {
+                Ok(()) => self.requeue_strays(&mut results),
+                Err(ValidationError::NodeNotFound(_)) => {
+                    let now = self.clock.now_millis();
+                    self.strays.insert(addr, (block, now));
+                },
+                Err(_) => {}
+            }
+
+        }
+
+        self.trim_strays();
+        results
+
+    }
+
+    /// Adds the block to the tips and applies them.  If it doesn't work then the tips stay as
+    /// they were, and so does the state.
+    fn connect(&mut self, addr: Address, block: &VBlock) -> Result<(), ValidationError> {
+
+        let ps = parents_of(block);
+        if let Some(p) = ps.iter().find(|p| !self.state.is_applied(**p)) {
+            return Err(ValidationError::NodeNotFound(*p));
+        }
+
+        // If we can't store it, it waits with the strays and is tried again later.
+        self.src.put(block.clone()).map_err(|_| ValidationError::NodeNotFound(addr))?;
+
+        let mut tips: Vec<Address> = self.tips.iter().filter(|t| !ps.contains(t)).cloned().collect();
+        tips.push(addr);
+        tips.sort();
+        self.state.apply_ordered(&self.src, &self.rule, tips.as_slice())?;
+        self.tips = tips;
+        Ok(())
+
+    }
+
+    /// Moves the blocks whose parents have all been applied back into the queue, after running
+    /// the first phase again now that more keys are known.  The ones that fail are dropped.
+    fn requeue_strays(&mut self, results: &mut BlockResults) {
+
+        let mut ready: Vec<(u64, Address)> = {
+            let st = &self.state;
+            self.strays.iter()
+                .filter(|&(_, &(ref b, _))| parents_of(b).iter().all(|p| st.is_applied(*p)))
+                .map(|(a, &(ref b, _))| (node_info(b).0, *a))
+                .collect()
+        };
+
+        // Lowest first, so it doesn't depend on the order they came in.
+        ready.sort();
+        let now = self.clock.now_millis();
+        for (_, a) in ready {
+            let (b, _) = self.strays.remove(&a).expect("stray went missing");
+            match check_block(&self.state.params, &self.state, &b, now) {
+                Ok(()) => self.queue.push_back((a, b)),
+                Err(e) => results.push((a, Err(e)))
+            }
+        }
+
+    }
+
+    /// Drops strays that have waited too long, then the oldest ones until there's few enough.
+    fn trim_strays(&mut self) {
+
+        let oldest = self.clock.now_millis() - self.max_stray_age;
+        self.strays.retain(|_, &mut (_, t)| t >= oldest);
+
+        if self.strays.len() > self.max_strays {
+            let mut by_age: Vec<(i64, Address)> = self.strays.iter().map(|(a, &(_, t))| (t, *a)).collect();
+            by_age.sort();
+            let extra = by_age.len() - self.max_strays;
+            for &(_, a) in &by_age[..extra] {
+                self.strays.remove(&a);
+            }
+        }
+
+    }
+
+}
+
+fn parents_of(block: &VBlock) -> Vec<Address> {
+    block.extract_owned().get_header().extract_owned().parents()
+}
+
+impl sig::KeyResolver for ValdiationState {
+    fn resolve(&self, fp: Fingerprint) -> Option<ValidationKey> {
+        self.find_key(fp)
+    }
+}
+
+impl IdentityRegistry for BlockchainState {
+
+    fn who_is(&self, fp: Fingerprint, height: u64) -> Option<IdentityRecord> {
+
+        let kd = match self.keys.get(&fp) {
+            Some(kd) if kd.added_at <= height => kd,
+            _ => return None
+        };
+
+        let id = self.idents.get(&kd.ident)?;
+        let profile = id.profiles.iter().rev().find(|&&(h, _)| h <= height).map(|&(_, ref d)| d.clone());
+
+        Some(IdentityRecord {
+            ident: kd.ident,
+            profile: profile,
+            delegated: kd.delegated,
+            revoked: kd.revoked_at.map_or(false, |h| height > h)
+        })
+
+    }
+
+}
+
+impl IdentityRegistry for ValdiationState {
+    fn who_is(&self, fp: Fingerprint, height: u64) -> Option<IdentityRecord> {
+        self.data_state.who_is(fp, height)
+    }
+}
+
+type SegmentCost = u64;
+
+fn calc_segment_cost(params: &NetworkParams, seg: segment::Segment) -> SegmentCost {
+    use dag::segment::SegmentContent::*;
+    let costs = params.costs();
+    match seg.content() {
+        IdentDecl(_) => costs.ident,
+        Artifact(ad) => ad.to_blob().len() as SegmentCost, // TODO Make this more mathy.
+        ArtifactPointer(_) => costs.artifact_ptr,
+        Delegation(_) => costs.delegation,
+        KeyRotation(_) => costs.key_rotation,
+        Revocation(_) => costs.revocation,
+        PolicyChange(_) => costs.key_rotation // About as big a deal.
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::sig::{Keypair, Scheme};
+    use dag::artifact::ArtifactData;
+    use dag::builder::BlockBuilder;
+    use dag::delegation::Delegation;
+    use dag::identity::{Capabilities, KeyRotation, PolicyChange, Revocation};
+    use dag::params::REGTEST_ID;
+    use dag::pow::PowStamp;
+    use dag::segment::{Segment, SegmentContent};
+    use dag::DagNode;
+    use db::testutil::MemBlobSource;
+
+    use super::*;
+
+    /// Everything in here is on regtest.
+    fn sign<T: BinaryComponent>(kp: Keypair, body: T) -> Signed<T> {
+        Signed::new_on(kp, REGTEST_ID, body)
+    }
+
+    fn state_with(master: Keypair) -> ValdiationState {
+        let mut vs = ValdiationState::new(NetworkParams::regtest());
+        vs.data_state.add_identity(master.into(), 5000, 0);
+        vs
+    }
+
+    fn post(kp: Keypair, ts: i64) -> Signed<Segment> {
+        sign(kp, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, b"hi".to_vec())), ts))
+    }
+
+    fn declare(signer: Keypair, kp: Keypair, name: &str) -> Signed<Segment> {
+        let d = IdentityDecl::new(kp.into(), name.into(), None, "".into(), Capabilities::POSTER);
+        sign(signer, Segment::new(SegmentContent::IdentDecl(d), 0))
+    }
+
+    fn delegate(master: Keypair, child: Keypair, expires: Option<i64>, seq: u64) -> Signed<Segment> {
+        let sd = sign(master, Delegation::new(child.into(), expires, seq));
+        sign(master, Segment::new(SegmentContent::Delegation(sd), 0))
+    }
+
+    fn rotate(old: Keypair, new: Keypair, seq: u64) -> Signed<Segment> {
+        let sr = sign(old, KeyRotation::new(new.into(), seq));
+        sign(old, Segment::new(SegmentContent::KeyRotation(sr), 0))
+    }
+
+    fn revoke(by: Keypair, target: Keypair, seq: u64) -> Signed<Segment> {
+        let sr = sign(by, Revocation::new(target.into(), seq));
+        sign(by, Segment::new(SegmentContent::Revocation(sr), 0))
+    }
+
+    fn set_policy(by: Keypair, policy: Option<SigPolicy>, seq: u64) -> Signed<Segment> {
+        let sp = sign(by, PolicyChange::new(policy, seq));
+        sign(by, Segment::new(SegmentContent::PolicyChange(sp), 0))
+    }
+
+    /// Parents are given as blocks so that we can make blocks from before them.
+    fn mk(ps: &[&VBlock], ts: i64, segs: Vec<SignedSegment>, kp: Keypair) -> VBlock {
+        ps.iter()
+            .fold(BlockBuilder::with_clock(1, move || ts).network(REGTEST_ID), |bb, p| bb.parent(Address::of_bincomp(*p), node_info(p).0))
+            .segments(segs)
+            .build(kp)
+            .unwrap()
+    }
+
+    fn multi(policy: &SigPolicy, seg: Signed<Segment>, signers: &[Keypair]) -> SignedSegment {
+        let mut ms = sig::MultiSigned::new(policy.clone(), seg.extract());
+        for kp in signers {
+            ms.sign_on(*kp, REGTEST_ID).unwrap();
+        }
+        SignedSegment::Multi(ms)
+    }
+
+    #[test]
+    fn ck_delegated_segments() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let child = master.derive_child(0);
+        let mfp: Fingerprint = master.into();
+        let cfp: Fingerprint = child.into();
+        let mut vs = state_with(master);
+
+        assert_eq!(vs.verify_segment(&post(master, 10), 1, 10), Ok(mfp));
+        assert_eq!(vs.verify_segment(&post(child, 10), 1, 10), Err(ValidationError::UnknownSigner(cfp)));
+
+        vs.apply_segment(&delegate(master, child, Some(100), 1), 1, 0).unwrap();
+        assert_eq!(vs.find_key(cfp), Some(child.into()));
+
+        // Segments from the child count as the master's, until the delegation runs out.  That's
+        // by the block's timestamp, backdating the segment doesn't help.
+        assert_eq!(vs.verify_segment(&post(child, 100), 1, 100), Ok(mfp));
+        assert_eq!(vs.verify_segment(&post(child, 101), 1, 101), Err(ValidationError::DelegationExpired(cfp)));
+        assert_eq!(vs.verify_segment(&post(child, 0), 1, 101), Err(ValidationError::DelegationExpired(cfp)));
+
+        // Re-issuing it extends it.
+        vs.apply_segment(&delegate(master, child, None, 2), 1, 101).unwrap();
+        assert_eq!(vs.verify_segment(&post(child, 101), 1, 101), Ok(mfp));
+
+        // And it can be cut short again, without the old one being replayed to undo that.
+        let forever = delegate(master, child, None, 3);
+        vs.apply_segment(&forever, 1, 101).unwrap();
+        vs.apply_segment(&delegate(master, child, Some(200), 4), 1, 101).unwrap();
+        assert_eq!(vs.apply_segment(&forever, 2, 101), Err(ValidationError::StaleKeyChange(3)));
+        assert_eq!(vs.verify_segment(&post(child, 0), 2, 201), Err(ValidationError::DelegationExpired(cfp)));
+
+    }
+
+    #[test]
+    fn ck_bad_delegations() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let other = Scheme::Secp256k1.generate(&[2; 32]);
+        let child = master.derive_child(0);
+        let mut vs = state_with(master);
+
+        // Only main keys can delegate, so children can't delegate further.
+        let st = &mut vs.data_state;
+        let sd = sign(child, Delegation::new(child.derive_child(0).into(), None, 1));
+        assert_eq!(st.apply_delegation(&sd, 0, 1, false), Err(ValidationError::UnknownSigner(child.into())));
+
+        st.apply_delegation(&sign(master, Delegation::new(child.into(), None, 1)), 0, 1, false).unwrap();
+        assert_eq!(st.apply_delegation(&sd, 0, 1, false), Err(ValidationError::NotMainKey(child.into())));
+
+        // Can't delegate to yourself, take over someone else's key, or take over an identity.
+        let sd = sign(master, Delegation::new(master.into(), None, 2));
+        assert_eq!(st.apply_delegation(&sd, 0, 1, false), Err(ValidationError::KeyInUse(master.into())));
+        st.add_identity(other.into(), 0, 0);
+        let sd = sign(other, Delegation::new(child.into(), None, 1));
+        assert_eq!(st.apply_delegation(&sd, 0, 1, false), Err(ValidationError::KeyInUse(child.into())));
+        let sd = sign(master, Delegation::new(other.into(), None, 2));
+        assert_eq!(st.apply_delegation(&sd, 0, 1, false), Err(ValidationError::KeyInUse(other.into())));
+
+    }
+
+    #[test]
+    fn ck_key_rotation() {
+
+        let old = Scheme::Ed25519.generate(&[1; 32]);
+        let new = Scheme::Secp256k1.generate(&[2; 32]);
+        let child = old.derive_child(0);
+        let ident: Fingerprint = old.into();
+        let mut vs = state_with(old);
+
+        vs.apply_segment(&delegate(old, child, None, 1), 4, 0).unwrap();
+        vs.apply_segment(&rotate(old, new, 2), 5, 0).unwrap();
+        assert_eq!(vs.data_state.find_identity(&ident).unwrap().key, new.into());
+
+        // The old key still works in the rotating block, but not after.
+        assert_eq!(vs.verify_segment(&post(old, 0), 5, 0), Ok(ident));
+        assert_eq!(vs.verify_segment(&post(old, 0), 6, 0), Err(ValidationError::KeyRevoked(old.into())));
+        assert_eq!(vs.verify_segment(&post(new, 0), 6, 0), Ok(ident));
+
+        // Same for what it delegated to.
+        assert_eq!(vs.verify_segment(&post(child, 0), 5, 0), Ok(ident));
+        assert_eq!(vs.verify_segment(&post(child, 0), 6, 0), Err(ValidationError::KeyRevoked(child.into())));
+
+        // And it can't rotate again, even in the same block.
+        let other = Scheme::Ed25519.generate(&[3; 32]);
+        assert_eq!(vs.apply_segment(&rotate(old, other, 3), 5, 0), Err(ValidationError::NotMainKey(old.into())));
+
+        // Can't rotate to a key that's already in use, or with a sequence number that's been used.
+        assert_eq!(vs.apply_segment(&rotate(new, old, 3), 6, 0), Err(ValidationError::KeyInUse(old.into())));
+        assert_eq!(vs.apply_segment(&rotate(new, other, 2), 6, 0), Err(ValidationError::StaleKeyChange(2)));
+
+    }
+
+    #[test]
+    fn ck_key_revocation() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let a = master.derive_child(0);
+        let b = master.derive_child(1);
+        let mut vs = state_with(master);
+
+        vs.apply_segment(&delegate(master, a, None, 1), 1, 0).unwrap();
+        vs.apply_segment(&delegate(master, b, None, 2), 1, 0).unwrap();
+
+        // Delegated keys can't revoke each other, but can revoke themselves.
+        assert_eq!(vs.apply_segment(&revoke(a, b, 3), 2, 0), Err(ValidationError::NotMainKey(a.into())));
+        vs.apply_segment(&revoke(a, a, 3), 2, 0).unwrap();
+        assert_eq!(vs.verify_segment(&post(a, 0), 3, 0), Err(ValidationError::KeyRevoked(a.into())));
+
+        // Once revoked a key can't be delegated to again.
+        assert_eq!(vs.apply_segment(&delegate(master, a, None, 4), 3, 0), Err(ValidationError::KeyInUse(a.into())));
+
+        // The main key can revoke any of them, including itself.
+        vs.apply_segment(&revoke(master, b, 4), 3, 0).unwrap();
+        assert_eq!(vs.verify_segment(&post(b, 0), 4, 0), Err(ValidationError::KeyRevoked(b.into())));
+        let c = master.derive_child(2);
+        vs.apply_segment(&delegate(master, c, None, 5), 3, 0).unwrap();
+        vs.apply_segment(&revoke(master, master, 6), 4, 0).unwrap();
+        assert_eq!(vs.verify_segment(&post(master, 0), 5, 0), Err(ValidationError::KeyRevoked(master.into())));
+
+        // Which takes what it delegated to down with it.
+        assert_eq!(vs.verify_segment(&post(c, 0), 4, 0), Ok(master.into()));
+        assert_eq!(vs.verify_segment(&post(c, 0), 5, 0), Err(ValidationError::KeyRevoked(c.into())));
+
+        // Nobody else's keys though.
+        let other = Scheme::Ed25519.generate(&[9; 32]);
+        vs.data_state.add_identity(other.into(), 0, 0);
+        assert_eq!(vs.apply_segment(&revoke(other, master, 1), 5, 0), Err(ValidationError::UnknownSigner(master.into())));
+
+    }
+
+    #[test]
+    fn ck_self_revocation_keeps_seq() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let a = master.derive_child(0);
+        let new = Scheme::Ed25519.generate(&[2; 32]);
+        let mut vs = state_with(master);
+
+        // A delegate revoking itself with the last sequence number doesn't stop the main key.
+        vs.apply_segment(&delegate(master, a, None, 1), 1, 0).unwrap();
+        vs.apply_segment(&revoke(a, a, u64::MAX), 2, 0).unwrap();
+        assert_eq!(vs.verify_segment(&post(a, 0), 3, 0), Err(ValidationError::KeyRevoked(a.into())));
+        vs.apply_segment(&rotate(master, new, 2), 3, 0).unwrap();
+        assert_eq!(vs.data_state.find_identity(&master.into()).unwrap().key, new.into());
+
+    }
+
+    #[test]
+    fn ck_identity_registry() {
+
+        let alice = Scheme::Ed25519.generate(&[1; 32]);
+        let rotated = Scheme::Ed25519.generate(&[2; 32]);
+        let child = alice.derive_child(0);
+        let ident: Fingerprint = alice.into();
+        let mut vs = state_with(Scheme::Ed25519.generate(&[9; 32]));
+
+        assert_eq!(vs.who_is(ident, 10), None);
+        vs.apply_segment(&declare(alice, alice, "alice"), 10, 0).unwrap();
+        vs.apply_segment(&delegate(alice, child, None, 1), 11, 0).unwrap();
+        vs.apply_segment(&rotate(alice, rotated, 2), 12, 0).unwrap();
+        vs.apply_segment(&declare(rotated, alice, "alice 2"), 13, 0).unwrap();
+
+        let name = |fp: Fingerprint, h| vs.who_is(fp, h).and_then(|r| r.profile).map(|p| p.name().to_string());
+        assert_eq!(vs.who_is(ident, 9), None);
+        assert_eq!(name(ident, 10), Some("alice".into()));
+        assert_eq!(name(ident, 12), Some("alice".into()));
+        assert_eq!(name(ident, 13), Some("alice 2".into()));
+
+        // Any of its keys lead back to it.
+        assert_eq!(vs.who_is(child.into(), 10), None);
+        let r = vs.who_is(child.into(), 11).unwrap();
+        assert_eq!((r.ident, r.delegated, r.revoked), (ident, true, false));
+        let r = vs.who_is(rotated.into(), 13).unwrap();
+        assert_eq!((r.ident, r.delegated, r.revoked), (ident, false, false));
+        assert_eq!(vs.who_is(alice.into(), 12).unwrap().revoked, false);
+        assert_eq!(vs.who_is(alice.into(), 13).unwrap().revoked, true);
+
+    }
+
+    #[test]
+    fn ck_bad_identity_decls() {
+
+        let alice = Scheme::Ed25519.generate(&[1; 32]);
+        let mallory = Scheme::Ed25519.generate(&[2; 32]);
+        let mut vs = state_with(Scheme::Ed25519.generate(&[9; 32]));
+
+        assert_eq!(vs.apply_segment(&declare(alice, alice, ""), 1, 0), Err(ValidationError::BadIdentityDecl));
+
+        // Can't declare someone else's key, before or after they have.
+        let err = Err(ValidationError::BadSignature(sig::SigVerificationError::FingerprintMismatch));
+        assert_eq!(vs.apply_segment(&declare(mallory, alice, "alice"), 1, 0), err);
+        vs.apply_segment(&declare(alice, alice, "alice"), 1, 0).unwrap();
+        assert_eq!(vs.apply_segment(&declare(mallory, alice, "alice"), 2, 0), err);
+
+        // Or redeclare a delegated key as its own identity.
+        let child = alice.derive_child(0);
+        vs.apply_segment(&delegate(alice, child, None, 1), 2, 0).unwrap();
+        assert_eq!(vs.apply_segment(&declare(child, child, "child"), 3, 0), Err(ValidationError::NotMainKey(child.into())));
+        assert_eq!(vs.apply_segment(&declare(alice, child, "child"), 3, 0), Err(ValidationError::KeyInUse(child.into())));
+
+    }
+
+    #[test]
+    fn ck_governance_policy() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let a = master.derive_child(0);
+        let b = master.derive_child(1);
+        let c = master.derive_child(2);
+        let ident: Fingerprint = master.into();
+        let mut vs = state_with(master);
+
+        vs.apply_segment(&delegate(master, a, None, 1), 1, 0).unwrap();
+        vs.apply_segment(&delegate(master, b, None, 2), 1, 0).unwrap();
+
+        // Multi-signing doesn't mean anything until there's a policy on the chain.
+        let policy = SigPolicy::new(2, vec![master.into(), a.into(), b.into()]).unwrap();
+        let seg = multi(&policy, delegate(master, c, None, 3), &[master, a]);
+        assert_eq!(vs.apply_signed_segment(&seg, 2, 0), Err(ValidationError::WrongPolicy(ident)));
+        vs.apply_segment(&set_policy(master, Some(policy.clone()), 3), 2, 0).unwrap();
+
+        // After that the main key can't do it alone, and neither can a policy it picks itself.
+        assert_eq!(vs.apply_segment(&delegate(master, c, None, 4), 3, 0), Err(ValidationError::WrongPolicy(ident)));
+        let own = SigPolicy::new(1, vec![master.into()]).unwrap();
+        assert_eq!(vs.apply_signed_segment(&multi(&own, delegate(master, c, None, 4), &[master]), 3, 0), Err(ValidationError::WrongPolicy(ident)));
+        let seg = multi(&policy, delegate(master, c, None, 4), &[master]);
+        assert_eq!(vs.apply_signed_segment(&seg, 3, 0), Err(ValidationError::BadSignature(SigVerificationError::ThresholdNotMet)));
+
+        // Enough of the policy can, even without the main key.
+        vs.apply_signed_segment(&multi(&policy, delegate(a, c, None, 4), &[a, b]), 3, 0).unwrap();
+        assert_eq!(vs.verify_segment(&post(c, 0), 4, 0), Ok(ident));
+        vs.apply_signed_segment(&multi(&policy, revoke(master, a, 5), &[master, b]), 4, 0).unwrap();
+        assert_eq!(vs.verify_segment(&post(c, 0), 5, 0), Err(ValidationError::KeyRevoked(c.into())));
+
+        // Keys that have been revoked don't count, even though they're still in the policy.
+        let seg = multi(&policy, revoke(master, b, 6), &[master, a]);
+        assert_eq!(vs.apply_signed_segment(&seg, 5, 0), Err(ValidationError::BadSignature(SigVerificationError::UnknownKey)));
+
+        // The policy can be dropped, and keys can still always revoke themselves.
+        vs.apply_signed_segment(&multi(&policy, set_policy(b, None, 6), &[master, b]), 5, 0).unwrap();
+        vs.apply_segment(&revoke(b, b, 7), 5, 0).unwrap();
+        vs.apply_segment(&delegate(master, a.derive_child(0), None, 8), 6, 0).unwrap();
+
+    }
+
+    #[test]
+    fn ck_multi_signed_costs() {
+
+        // The identity's policy has someone else's key in it, which sorts first so it's the
+        // first co-signer.
+        let (k1, k2) = (Scheme::Ed25519.generate(&[1; 32]), Scheme::Ed25519.generate(&[2; 32]));
+        let (master, foreign) = if Fingerprint::from(k1) > Fingerprint::from(k2) { (k1, k2) } else { (k2, k1) };
+        let child = master.derive_child(0);
+        let params = NetworkParams::regtest();
+        let mut vs = state_with(master);
+        vs.data_state.add_identity(foreign.into(), 5000, 0);
+
+        let policy = SigPolicy::new(2, vec![master.into(), foreign.into()]).unwrap();
+        vs.apply_segment(&set_policy(master, Some(policy.clone()), 1), 0, 0).unwrap();
+
+        // The identity being managed pays, not whoever signed first.
+        let seg = multi(&policy, delegate(master, child, None, 2), &[master, foreign]);
+        if let SignedSegment::Multi(ref m) = seg {
+            assert_eq!(m.sigs()[0].into_fingerprint(), foreign.into());
+        }
+        let block = mk(&[params.genesis()], params.genesis().timestamp() + 1, vec![seg], master);
+        vs.apply_block(Address::of_bincomp(&block), block).unwrap();
+        let credits = params.credits();
+        assert_eq!(vs.balance(master.into()), Some(5000 + credits.regen - credits.block_cost - params.costs().delegation));
+        assert_eq!(vs.balance(foreign.into()), Some(5000 + credits.regen));
+
+    }
+
+    #[test]
+    fn ck_block_limits() {
+
+        use dag::params::BlockLimits;
+
+        let kp = Scheme::Ed25519.generate(&[1; 32]);
+        let params = NetworkParams::regtest().with_limits(BlockLimits { max_segments: 2, ..BlockLimits::DEFAULT });
+        let vs = ValdiationState::new(params.clone());
+        assert_eq!(vs.history.front().map(|h| h.0), Some(params.genesis_address()));
+
+        let now = params.genesis().timestamp() + 10;
+        let build = |n: i64| BlockBuilder::with_clock(1, || now).network(REGTEST_ID).parent_block(params.genesis()).segments((0..n).map(|i| post(kp, i))).build(kp).unwrap();
+        assert_eq!(check_block(&params, &vs, &build(2), now), Ok(()));
+        match check_block(&params, &vs, &build(3), now) {
+            Err(ValidationError::ComponentTooLarge(_)) => {},
+            r => panic!("expected the block to be too large, got {:?}", r)
+        }
+
+    }
+
+    #[test]
+    fn ck_apply_ordered() {
+
+        use order::HeaviestSubDag;
+        use db::testutil::build_with;
+
+        let rule = HeaviestSubDag::new();
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let child = master.derive_child(0);
+        let params = NetworkParams::regtest();
+        let policy = SigPolicy::new(2, vec![master.into(), child.into()]).unwrap();
+
+        // The child gets delegated to on one side and put in a policy with the master, then it's
+        // revoked by both of them after the merge.
+        let segs = |i| -> Vec<SignedSegment> {
+            match i {
+                1 => vec![post(master, 1).into()],
+                2 => vec![delegate(master, child, None, 1).into(), set_policy(master, Some(policy.clone()), 2).into()],
+                3 => vec![multi(&policy, revoke(master, child, 3), &[master, child])],
+                _ => vec![]
+            }
+        };
+
+        let (src, _, a) = build_with(Some(params.genesis().clone()), &[&[], &[0], &[0], &[1, 2]], segs);
+
+        let mut vs = state_with(master);
+        vs.apply_ordered(&src, &rule, &[a[3]]).unwrap();
+        assert_eq!(vs.history.len(), 4);
+        assert_eq!(vs.history.back().unwrap().0, a[3]);
+        assert_eq!(vs.who_is(child.into(), 2).unwrap().revoked, false);
+        assert_eq!(vs.who_is(child.into(), 3).unwrap().revoked, true);
+
+        // Applying it again doesn't do anything.
+        vs.apply_ordered(&src, &rule, &[a[3]]).unwrap();
+        assert_eq!(vs.history.len(), 4);
+
+        // If we applied the side that sorts second first, then it has to be undone.
+        let second = if a[1] < a[2] { a[2] } else { a[1] };
+        let mut vs = state_with(master);
+        vs.apply_ordered(&src, &rule, &[second]).unwrap();
+        vs.apply_ordered(&src, &rule, &[a[3]]).unwrap();
+        assert_eq!(vs.history.len(), 4);
+        assert_eq!(vs.who_is(child.into(), 3).unwrap().revoked, true);
+
+        // Unless it's too far back to undo.
+        let mut vs = state_with(master);
+        vs.set_undo_limit(0);
+        vs.apply_ordered(&src, &rule, &[second]).unwrap();
+        assert_eq!(vs.apply_ordered(&src, &rule, &[a[3]]), Err(ValidationError::OrderConflict(second)));
+
+        let missing = Address::of_slice(b"missing");
+        assert_eq!(vs.apply_ordered(&src, &rule, &[missing]), Err(ValidationError::NodeNotFound(missing)));
+
+    }
+
+    #[test]
+    fn ck_reorgs() {
+
+        use order::HeaviestSubDag;
+        use db::testutil::build_with;
+
+        let rule = HeaviestSubDag::new();
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let alice = Scheme::Ed25519.generate(&[2; 32]);
+        let bob = Scheme::Secp256k1.generate(&[3; 32]);
+        let stranger = Scheme::Ed25519.generate(&[8; 32]);
+        let params = NetworkParams::regtest();
+
+        //   0 - 1 - 2          (A)
+        //    \
+        //     3 - 4 - 5        (B)
+        //    \
+        //     6 - 7 - 8        (C, where 7 is signed by a stranger)
+        let segs = |i| -> Vec<SignedSegment> {
+            match i {
+                1 => vec![delegate(master, master.derive_child(1), None, 3).into()],
+                2 => vec![declare(alice, alice, "alice").into()],
+                3 => vec![declare(bob, bob, "bob").into()],
+                4 => vec![delegate(master, master.derive_child(2), Some(50), 2).into(), rotate(bob, bob.derive_child(0), 1).into()],
+                5 => vec![post(master, 5).into()],
+                7 => vec![post(stranger, 7).into()],
+                _ => vec![]
+            }
+        };
+        let shape: &[&[usize]] = &[&[], &[0], &[1], &[0], &[3], &[4], &[0], &[6], &[7]];
+        let (src, _, a) = build_with(Some(params.genesis().clone()), shape, segs);
+
+        // What you'd get from scratch.
+        let replay = |tips: &[Address]| {
+            let mut vs = state_with(master);
+            vs.apply_ordered(&src, &rule, tips).unwrap();
+            vs
+        };
+        let same = |x: &ValdiationState, y: &ValdiationState| {
+            x.history.iter().map(|h| h.0).eq(y.history.iter().map(|h| h.0))
+                && x.data_state.idents == y.data_state.idents
+                && x.data_state.keys == y.data_state.keys
+                && x.data_state.ledger == y.data_state.ledger
+        };
+
+        let mut vs = state_with(master);
+        vs.apply_ordered(&src, &rule, &[a[2]]).unwrap();
+        assert!(vs.who_is(alice.into(), 2).is_some());
+
+        // C's heavier too, but it's bad, so A stays where it is.
+        assert_eq!(vs.apply_ordered(&src, &rule, &[a[2], a[8]]), Err(ValidationError::UnknownSigner(stranger.into())));
+        assert!(same(&vs, &replay(&[a[2]])));
+        assert_eq!(vs.undo_log.len(), 2);
+
+        // B's heavier, so A gets undone and goes after it.
+        vs.apply_ordered(&src, &rule, &[a[2], a[5]]).unwrap();
+        assert_eq!(vs.history.iter().map(|h| h.0).collect::<Vec<_>>(), vec![a[0], a[3], a[4], a[5], a[1], a[2]]);
+        assert!(same(&vs, &replay(&[a[5], a[2]])));
+        assert!(vs.find_key(bob.derive_child(0).into()).is_some());
+
+        // Dropping A altogether.
+        vs.apply_ordered(&src, &rule, &[a[5]]).unwrap();
+        assert!(same(&vs, &replay(&[a[5]])));
+        assert_eq!(vs.who_is(alice.into(), 2), None);
+        assert_eq!(vs.who_is(master.derive_child(1).into(), 2), None);
+
+        // And back again, one block at a time.
+        vs.apply_ordered(&src, &rule, &[a[2]]).unwrap();
+        assert!(same(&vs, &replay(&[a[2]])));
+        assert_eq!(vs.find_key(bob.derive_child(0).into()), None);
+        assert_eq!(vs.revert_block(), Ok(a[2]));
+        assert_eq!(vs.revert_block(), Ok(a[1]));
+        assert!(same(&vs, &state_with(master)));
+        assert_eq!(vs.revert_block(), Err(ValidationError::OrderConflict(a[0])));
+
+        // Past the undo limit nothing changes.
+        vs.apply_ordered(&src, &rule, &[a[5]]).unwrap();
+        vs.set_undo_limit(2);
+        assert_eq!(vs.apply_ordered(&src, &rule, &[a[2]]), Err(ValidationError::OrderConflict(a[3])));
+        assert!(same(&vs, &replay(&[a[5]])));
+
+    }
+
+    #[test]
+    fn ck_bad_blocks_apply_nothing() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let child = master.derive_child(0);
+        let params = NetworkParams::regtest();
+        let mut vs = state_with(master);
+
+        // The delegation's fine, but the post after it is from someone nobody knows.
+        let stranger = Scheme::Ed25519.generate(&[8; 32]);
+        let block = dag::builder::BlockBuilder::with_clock(1, || params.genesis().timestamp() + 1)
+            .network(REGTEST_ID)
+            .parent_block(params.genesis())
+            .segment(delegate(master, child, None, 1))
+            .segment(post(stranger, 0))
+            .build(master)
+            .unwrap();
+
+        let before = vs.data_state.keys.clone();
+        let addr = Address::of_bincomp(&block);
+        assert_eq!(vs.apply_block(addr, block), Err(ValidationError::UnknownSigner(stranger.into())));
+        assert_eq!(vs.data_state.keys, before);
+        assert_eq!(vs.history.len(), 1);
+
+    }
+
+    #[test]
+    fn ck_other_networks() {
+
+        use dag::params::TESTNET_ID;
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let params = NetworkParams::regtest();
+        let mut vs = state_with(master);
+        let ts = params.genesis().timestamp() + 1;
+
+        // Things signed for another network, or for none at all, don't carry over.
+        let seg = Segment::new(SegmentContent::Artifact(ArtifactData::new(0, b"hi".to_vec())), 0);
+        for net in &[TESTNET_ID, sig::NO_NETWORK] {
+            let other = Signed::new_on(master, *net, seg.clone());
+            assert_eq!(vs.apply_segment(&other, 1, ts), Err(ValidationError::BadSignature(SigVerificationError::KeyMismatch)));
+        }
+
+        let block = dag::builder::BlockBuilder::with_clock(1, || ts)
+            .network(TESTNET_ID)
+            .parent_block(params.genesis())
+            .build(master)
+            .unwrap();
+        assert_eq!(check_block(&params, &vs, &block, ts), Err(ValidationError::BadSignature(SigVerificationError::KeyMismatch)));
+        assert_eq!(vs.apply_block(Address::of_bincomp(&block), block), Err(ValidationError::BadSignature(SigVerificationError::KeyMismatch)));
+
+        let rev = Signed::new_on(master, TESTNET_ID, Revocation::new(master.derive_child(0).into(), 1));
+        let seg = sign(master, Segment::new(SegmentContent::Revocation(rev), 0));
+        assert_eq!(vs.apply_segment(&seg, 1, ts), Err(ValidationError::BadSignature(SigVerificationError::KeyMismatch)));
+
+    }
+
+    #[test]
+    fn ck_pipeline() {
+
+        use dag::block::{Block, BlockHeader};
+        use dag::merkle;
+        use dag::params::BlockLimits;
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let child = master.derive_child(0);
+        let alice = Scheme::Ed25519.generate(&[2; 32]);
+        let stranger = Scheme::Ed25519.generate(&[8; 32]);
+
+        let params = NetworkParams::regtest().with_limits(BlockLimits { max_segments: 4, ..BlockLimits::DEFAULT });
+        let g = params.genesis().clone();
+        let t0 = g.timestamp();
+        let now = t0 + 1000;
+
+        let mut vs = ValdiationState::new(params.clone());
+        vs.data_state.add_identity(master.into(), 5000, 0);
+        let mut pl = Pipeline::with_clock(vs, NodeSource::new(MemBlobSource::new()), move || now);
+
+        let header = |height, root, kp| sign(kp, BlockHeader::new(1, t0 + 1, height, root, vec![params.genesis_address()]));
+        let addr = |b: &VBlock| Address::of_bincomp(b);
+
+        let b1 = mk(&[&g], t0 + 1, vec![post(master, t0).into()], master);
+        let b2 = mk(&[&b1], t0 + 2, vec![declare(alice, alice, "alice").into()], master);
+        let b3 = mk(&[&b2], t0 + 3, vec![delegate(master, child, None, 1).into()], master);
+
+        // Things that don't get past the first phase.
+        let mut tampered = post(master, 0).to_blob();
+        *tampered.last_mut().unwrap() ^= 1;
+        let tampered = Signed::<Segment>::from_slice_exact(tampered.as_slice()).unwrap();
+        let empty_root = merkle::segments_root(&[]);
+
+        assert_eq!(pl.submit(b"nope"), Err(ValidationError::DecodeError(Address::of_slice(b"nope"))));
+        match pl.submit(&mk(&[&g], t0 + 1, (0..5).map(|i| post(master, i).into()).collect(), master).to_blob()) {
+            Err(ValidationError::ComponentTooLarge(_)) => {},
+            r => panic!("expected the block to be too large, got {:?}", r)
+        }
+        let bad_root = sign(master, Block::new(header(1, sig::Hash::of_slice(b"nope"), master), vec![post(master, 0).into()]));
+        assert_eq!(pl.submit(&bad_root.to_blob()), Err(ValidationError::BadMerkleRoot));
+        let future = now + MAX_CLOCK_DRIFT + 1;
+        assert_eq!(pl.submit(&mk(&[&g], future, vec![], master).to_blob()), Err(ValidationError::BadTimestamp(future)));
+        assert_eq!(pl.submit(&mk(&[&g], t0 + 1, vec![post(master, t0 + 2).into()], master).to_blob()), Err(ValidationError::BadTimestamp(t0 + 2)));
+        let other_header = sign(master, Block::new(header(1, empty_root, stranger), vec![]));
+        assert_eq!(pl.submit(&other_header.to_blob()), Err(ValidationError::BadSignature(SigVerificationError::FingerprintMismatch)));
+        match pl.submit(&mk(&[&g], t0 + 1, vec![tampered.into()], master).to_blob()) {
+            Err(ValidationError::BadSignature(_)) => {},
+            r => panic!("expected a bad signature, got {:?}", r)
+        }
+        assert_eq!(pl.queued(), 0);
+
+        // Things that do, but not the second.  Alice's key isn't known yet, so her post is let
+        // through the first phase, but she doesn't have the credits for it.
+        let bad_height = sign(master, Block::new(header(5, empty_root, master), vec![]));
+        let before_parent = mk(&[&g], t0 - 1, vec![], master);
+        let unknown = mk(&[&g], t0 + 1, vec![], stranger);
+        let big = sign(alice, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![0; 1000])), t0));
+        let broke = mk(&[&b3], t0 + 4, vec![big.into()], master);
+        let missing = mk(&[&g], t0 + 5, vec![], master);
+        let orphan = mk(&[&missing], t0 + 6, vec![], master);
+
+        // The delegation comes in before its parent.
+        for b in &[&b1, &b3, &b2, &bad_height, &before_parent, &unknown, &broke, &orphan] {
+            assert_eq!(pl.submit(&b.to_blob()), Ok(addr(b)));
+        }
+        assert_eq!(pl.submit(&b3.to_blob()), Ok(addr(&b3)));
+        assert_eq!(pl.queued(), 8);
+
+        assert_eq!(pl.process(), vec![
+            (addr(&b1), Ok(())),
+            (addr(&b3), Err(ValidationError::NodeNotFound(addr(&b2)))),
+            (addr(&b2), Ok(())),
+            (addr(&bad_height), Err(ValidationError::BadHeight(5))),
+            (addr(&before_parent), Err(ValidationError::BadTimestamp(t0 - 1))),
+            (addr(&unknown), Err(ValidationError::UnknownSigner(stranger.into()))),
+            (addr(&broke), Err(ValidationError::NodeNotFound(addr(&b3)))),
+            (addr(&orphan), Err(ValidationError::NodeNotFound(addr(&missing)))),
+            (addr(&b3), Ok(())),
+            (addr(&broke), Err(ValidationError::InsufficientCredits))
+        ]);
+
+        let st = pl.state();
+        assert_eq!(st.history.iter().map(|h| h.0).collect::<Vec<_>>(), vec![addr(&g), addr(&b1), addr(&b2), addr(&b3)]);
+        assert_eq!(pl.strays.keys().collect::<Vec<_>>(), vec![&addr(&orphan)]);
+        assert_eq!(pl.tips(), &[addr(&b3)]);
+        assert!(st.who_is(alice.into(), 2).is_some());
+        assert!(st.who_is(child.into(), 3).is_some());
+
+        // Once the missing parent shows up, so does the orphan.
+        assert_eq!(pl.submit(&b1.to_blob()), Ok(addr(&b1)));
+        assert_eq!(pl.submit(&missing.to_blob()), Ok(addr(&missing)));
+        assert_eq!(pl.process(), vec![(addr(&missing), Ok(())), (addr(&orphan), Ok(()))]);
+        assert_eq!(pl.queued(), 0);
+        let mut tips = vec![addr(&b3), addr(&orphan)];
+        tips.sort();
+        assert_eq!(pl.tips(), tips.as_slice());
+
+    }
+
+    #[test]
+    fn ck_pipeline_orders() {
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let alice = Scheme::Ed25519.generate(&[2; 32]);
+        let g = NetworkParams::regtest().genesis().clone();
+        let t0 = g.timestamp();
+
+        //   g - x1 - x2
+        //    \        \
+        //     y1 ----- m
+        let x1 = mk(&[&g], t0 + 1, vec![post(master, t0).into()], master);
+        let x2 = mk(&[&x1], t0 + 2, vec![declare(alice, alice, "alice").into()], master);
+        let y1 = mk(&[&g], t0 + 1, vec![post(master, t0 + 1).into()], master);
+        let m = mk(&[&x2, &y1], t0 + 3, vec![post(alice, t0 + 3).into()], master);
+
+        let run = |blocks: &[&VBlock], each: bool| {
+            let mut pl = Pipeline::with_clock(state_with(master), NodeSource::new(MemBlobSource::new()), move || t0 + 10);
+            for b in blocks {
+                pl.submit(&b.to_blob()).unwrap();
+                if each {
+                    pl.process();
+                }
+            }
+            pl.process();
+            pl
+        };
+
+        let first = run(&[&x1, &x2, &y1, &m], true);
+        assert_eq!(first.tips(), &[Address::of_bincomp(&m)]);
+        assert!(first.state().who_is(alice.into(), 2).is_some());
+
+        for &(order, each) in &[([&m, &y1, &x2, &x1], true), ([&y1, &m, &x1, &x2], false), ([&x2, &m, &x1, &y1], true), ([&m, &x2, &x1, &y1], false)] {
+            let pl = run(&order, each);
+            let (st, want) = (pl.state(), first.state());
+            assert!(st.history.iter().map(|h| h.0).eq(want.history.iter().map(|h| h.0)));
+            assert_eq!(st.data_state.ledger, want.data_state.ledger);
+            assert_eq!(st.data_state.keys, want.data_state.keys);
+            assert_eq!(pl.tips(), first.tips());
+        }
+
+    }
+
+    #[test]
+    fn ck_pipeline_strays() {
+
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let alice = Scheme::Ed25519.generate(&[2; 32]);
+        let g = NetworkParams::regtest().genesis().clone();
+        let t0 = g.timestamp();
+        let addr = |b: &VBlock| Address::of_bincomp(b);
+
+        let now = Rc::new(Cell::new(t0 + 10));
+        let clock = now.clone();
+        let mut pl = Pipeline::with_clock(state_with(master), NodeSource::new(MemBlobSource::new()), move || clock.get());
+        pl.set_stray_limits(2, 1000);
+
+        // Three orphans, one after another, so the first one is pushed out.
+        let missing: Vec<VBlock> = (0..3).map(|i| mk(&[&g], t0 + 1 + i, vec![], master)).collect();
+        let orphans: Vec<VBlock> = missing.iter().map(|p| mk(&[p], t0 + 5, vec![], master)).collect();
+        for (i, o) in orphans.iter().enumerate() {
+            now.set(t0 + 10 + i as i64);
+            pl.submit(&o.to_blob()).unwrap();
+            assert_eq!(pl.process(), vec![(addr(o), Err(ValidationError::NodeNotFound(addr(&missing[i]))))]);
+        }
+        let mut left: Vec<Address> = pl.strays.keys().cloned().collect();
+        left.sort();
+        let mut want = vec![addr(&orphans[1]), addr(&orphans[2])];
+        want.sort();
+        assert_eq!(left, want);
+
+        // Then the second one's waited too long.
+        now.set(t0 + 1012);
+        assert_eq!(pl.process(), vec![]);
+        assert_eq!(pl.strays.keys().collect::<Vec<_>>(), vec![&addr(&orphans[2])]);
+
+        // Alice isn't known when her block comes in, so its signature can't be checked until the
+        // block declaring her does.  It's been moved from another of her blocks.
+        let decl = mk(&[&g], t0 + 1, vec![declare(alice, alice, "alice").into()], master);
+        let donor = mk(&[&decl], t0 + 3, vec![], alice);
+        let mut forged = donor.sig().to_blob();
+        forged.extend(mk(&[&decl], t0 + 2, vec![], alice).extract_owned().to_blob());
+        let forged = VBlock::from_slice_exact(forged.as_slice()).unwrap();
+
+        assert_eq!(pl.submit(&forged.to_blob()), Ok(addr(&forged)));
+        assert_eq!(pl.process(), vec![(addr(&forged), Err(ValidationError::NodeNotFound(addr(&decl))))]);
+        pl.submit(&decl.to_blob()).unwrap();
+        assert_eq!(pl.process(), vec![
+            (addr(&decl), Ok(())),
+            (addr(&forged), Err(ValidationError::BadSignature(SigVerificationError::KeyMismatch)))
+        ]);
+        assert_eq!(pl.strays.keys().collect::<Vec<_>>(), vec![&addr(&orphans[2])]);
+        assert_eq!(pl.queued(), 0);
+
+    }
+
+    #[test]
+    fn ck_credit_ledger() {
+
+        use dag::params::CreditPolicy;
+        use order::HeaviestSubDag;
+        use db::testutil::build_with;
+
+        let rule = HeaviestSubDag::new();
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let alice = Scheme::Ed25519.generate(&[2; 32]);
+        let policy = CreditPolicy { initial: 100, regen: 2, regen_cap: 150, block_cost: 1, stamp_bits: 0, ..CreditPolicy::DEFAULT };
+        let params = NetworkParams::regtest().with_credits(policy);
+
+        let art = |n: usize| sign(alice, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![0; n])), 0));
+        let cost = calc_segment_cost(&params, art(20).extract_owned());
+
+        // Blocks are all signed by the master key.
+        let segs = |i| -> Vec<SignedSegment> {
+            match i {
+                1 => vec![declare(alice, alice, "alice").into()],
+                3 => vec![art(20).into()],
+                _ => vec![]
+            }
+        };
+        // A long chain, so there's room to regenerate up to the cap at the end.
+        let links: Vec<[usize; 1]> = (0..60).map(|i| [i]).collect();
+        let shape: Vec<&[usize]> = Some(&[][..]).into_iter().chain(links.iter().map(|l| &l[..])).collect();
+        let (src, blocks, a) = build_with(Some(params.genesis().clone()), shape.as_slice(), segs);
+
+        let mut vs = ValdiationState::new(params.clone());
+        vs.data_state.add_identity(master.into(), 5000, 0);
+        vs.apply_ordered(&src, &rule, &[a[5]]).unwrap();
+
+        // Master pays for declaring alice along with the block, and she regenerates from what she
+        // starts with.
+        let alice_fp: Fingerprint = alice.into();
+        assert_eq!(vs.balance_at(alice_fp, a[0]), None);
+        assert_eq!(vs.balance_at(alice_fp, a[1]), Some(100));
+        assert_eq!(vs.balance_at(alice_fp, a[2]), Some(102));
+        assert_eq!(vs.balance_at(alice_fp, a[3]), Some(104 - cost));
+        assert_eq!(vs.balance_at(alice_fp, a[5]), Some(108 - cost));
+        assert_eq!(vs.balance(alice_fp), Some(108 - cost));
+
+        // Master's above the cap, so it only goes down.
+        assert_eq!(vs.balance_at(master.into(), a[0]), Some(5000));
+        assert_eq!(vs.balance_at(master.into(), a[1]), Some(4998));
+        assert_eq!(vs.balance_at(master.into(), a[3]), Some(4996));
+        assert_eq!(vs.balance(master.into()), Some(4994));
+
+        // Undoing blocks puts the credits back, and forgets what the balances were.
+        assert_eq!(vs.revert_block(), Ok(a[5]));
+        assert_eq!(vs.revert_block(), Ok(a[4]));
+        assert_eq!(vs.revert_block(), Ok(a[3]));
+        assert_eq!(vs.balance(alice_fp), Some(102));
+        assert_eq!(vs.balance(master.into()), Some(4997));
+        assert_eq!(vs.balance_at(alice_fp, a[3]), None);
+
+        // Too big to afford, and none of it happens.
+        let head = |b: &VBlock| b.extract_owned().get_header().extract_owned();
+        let pricey = dag::builder::BlockBuilder::with_clock(1, || head(&blocks[2]).timestamp() + 1)
+            .network(REGTEST_ID)
+            .parent_block(&blocks[2])
+            .segment(art(200))
+            .build(master)
+            .unwrap();
+        assert_eq!(vs.apply_block(Address::of_bincomp(&pricey), pricey), Err(ValidationError::InsufficientCredits));
+        assert_eq!(vs.balance(master.into()), Some(4997));
+
+        // Regeneration stops at the cap.
+        vs.apply_ordered(&src, &rule, &[a[60]]).unwrap();
+        assert_eq!(vs.balance_at(alice_fp, a[20]), Some(138 - cost));
+        assert_eq!(vs.balance(alice_fp), Some(150));
+
+    }
+
+    #[test]
+    fn ck_founders() {
+
+        // What the built in genesis blocks are signed with, which regtest's declares.
+        let founder = Scheme::Ed25519.generate(b"jiyunet/genesis/v1");
+        let alice = Scheme::Ed25519.generate(&[2; 32]);
+        let params = NetworkParams::regtest();
+        let (costs, credits) = (params.costs(), params.credits());
+        let g = params.genesis().clone();
+        let t0 = g.timestamp();
+        let addr = |b: &VBlock| Address::of_bincomp(b);
+
+        let mut vs = ValdiationState::new(params.clone());
+        assert_eq!(vs.who_is(founder.into(), 0).map(|r| r.ident), Some(founder.into()));
+        assert_eq!(vs.balance(founder.into()), Some(credits.founding));
+
+        // The founder brings alice on and pays for it.
+        let b1 = mk(&[&g], t0 + 1, vec![declare(alice, alice, "alice").into()], founder);
+        assert_eq!(vs.apply_block(addr(&b1), b1.clone()), Ok(()));
+        assert_eq!(vs.balance(founder.into()), Some(credits.founding - credits.block_cost - costs.ident));
+        let mut bal = credits.initial;
+        assert_eq!(vs.balance(alice.into()), Some(bal));
+
+        // Then she can make blocks of her own.
+        let art = sign(alice, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![0; 10])), t0 + 2));
+        let art_cost = calc_segment_cost(&params, art.extract_owned());
+        let b2 = mk(&[&b1], t0 + 2, vec![art.into()], alice);
+        assert_eq!(vs.apply_block(addr(&b2), b2.clone()), Ok(()));
+        bal = bal + credits.regen - credits.block_cost - art_cost;
+        assert_eq!(vs.balance(alice.into()), Some(bal));
+
+        // A block from the side isn't any higher, so nothing regenerates with it.
+        let side = mk(&[&g], t0 + 3, vec![], founder);
+        assert_eq!(vs.apply_block(addr(&side), side.clone()), Ok(()));
+        assert_eq!(vs.balance_at(alice.into(), addr(&side)), Some(bal));
+
+        let b3 = mk(&[&b2, &side], t0 + 4, vec![], alice);
+        assert_eq!(vs.apply_block(addr(&b3), b3.clone()), Ok(()));
+        assert_eq!(vs.balance(alice.into()), Some(bal + credits.regen - credits.block_cost));
+
+    }
+
+    #[test]
+    fn ck_mainnet_from_genesis() {
+
+        use dag::params::MAINNET_ID;
+
+        let seed = Scheme::Ed25519.generate(b"jiyunet/genesis/v1");
+        let alice = Scheme::Ed25519.generate(&[2; 32]);
+        let params = NetworkParams::mainnet();
+        let g = params.genesis().clone();
+        let t0 = g.timestamp();
+
+        let mk = |ps: &[&VBlock], ts: i64, segs: Vec<SignedSegment>, kp: Keypair| {
+            ps.iter()
+                .fold(BlockBuilder::with_clock(1, move || ts).network(MAINNET_ID), |bb, p| bb.parent_block(p))
+                .segments(segs)
+                .build(kp)
+                .unwrap()
+        };
+        let addr = |b: &VBlock| Address::of_bincomp(b);
+
+        // Everyone knows the key mainnet's genesis block is signed with, so it isn't anyone.
+        let mut vs = ValdiationState::new(params.clone());
+        assert_eq!(vs.who_is(seed.into(), 0), None);
+        let b = mk(&[&g], t0 + 1, vec![], seed);
+        assert_eq!(vs.apply_block(addr(&b), b), Err(ValidationError::UnknownSigner(seed.into())));
+
+        // So the first identity has to do the work to declare itself and sign its own block.
+        let d = IdentityDecl::new(alice.into(), "alice".into(), None, "".into(), Capabilities::POSTER);
+        let decl = Signed::new_on(alice, MAINNET_ID, Segment::new(SegmentContent::IdentDecl(d), t0 + 1));
+        let need = params.credits().stamp_bits_for(params.costs().ident + params.credits().block_cost);
+        // Found with `PowStamp::mint`, which takes a while at mainnet's difficulty.
+        let stamp = PowStamp::new(7029469);
+        assert!(stamp.work(&decl) >= need);
+        let b1 = mk(&[&g], t0 + 1, vec![SignedSegment::Stamped(decl, stamp)], alice);
+        assert_eq!(vs.apply_block(addr(&b1), b1.clone()), Ok(()));
+        assert_eq!(vs.balance(alice.into()), Some(params.credits().initial));
+
+    }
+
+    #[test]
+    fn ck_stamped_segments() {
+
+        use dag::params::{CreditPolicy, STAMP_BASE_COST};
+
+        let master = Scheme::Ed25519.generate(&[1; 32]);
+        let bob = Scheme::Ed25519.generate(&[3; 32]);
+        let carol = Scheme::Ed25519.generate(&[4; 32]);
+        let policy = CreditPolicy { initial: 0, stamp_bits: 6, block_cost: STAMP_BASE_COST, ..NetworkParams::regtest().credits() };
+        let params = NetworkParams::regtest().with_credits(policy);
+        let t0 = params.genesis().timestamp();
+
+        let mut vs = ValdiationState::new(params.clone());
+        vs.data_state.add_identity(master.into(), 5000, 0);
+
+        let mk_by = |kp: Keypair, parent: &VBlock, segs: Vec<SignedSegment>| {
+            let ts = parent.timestamp() + 1;
+            BlockBuilder::with_clock(1, move || ts).network(REGTEST_ID).parent_block(parent).segments(segs).build(kp).unwrap()
+        };
+        let mk = |parent: &VBlock, segs: Vec<SignedSegment>| mk_by(master, parent, segs);
+        let stamp = |seg: Signed<Segment>, bits| SignedSegment::mint_stamped(seg, bits).unwrap();
+        // A stamp with exactly that much work, so it isn't any more by luck.
+        let exactly = |seg: Signed<Segment>, bits| {
+            let st = (0..).map(|n| PowStamp::new(n)).find(|st| st.work(&seg) == bits).unwrap();
+            SignedSegment::Stamped(seg, st)
+        };
+        let apply = |vs: &mut ValdiationState, b: &VBlock| {
+            check_block(&params, vs, b, t0)?;
+            vs.apply_block(Address::of_bincomp(b), b.clone())
+        };
+
+        // Bob has nothing, so declaring him comes out of whoever signs the block, unless it's
+        // paid for with work.
+        let decl = declare(bob, bob, "bob");
+        let mut paid = vs.clone();
+        apply(&mut paid, &mk(params.genesis(), vec![decl.clone().into()])).unwrap();
+        assert_eq!(paid.balance(master.into()), Some(5000 + policy.regen - policy.block_cost - params.costs().ident));
+        let b1 = mk(params.genesis(), vec![stamp(decl, 6)]);
+        apply(&mut vs, &b1).unwrap();
+        assert_eq!(vs.balance(bob.into()), Some(0));
+        assert_eq!(vs.balance(master.into()), Some(5000 + policy.regen - policy.block_cost));
+
+        // Same for posting.
+        let b2 = mk(&b1, vec![post(bob, 0).into()]);
+        assert_eq!(apply(&mut vs, &b2), Err(ValidationError::InsufficientCredits));
+        let b2 = mk(&b1, vec![stamp(post(bob, 0), 6)]);
+        apply(&mut vs, &b2).unwrap();
+        assert_eq!(vs.balance(bob.into()), Some(policy.regen));
+
+        // Not enough work doesn't get through the first phase.
+        let seg = post(bob, 1);
+        let weak = (0..).map(|n| PowStamp::new(n)).find(|st| st.work(&seg) < 6).unwrap();
+        let b3 = mk(&b2, vec![SignedSegment::Stamped(seg, weak)]);
+        assert_eq!(apply(&mut vs, &b3), Err(ValidationError::InsufficientWork));
+
+        // Or anything at all, if the network doesn't take stamps.
+        let off = params.clone().with_credits(CreditPolicy { stamp_bits: 0, ..policy });
+        assert_eq!(check_block(&off, &vs, &b2, t0), Err(ValidationError::InsufficientWork));
+
+        // Bigger segments take more work.
+        let big = sign(bob, Segment::new(SegmentContent::Artifact(ArtifactData::new(0, vec![0; 1000])), 0));
+        let need = policy.stamp_bits_for(calc_segment_cost(&params, big.extract_owned()));
+        assert!(need > 6);
+        let b3 = mk(&b2, vec![exactly(big.clone(), need - 1)]);
+        assert_eq!(apply(&mut vs, &b3), Err(ValidationError::InsufficientWork));
+        let b3 = mk(&b2, vec![stamp(big, need as u8)]);
+        apply(&mut vs, &b3).unwrap();
+
+        // Carol doesn't know anyone, so she signs the block with her declaration in it herself,
+        // and the work has to pay for the block too.
+        let decl = declare(carol, carol, "carol");
+        let need = policy.stamp_bits_for(calc_segment_cost(&params, decl.extract_owned()) + policy.block_cost);
+        assert!(need > 6);
+        let b4 = mk_by(carol, &b3, vec![exactly(decl.clone(), need - 1)]);
+        assert_eq!(apply(&mut vs, &b4), Err(ValidationError::UnknownSigner(carol.into())));
+        let b4 = mk_by(carol, &b3, vec![stamp(decl, need as u8)]);
+        apply(&mut vs, &b4).unwrap();
+        assert_eq!(vs.balance(carol.into()), Some(0));
+        assert!(vs.who_is(carol.into(), 4).is_some());
+
+        // It has to be her own declaration.
+        let b5 = mk_by(carol.derive_child(0), &b4, vec![stamp(declare(carol, carol, "carol"), need as u8)]);
+        assert_eq!(apply(&mut vs, &b5), Err(ValidationError::UnknownSigner(carol.derive_child(0).into())));
+
+    }
+
+}
@@ -4,12 +4,17 @@ extern crate jiyunet_core as core;
 extern crate jiyunet_dag as dag;
 extern crate jiyunet_db as db;
 
+#[cfg(test)]
+#[macro_use] extern crate quickcheck;
+
 use dag::block;
 
 pub mod ck;
 pub mod io;
+pub mod order;
+pub mod registry;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ValidationError {
 
     // Problem decoding data.
@@ -21,7 +26,55 @@ pub enum ValidationError {
     // If something is too big to be allowed.
     ComponentTooLarge(core::sig::Hash),
 
+    // Block's merkle root doesn't match its segments.
+    BadMerkleRoot,
+
+    // Block from too far in the future, or before its parents, or a segment from after its block.
+    BadTimestamp(i64),
+
+    // Block height isn't one more than its highest parent.
+    BadHeight(u64),
+
     // Identitiy doesn't have credits for some action.
-    InsufficientCredits
+    InsufficientCredits,
+
+    // Proof of work stamp that isn't enough, or stamps aren't accepted.
+    InsufficientWork,
+
+    // Signed by a key that isn't an identity or delegated to by one.
+    UnknownSigner(core::sig::Fingerprint),
+
+    // Signature doesn't check out.
+    BadSignature(core::sig::SigVerificationError),
+
+    // Delegation or rotation to a key that's already in use, or has been.
+    KeyInUse(core::sig::Fingerprint),
+
+    // Signed by a delegated key after its delegation ran out.
+    DelegationExpired(core::sig::Fingerprint),
+
+    // Signed by a key after the block that revoked or rotated it away.
+    KeyRevoked(core::sig::Fingerprint),
+
+    // Tried to manage an identity with a key that isn't its current main key.
+    NotMainKey(core::sig::Fingerprint),
+
+    // Delegation, rotation, or revocation that isn't newer than the identity's last one, like a
+    // replay of an old one.
+    StaleKeyChange(u64),
+
+    // Governance for an identity that has a policy and isn't multi-signed under it, or that's
+    // multi-signed under some other policy.
+    WrongPolicy(core::sig::Fingerprint),
+
+    // Identity declaration with a bad name or bio.
+    BadIdentityDecl,
+
+    // Multi-signed segment that isn't for governance.  These don't decode, but can be made.
+    MultiSignedPost,
+
+    // A block we've already applied isn't where the fork choice puts it anymore, and it's too far
+    // back to undo.
+    OrderConflict(core::Address)
 
 }
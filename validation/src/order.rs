@@ -0,0 +1,386 @@
+//! Fork choice, which turns the DAG into one list of blocks that everyone applies in the same
+//! order.  Without it two nodes could see the same blocks and disagree on which credits were spent
+//! first.
+//!
+//! The default rule is a heaviest sub-DAG rule, like GHOST.  Every block has a weight, which is
+//! how many blocks are in its past (including itself).  Starting from the tips, we follow the
+//! heaviest parent at each step back to genesis to get the selected chain.  Then, going back up
+//! the chain, each block brings in everything in its past that hasn't been ordered yet, lightest
+//! first, and finally the tips do the same.  Ties are always broken by lowest `Address`.
+
+use std::cell::RefCell;
+use std::collections::{BinaryHeap, HashMap};
+
+use core::Address;
+use db::BlobSource;
+use db::traverse::{TraverseError, Traverser};
+
+/// A rule for putting all of the blocks behind a set of tips in order.  Parents always have to
+/// come before their children, and the order can only depend on the shape of the DAG.
+pub trait OrderingRule {
+    fn order<S: BlobSource>(&self, t: &Traverser<S>, tips: &[Address]) -> Result<Vec<Address>, TraverseError>;
+}
+
+/// Just orders by height, then by address.  Easy to reason about, but a block that's way behind
+/// gets ordered the same as one that's caught up, so it's mostly here for testing.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct ByHeight;
+
+impl OrderingRule for ByHeight {
+    fn order<S: BlobSource>(&self, t: &Traverser<S>, tips: &[Address]) -> Result<Vec<Address>, TraverseError> {
+        t.topological_order(tips, None)
+    }
+}
+
+/// Heaviest sub-DAG ordering, as described up top.  This is what validation uses.
+///
+/// Where each block goes in the order behind it never changes, so that's cached, and keeping the
+/// same rule around means each new block only costs a walk back to where its past meets the past
+/// of its heaviest parent.
+#[derive(Debug, Default)]
+pub struct HeaviestSubDag {
+    placed: RefCell<HashMap<Address, Placement>>
+}
+
+/// What we know about a block once it's been placed.
+#[derive(Clone, Debug)]
+struct Placement {
+    height: u64, // Worked out from the parents, like `Traverser::height`.
+    parents: Vec<Address>,
+    weight: usize,
+    selected: Option<Address>, // Heaviest parent.
+    group: Vec<Address> // What it brings in after its selected parent's order, lightest first.
+}
+
+impl HeaviestSubDag {
+
+    pub fn new() -> HeaviestSubDag {
+        HeaviestSubDag::default()
+    }
+
+    /// Number of blocks that have been placed so far.
+    pub fn cached(&self) -> usize {
+        self.placed.borrow().len()
+    }
+
+    /// Places the block, and everything behind it that hasn't been yet.  Parents have to be
+    /// placed first, so keep going down until we get to ones that are.
+    fn place<S: BlobSource>(&self, t: &Traverser<S>, b: Address) -> Result<(), TraverseError> {
+
+        let mut stack = vec![b];
+        while let Some(&x) = stack.last() {
+
+            if self.placed.borrow().contains_key(&x) {
+                stack.pop();
+                continue;
+            }
+
+            let parents = t.parents(x)?;
+            let missing: Vec<Address> = parents.iter().filter(|p| !self.placed.borrow().contains_key(p)).cloned().collect();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+
+            let p = self.placement(x, parents);
+            self.placed.borrow_mut().insert(x, p);
+            stack.pop();
+
+        }
+
+        Ok(())
+
+    }
+
+    /// Works out where the block goes, once all of its parents are placed.  Its past is itself,
+    /// its heaviest parent's past, and whatever the other parents bring in, so that's its weight.
+    fn placement(&self, b: Address, parents: Vec<Address>) -> Placement {
+
+        let selected = self.heaviest(parents.as_slice());
+        let (height, weight, mut group) = match selected {
+            Some(sp) => {
+                let placed = self.placed.borrow();
+                let height = parents.iter().map(|p| placed[p].height + 1).max().expect("selected parent with no parents");
+                let merged = self.merge_set(sp, parents.as_slice());
+                (height, placed[&sp].weight + merged.len() + 1, merged)
+            },
+            None => (0, 1, Vec::new())
+        };
+
+        // Heavier than anything behind it, so it goes last.
+        group.push(b);
+        Placement {
+            height: height,
+            parents: parents,
+            weight: weight,
+            selected: selected,
+            group: group
+        }
+
+    }
+
+    /// Picks the heaviest of the placed blocks, or the lowest address if they're tied.
+    fn heaviest(&self, bs: &[Address]) -> Option<Address> {
+        let placed = self.placed.borrow();
+        let mut best: Option<(usize, Address)> = None;
+        for b in bs {
+            let w = placed[b].weight;
+            best = match best {
+                Some((bw, ba)) if bw > w || (bw == w && ba < *b) => Some((bw, ba)),
+                _ => Some((w, *b))
+            };
+        }
+        best.map(|(_, b)| b)
+    }
+
+    /// Finds what's in the past of the blocks but not in the past of `base`, lightest first.
+    ///
+    /// Goes down from the top by height, marking what's behind `base` as it goes.  Parents are
+    /// always lower than their children, so by the time a block comes up everything that could
+    /// reach it from `base` already has, and we can stop once only `base`'s past is left.
+    fn merge_set(&self, base: Address, bs: &[Address]) -> Vec<Address> {
+
+        let placed = self.placed.borrow();
+        let mut behind: HashMap<Address, bool> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut left = 0;
+
+        behind.insert(base, true);
+        queue.push((placed[&base].height, base));
+        for b in bs {
+            if !behind.contains_key(b) {
+                behind.insert(*b, false);
+                queue.push((placed[b].height, *b));
+                left += 1;
+            }
+        }
+
+        let mut merged = Vec::new();
+        while left > 0 {
+
+            let (_, x) = queue.pop().expect("ran out of blocks before reaching the base");
+            let is_behind = behind[&x];
+            if !is_behind {
+                merged.push((placed[&x].weight, x));
+                left -= 1;
+            }
+
+            for p in &placed[&x].parents {
+                match behind.get(p).cloned() {
+                    None => {
+                        behind.insert(*p, is_behind);
+                        queue.push((placed[p].height, *p));
+                        if !is_behind {
+                            left += 1;
+                        }
+                    },
+                    Some(false) if is_behind => {
+                        behind.insert(*p, true);
+                        left -= 1;
+                    },
+                    _ => {}
+                }
+            }
+
+        }
+
+        merged.sort();
+        merged.into_iter().map(|(_, b)| b).collect()
+
+    }
+
+}
+
+impl OrderingRule for HeaviestSubDag {
+
+    fn order<S: BlobSource>(&self, t: &Traverser<S>, tips: &[Address]) -> Result<Vec<Address>, TraverseError> {
+
+        for tip in tips {
+            self.place(t, *tip)?;
+        }
+
+        let top = match self.heaviest(tips) {
+            Some(b) => b,
+            None => return Ok(Vec::new())
+        };
+
+        // Walk back along the heaviest parents, then each one brings in its group going up.
+        let placed = self.placed.borrow();
+        let mut chain = Vec::new();
+        let mut next = Some(top);
+        while let Some(b) = next {
+            chain.push(b);
+            next = placed[&b].selected;
+        }
+
+        let mut ordered = Vec::new();
+        for b in chain.into_iter().rev() {
+            ordered.extend_from_slice(placed[&b].group.as_slice());
+        }
+
+        // Then whatever the other tips have that the heaviest one doesn't.
+        ordered.extend(self.merge_set(top, tips));
+        Ok(ordered)
+
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashSet;
+
+    use db::testutil::build;
+
+    use super::*;
+
+    fn is_topological<S: BlobSource>(t: &Traverser<S>, order: &[Address]) -> bool {
+        order.iter().enumerate().all(|(i, b)| t.parents(*b).unwrap().iter().all(|p| order[..i].contains(p)))
+    }
+
+    /// The rule worked out the slow way, straight from the description up top.
+    fn naive_order<S: BlobSource>(t: &Traverser<S>, tips: &[Address]) -> Vec<Address> {
+
+        let weight = |b: &Address| t.past(*b).unwrap().len();
+        let heaviest = |bs: &[Address]| bs.iter().map(|b| (weight(b), ::std::cmp::Reverse(*b))).max().map(|(_, r)| r.0);
+        let merge = |bs: HashSet<Address>, ordered: &mut Vec<Address>| {
+            let mut new: Vec<_> = bs.into_iter().filter(|b| !ordered.contains(b)).map(|b| (weight(&b), b)).collect();
+            new.sort();
+            ordered.extend(new.into_iter().map(|(_, b)| b));
+        };
+
+        let mut chain = Vec::new();
+        let mut next = heaviest(tips);
+        while let Some(b) = next {
+            chain.push(b);
+            next = heaviest(t.parents(b).unwrap().as_slice());
+        }
+
+        let mut ordered = Vec::new();
+        for b in chain.into_iter().rev() {
+            merge(t.past(b).unwrap(), &mut ordered);
+        }
+        merge(tips.iter().flat_map(|tip| t.past(*tip).unwrap()).collect(), &mut ordered);
+        ordered
+
+    }
+
+    //   0 - 1 - 2 - 3 - 4
+    //    \
+    //     5 - 6
+    //          \
+    //           7 (merges 4 and 6)
+    const FORKED: &[&[usize]] = &[&[], &[0], &[1], &[2], &[3], &[0], &[5], &[4, 6]];
+
+    #[test]
+    fn ck_heaviest_chain_goes_first() {
+
+        let (src, _, a) = build(None, FORKED);
+        let t = Traverser::new(&src);
+
+        // With both forks as tips, the longer one wins and the short one comes after it.
+        let order = HeaviestSubDag::new().order(&t, &[a[6], a[4]]).unwrap();
+        assert_eq!(order, vec![a[0], a[1], a[2], a[3], a[4], a[5], a[6]]);
+
+        // Same thing once they're merged.
+        let order = HeaviestSubDag::new().order(&t, &[a[7]]).unwrap();
+        assert_eq!(order, vec![a[0], a[1], a[2], a[3], a[4], a[5], a[6], a[7]]);
+        assert!(is_topological(&t, order.as_slice()));
+
+        // Ordering by height would interleave them instead.
+        let order = ByHeight.order(&t, &[a[7]]).unwrap();
+        assert!(is_topological(&t, order.as_slice()));
+        assert!(order != HeaviestSubDag::new().order(&t, &[a[7]]).unwrap());
+
+    }
+
+    #[test]
+    fn ck_ties_break_by_address() {
+
+        // Two forks of the same weight.
+        let (src, _, a) = build(None, &[&[], &[0], &[0]]);
+        let t = Traverser::new(&src);
+
+        let (lo, hi) = match a[1] < a[2] {
+            true => (a[1], a[2]),
+            false => (a[2], a[1])
+        };
+
+        assert_eq!(HeaviestSubDag::new().order(&t, &[a[1], a[2]]).unwrap(), vec![a[0], lo, hi]);
+        assert_eq!(HeaviestSubDag::new().order(&t, &[a[2], a[1]]).unwrap(), vec![a[0], lo, hi]);
+        assert_eq!(HeaviestSubDag::new().order(&t, &[]).unwrap(), vec![]);
+
+    }
+
+    #[test]
+    fn ck_placements_are_cached() {
+
+        let (src, blocks, a) = build(None, FORKED);
+        let rule = HeaviestSubDag::new();
+        let order = rule.order(&Traverser::new(&src), &[a[4], a[6]]).unwrap();
+        assert_eq!(rule.cached(), 7);
+
+        // Merging the forks only needs the new block, everything else is already placed.
+        let t = Traverser::new(&src);
+        let mut more = order.clone();
+        more.push(a[7]);
+        assert_eq!(rule.order(&t, &[a[7]]).unwrap(), more);
+        assert_eq!(t.cached(), 1);
+        assert_eq!(rule.cached(), 8);
+
+        // Same for a block on top, and it agrees with starting over.
+        let kp = ::core::sig::Scheme::Ed25519.generate(&[1; 32]);
+        let top = ::dag::builder::BlockBuilder::with_clock(1, || 9).parent_block(&blocks[7]).build(kp).unwrap();
+        let ta = Address::of_bincomp(&top);
+        src.put(top).unwrap();
+        let t = Traverser::new(&src);
+        more.push(ta);
+        assert_eq!(rule.order(&t, &[ta]).unwrap(), more);
+        assert_eq!(t.cached(), 1);
+        assert_eq!(HeaviestSubDag::new().order(&Traverser::new(&src), &[ta]).unwrap(), more);
+
+    }
+
+    /// Orders each bigger piece of the DAG in turn with the same rule, as if the blocks were
+    /// coming in one at a time, and checks it against the slow way.
+    fn incremental_agrees<S: BlobSource>(t: &Traverser<S>, blocks: &[Address]) -> bool {
+        let rule = HeaviestSubDag::new();
+        (1..blocks.len() + 1).all(|n| {
+            let tips = t.tips(&blocks[..n]).unwrap();
+            rule.order(t, tips.as_slice()).unwrap() == naive_order(t, tips.as_slice())
+        })
+    }
+
+    quickcheck! {
+
+        fn prop_order_is_topological(seed: Vec<(u8, u8)>) -> bool {
+
+            // Every block after genesis picks up to two earlier blocks as parents.
+            let mut shape: Vec<Vec<usize>> = vec![vec![]];
+            for (i, &(x, y)) in seed.iter().take(24).enumerate() {
+                let n = i + 1;
+                let mut ps = vec![x as usize % n, y as usize % n];
+                ps.sort();
+                ps.dedup();
+                shape.push(ps);
+            }
+
+            let shape_refs: Vec<&[usize]> = shape.iter().map(|v| v.as_slice()).collect();
+            let (src, _, a) = build(None, shape_refs.as_slice());
+            let t = Traverser::new(&src);
+            let tips = t.tips(a.as_slice()).unwrap();
+            let order = HeaviestSubDag::new().order(&t, tips.as_slice()).unwrap();
+
+            let mut rev = tips.clone();
+            rev.reverse();
+            order.len() == a.len() && is_topological(&t, order.as_slice())
+                && HeaviestSubDag::new().order(&t, rev.as_slice()).unwrap() == order
+                && naive_order(&t, tips.as_slice()) == order
+                && incremental_agrees(&t, a.as_slice())
+
+        }
+
+    }
+
+}
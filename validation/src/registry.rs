@@ -0,0 +1,29 @@
+//! Looking up who's behind a fingerprint.
+
+use core::sig::Fingerprint;
+
+use dag::identity::IdentityDecl;
+
+/// What's known about whoever's behind some key, as of some block.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct IdentityRecord {
+
+    /// Fingerprint the identity is known by, from the key it was declared with.
+    pub ident: Fingerprint,
+
+    /// Its latest profile, if it's ever declared one.
+    pub profile: Option<IdentityDecl>,
+
+    /// If the key is one that was delegated to, rather than one of the identity's own.
+    pub delegated: bool,
+
+    /// If the key had been revoked or rotated away.
+    pub revoked: bool
+
+}
+
+/// Something that can answer "who is fingerprint X" as of a block height.  The fingerprint can be
+/// any key an identity has used, not just the one it's known by.
+pub trait IdentityRegistry {
+    fn who_is(&self, fp: Fingerprint, height: u64) -> Option<IdentityRecord>;
+}